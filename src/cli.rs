@@ -1,7 +1,12 @@
-use clap::{App, Arg};
+use crate::error::{Error, Result, EXIT_USAGE};
+use crate::{
+    crypto, dnstree, enr, fuzz, handshake, packet, request_enr, search, server, simulation,
+};
+use clap::{App, Arg, ArgGroup, ArgMatches};
+use std::str::FromStr;
 
-pub fn start_cli<'a>() -> clap::ArgMatches<'a> {
-    App::new("discv5-cli")
+pub fn start_cli<'a>() -> ArgMatches<'a> {
+    let app = App::new("discv5-cli")
         .version("0.2.4")
         .author("Sigma Prime <contact@sigmaprime.io>")
         .about(
@@ -21,7 +26,108 @@ pub fn start_cli<'a>() -> clap::ArgMatches<'a> {
         .subcommand(server_cli())
         .subcommand(packet_cli())
         .subcommand(request_enr())
-        .get_matches()
+        .subcommand(enr_cli())
+        .subcommand(search_cli())
+        .subcommand(simulate_cli())
+        .subcommand(dnstree_cli())
+        .subcommand(fuzz_cli())
+        .subcommand(handshake_cli())
+        .subcommand(crypto_cli());
+
+    match app.get_matches_safe() {
+        Ok(matches) => matches,
+        // help and version requests are printed to stdout and exit successfully
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            eprintln!("{}", e.message);
+            std::process::exit(EXIT_USAGE);
+        }
+    }
+}
+
+/// Runs the subcommand given on the command line.
+pub async fn run(cli_matches: &ArgMatches<'_>) -> Result<()> {
+    if let Some(server_matches) = cli_matches.subcommand_matches("server") {
+        server::run(server::ServerConfig::from_matches(server_matches)?).await
+    } else if let Some(enr_matches) = cli_matches.subcommand_matches("request-enr") {
+        request_enr::run(enr_matches).await
+    } else if let Some(packet_matches) = cli_matches.subcommand_matches("packet") {
+        if let Some(decode_matches) = packet_matches.subcommand_matches("decode") {
+            packet::decode(decode_matches)
+        } else if let Some(vectors_matches) = packet_matches.subcommand_matches("vectors") {
+            if vectors_matches.subcommand_matches("generate").is_some() {
+                packet::vectors::generate()
+            } else if vectors_matches.subcommand_matches("verify").is_some() {
+                packet::vectors::verify()
+            } else {
+                Err(Error::Usage(
+                    "A vectors subcommand must be supplied. See --help for options".into(),
+                ))
+            }
+        } else {
+            // Currently no encode sub command
+            Err(Error::Usage(
+                "A packet subcommand must be supplied. See --help for options".into(),
+            ))
+        }
+    } else if let Some(enr_matches) = cli_matches.subcommand_matches("enr") {
+        if let Some(from_enode_matches) = enr_matches.subcommand_matches("from-enode") {
+            enr::from_enode(from_enode_matches)
+        } else if let Some(to_enode_matches) = enr_matches.subcommand_matches("to-enode") {
+            enr::to_enode(to_enode_matches)
+        } else if let Some(diff_matches) = enr_matches.subcommand_matches("diff") {
+            enr::diff(diff_matches)
+        } else if let Some(update_matches) = enr_matches.subcommand_matches("update") {
+            enr::update(update_matches)
+        } else if let Some(lint_matches) = enr_matches.subcommand_matches("lint") {
+            enr::lint(lint_matches)
+        } else {
+            Err(Error::Usage(
+                "An enr subcommand must be supplied. See --help for options".into(),
+            ))
+        }
+    } else if let Some(search_matches) = cli_matches.subcommand_matches("search") {
+        search::run(search_matches).await
+    } else if let Some(simulate_matches) = cli_matches.subcommand_matches("simulate") {
+        simulation::run(simulate_matches)
+    } else if let Some(dnstree_matches) = cli_matches.subcommand_matches("dnstree") {
+        if let Some(build_matches) = dnstree_matches.subcommand_matches("build") {
+            dnstree::build(build_matches)
+        } else if let Some(verify_matches) = dnstree_matches.subcommand_matches("verify") {
+            dnstree::verify(verify_matches)
+        } else {
+            Err(Error::Usage(
+                "A dnstree subcommand must be supplied. See --help for options".into(),
+            ))
+        }
+    } else if let Some(fuzz_matches) = cli_matches.subcommand_matches("fuzz") {
+        fuzz::run(fuzz_matches).await
+    } else if let Some(handshake_matches) = cli_matches.subcommand_matches("handshake") {
+        if let Some(simulate_matches) = handshake_matches.subcommand_matches("simulate") {
+            handshake::simulate(simulate_matches)
+        } else {
+            Err(Error::Usage(
+                "A handshake subcommand must be supplied. See --help for options".into(),
+            ))
+        }
+    } else if let Some(crypto_matches) = cli_matches.subcommand_matches("crypto") {
+        if let Some(derive_matches) = crypto_matches.subcommand_matches("derive-keys") {
+            crypto::derive_keys_cmd(derive_matches)
+        } else if let Some(sign_matches) = crypto_matches.subcommand_matches("id-sign") {
+            crypto::id_sign_cmd(sign_matches)
+        } else if let Some(verify_matches) = crypto_matches.subcommand_matches("id-verify") {
+            crypto::id_verify_cmd(verify_matches)
+        } else {
+            Err(Error::Usage(
+                "A crypto subcommand must be supplied. See --help for options".into(),
+            ))
+        }
+    } else {
+        // No subcommand supplied
+        Err(Error::Usage(
+            "A subcommand must be supplied. See --help for options".into(),
+        ))
+    }
 }
 
 fn server_cli<'a, 'b>() -> App<'a, 'b> {
@@ -75,6 +181,13 @@ fn server_cli<'a, 'b>() -> App<'a, 'b> {
                 .help("Specifies the Eth2 field as ssz encoded hex bytes.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("fork-digest")
+                .value_name("HEX_BYTES")
+                .long("fork-digest")
+                .help("Only allows nodes whose eth2 ENR field carries this fork digest into the routing table and query results.")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("enr_default")
                 .short("w")
@@ -99,7 +212,7 @@ fn server_cli<'a, 'b>() -> App<'a, 'b> {
                 .short("e")
                 .value_name("BASE64-ENR")
                 .allow_hyphen_values(true)
-                .help("A base64 ENR that this node will initially connect to.")
+                .help("A base64 ENR or enode URL that this node will initially connect to. The ENR of an enode is requested over discv5, so discv4-only nodes time out.")
                 .takes_value(true),
         )
         .arg(
@@ -108,7 +221,7 @@ fn server_cli<'a, 'b>() -> App<'a, 'b> {
                 .short("n")
                 .value_name("INT")
                 .default_value("2")
-                .help("The minimum number of peers required to update the IP address. Cannot be less than 2.")
+                .help("The minimum number of peers required to update the IP address. Cannot be less than 2. Each update is reported, but not which peers observed which address, as discv5 does not expose the votes.")
                 .takes_value(true),
         )
         .arg(
@@ -124,6 +237,33 @@ fn server_cli<'a, 'b>() -> App<'a, 'b> {
                 .default_value("10")
                 .help("Displays statistics on the local routing table.")
         )
+        .arg(
+            Arg::with_name("dump-table")
+                .long("dump-table")
+                .value_name("FILE")
+                .help("On SIGUSR1, writes every routing table entry as JSON to this file instead of the log.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("topology")
+                .long("topology")
+                .value_name("FILE")
+                .help("On SIGUSR1 and shutdown, writes which node returned which other node to FILE, as GraphML if it ends in .graphml and as GraphViz DOT otherwise. Queries are then sent from a separate identity to observe the responses.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("track-peers")
+                .long("track-peers")
+                .help("After each search, probes every table peer with an ENR request and reports the latency and reliability of the probes with the statistics.")
+        )
+        .arg(
+            Arg::with_name("peer-report")
+                .long("peer-report")
+                .value_name("FILE")
+                .help("Writes the ranked peer report of --track-peers as JSON to this file.")
+                .requires("track-peers")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("no-search")
                 .short("x")
@@ -142,13 +282,55 @@ fn packet_cli<'a, 'b>() -> App<'a, 'b> {
                         .value_name("Packet")
                         .required(true)
                         .takes_value(true)
-                        .help("The packet to be decoded as a hex string."),
+                        .help("The packet to be decoded as a hex or base64 string, - to read packets from stdin or @PATH to read them from a file. Text input holds one packet per line."),
                 )
                 .arg(
-                    Arg::with_name("node_id")
+                    Arg::with_name("node-id")
                         .value_name("Node Id")
                         .takes_value(true)
                         .help("The node id of the destination of this packet to determine WHOAREYOU packets as a hex string."),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .possible_values(&["auto", "hex", "base64", "binary"])
+                        .default_value("auto")
+                        .help("The encoding of the packets. Binary input from a file or stdin is a single packet.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("wrapped")
+                        .long("wrapped")
+                        .help("Reads text input as one packet per block of lines, separated by blank lines, so packets wrapped over several lines, as hexdumps are, are joined."),
+                )
+                .arg(
+                    Arg::with_name("keyring")
+                        .long("keyring")
+                        .value_name("FILE")
+                        .help("A file of the nodes packets may be addressed to, one ENR or hex node id per line, optionally preceded by a name. Without a node id each is tried to unmask the header, and sources and destinations are labelled by name.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("layout")
+                        .long("layout")
+                        .help("Prints the bytes of the packet grouped and labelled by field, unmasking the header with the node id."),
+                )
+                .arg(
+                    Arg::with_name("diagnose")
+                        .long("diagnose")
+                        .help("Explains the likely cause of a decoding failure and what to try next."),
+                ),
+        )
+        .subcommand(
+            App::new("vectors")
+                .about("Reproduces the discv5 wire test vectors from their published inputs")
+                .subcommand(
+                    App::new("generate").about("Prints the inputs and computed values of every vector"),
+                )
+                .subcommand(
+                    App::new("verify")
+                        .about("Compares the computed values to the published ones field by field"),
                 ),
         )
 }
@@ -160,6 +342,676 @@ fn request_enr<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("multiaddr")
                 .value_name("MULTIADDR")
                 .takes_value(true)
-                .help("The multiaddr of the node to request their ENR from"),
+                .help("The multiaddr or enode URL of the node to request their ENR from. The request uses discv5, so discv4-only nodes time out."),
         )
 }
+
+fn enr_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("enr")
+        .about("Inspects and converts ENRs")
+        .subcommand(
+            App::new("from-enode")
+                .about("Converts an enode URL into its node id and, given its key, a signed ENR")
+                .arg(
+                    Arg::with_name("enode")
+                        .value_name("ENODE")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The enode URL to convert."),
+                )
+                .arg(
+                    Arg::with_name("secp256k1-key")
+                        .long("key")
+                        .short("k")
+                        .value_name("HEX_BYTES")
+                        .takes_value(true)
+                        .help("The secp256k1 private key (hex encoded) of the enode, used to sign the ENR."),
+                )
+                .arg(
+                    Arg::with_name("enr-seq-no")
+                        .long("enr-seq-no")
+                        .value_name("UINT")
+                        .takes_value(true)
+                        .help("Specifies the ENR sequence number when creating the ENR."),
+                ),
+        )
+        .subcommand(
+            App::new("to-enode")
+                .about("Converts an ENR into an enode URL")
+                .arg(
+                    Arg::with_name("enr")
+                        .value_name("BASE64-ENR")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .takes_value(true)
+                        .help("The base64 ENR to convert."),
+                ),
+        )
+        .subcommand(
+            App::new("diff")
+                .about("Reports the differences between two ENRs")
+                .arg(
+                    Arg::with_name("enr-a")
+                        .value_name("BASE64-ENR-A")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .takes_value(true)
+                        .help("The original ENR."),
+                )
+                .arg(
+                    Arg::with_name("enr-b")
+                        .value_name("BASE64-ENR-B")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .takes_value(true)
+                        .help("The ENR to compare it to."),
+                ),
+        )
+        .subcommand(
+            App::new("update")
+                .about("Edits an ENR and re-signs it with its key")
+                .arg(
+                    Arg::with_name("enr")
+                        .value_name("BASE64-ENR")
+                        .required(true)
+                        .allow_hyphen_values(true)
+                        .takes_value(true)
+                        .help("The ENR to update."),
+                )
+                .arg(
+                    Arg::with_name("secp256k1-key")
+                        .long("key")
+                        .short("k")
+                        .value_name("HEX_BYTES")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The secp256k1 private key (hex encoded) of the ENR."),
+                )
+                .arg(
+                    Arg::with_name("set")
+                        .long("set")
+                        .value_name("KEY=VALUE")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .help("Sets a key. IPs and ports are given as such, other values as hex. Can be repeated."),
+                )
+                .arg(
+                    Arg::with_name("remove")
+                        .long("remove")
+                        .value_name("KEY")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .help("Removes a key. Can be repeated."),
+                )
+                .arg(
+                    Arg::with_name("enr-seq-no")
+                        .long("enr-seq-no")
+                        .value_name("UINT")
+                        .takes_value(true)
+                        .help("Sets the sequence number instead of incrementing it."),
+                ),
+        )
+        .subcommand(
+            App::new("lint")
+                .about("Checks ENRs against the spec and common mistakes, failing on errors")
+                .arg(
+                    Arg::with_name("enr")
+                        .value_name("BASE64-ENR")
+                        .required(true)
+                        .multiple(true)
+                        .allow_hyphen_values(true)
+                        .takes_value(true)
+                        .help("The ENRs to check."),
+                )
+                .arg(
+                    Arg::with_name("strict")
+                        .long("strict")
+                        .help("Fails on warnings, such as private IP addresses, as well as errors."),
+                ),
+        )
+}
+
+fn search_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("search")
+        .about("Searches the network for peers whose ENR matches a predicate")
+        .arg(
+            Arg::with_name("attnet")
+                .long("attnet")
+                .value_name("SUBNET")
+                .help("Searches for peers subscribed to this attestation subnet, from 0 to 63.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("syncnet")
+                .long("syncnet")
+                .value_name("SUBNET")
+                .help("Searches for peers subscribed to this sync committee subnet, from 0 to 3.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("has-key")
+                .long("has-key")
+                .value_name("KEY")
+                .help("Searches for peers whose ENR contains this key.")
+                .takes_value(true),
+        )
+        .group(
+            ArgGroup::with_name("predicate")
+                .args(&["attnet", "syncnet", "has-key"])
+                .required(true),
+        )
+        .arg(
+            Arg::with_name("enr")
+                .long("enr")
+                .short("e")
+                .value_name("BASE64-ENR")
+                .allow_hyphen_values(true)
+                .required(true)
+                .multiple(true)
+                .number_of_values(1)
+                .help("A base64 ENR or enode URL to start the search from. Can be repeated. The ENR of an enode is requested over discv5, so discv4-only nodes time out.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("peers")
+                .long("peers")
+                .short("n")
+                .value_name("INT")
+                .default_value("10")
+                .help("The number of matching peers to search for.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("timeout")
+                .long("timeout")
+                .value_name("SECONDS")
+                .default_value("60")
+                .help("The time after which the search stops, even if not enough peers were found.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-address")
+                .value_name("IP-ADDRESS")
+                .long("listen-address")
+                .short("l")
+                .help("Specifies the listening address of the searching node.")
+                .default_value("0.0.0.0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-port")
+                .value_name("PORT")
+                .long("listen-port")
+                .short("p")
+                .help("Specifies the listening UDP port of the searching node.")
+                .default_value("9000")
+                .takes_value(true),
+        )
+}
+
+fn simulate_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("simulate")
+        .about("Runs reproducible lookups over a model of discv5 routing tables, without discv5 sessions")
+        .arg(
+            Arg::with_name("nodes")
+                .long("nodes")
+                .short("n")
+                .value_name("INT")
+                .default_value("64")
+                .help("The number of simulated nodes. The first node is the bootnode.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .short("s")
+                .value_name("UINT")
+                .default_value("0")
+                .help("The seed for all randomness in the simulation. A run is reproducible from its seed.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("latency")
+                .long("latency")
+                .value_name("MILLISECONDS")
+                .default_value("50")
+                .help("The mean one-way latency of a message.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("jitter")
+                .long("jitter")
+                .value_name("MILLISECONDS")
+                .default_value("10")
+                .help("The maximum deviation of a message's latency from the mean.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("loss")
+                .long("loss")
+                .value_name("PROBABILITY")
+                .default_value("0")
+                .help("The probability between 0 and 1 that a message is lost.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("parallelism")
+                .long("parallelism")
+                .value_name("INT")
+                .default_value("3")
+                .help("The number of concurrent requests of a lookup.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("request-timeout")
+                .long("request-timeout")
+                .value_name("MILLISECONDS")
+                .default_value("1000")
+                .help("The virtual time after which an unanswered request fails.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("queries")
+                .long("queries")
+                .short("q")
+                .value_name("INT")
+                .default_value("10")
+                .help("The number of random lookups to measure after bootstrapping.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("topology")
+                .long("topology")
+                .value_name("FILE")
+                .help("Writes which node returned which other node to FILE, as GraphML if it ends in .graphml and as GraphViz DOT otherwise.")
+                .takes_value(true),
+        )
+}
+
+fn dnstree_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("dnstree")
+        .about("Builds and verifies EIP-1459 DNS node lists")
+        .subcommand(
+            App::new("build")
+                .about("Builds a signed tree of ENRs and links as the TXT records of a zone file")
+                .arg(
+                    Arg::with_name("domain")
+                        .long("domain")
+                        .short("d")
+                        .value_name("DOMAIN")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The domain the tree is published at."),
+                )
+                .arg(
+                    Arg::with_name("secp256k1-key")
+                        .long("key")
+                        .short("k")
+                        .value_name("HEX_BYTES")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The secp256k1 private key (hex encoded) the tree is signed with."),
+                )
+                .arg(
+                    Arg::with_name("enr")
+                        .value_name("BASE64-ENR")
+                        .allow_hyphen_values(true)
+                        .multiple(true)
+                        .takes_value(true)
+                        .help("The ENRs of the list."),
+                )
+                .arg(
+                    Arg::with_name("enr-file")
+                        .long("enr-file")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("A file of ENRs to add to the list, one per line."),
+                )
+                .arg(
+                    Arg::with_name("link")
+                        .long("link")
+                        .value_name("ENRTREE-LINK")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .takes_value(true)
+                        .help("An enrtree:// link to another list. Can be repeated."),
+                )
+                .arg(
+                    Arg::with_name("seq")
+                        .long("seq")
+                        .value_name("UINT")
+                        .default_value("1")
+                        .takes_value(true)
+                        .help("The sequence number of the tree."),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .long("output")
+                        .short("o")
+                        .value_name("FILE")
+                        .takes_value(true)
+                        .help("Writes the zone file to FILE instead of stdout."),
+                ),
+        )
+        .subcommand(
+            App::new("verify")
+                .about("Checks the signature and hashes of a zone file tree and prints its ENRs")
+                .arg(
+                    Arg::with_name("zone-file")
+                        .value_name("ZONE-FILE")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The zone file containing the TXT records of the tree."),
+                )
+                .arg(
+                    Arg::with_name("domain")
+                        .long("domain")
+                        .short("d")
+                        .value_name("DOMAIN")
+                        .required(true)
+                        .takes_value(true)
+                        .help("The domain the tree is published at."),
+                )
+                .arg(
+                    Arg::with_name("public-key")
+                        .long("public-key")
+                        .value_name("KEY")
+                        .takes_value(true)
+                        .help("The expected signer, as a base32 public key or an enrtree:// link."),
+                ),
+        )
+}
+
+fn fuzz_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("fuzz")
+        .about("Sends mutated packets to a node and saves the packets that precede it becoming unresponsive")
+        .arg(
+            Arg::with_name("enr")
+                .value_name("BASE64-ENR")
+                .allow_hyphen_values(true)
+                .required(true)
+                .help("The ENR of the node to fuzz.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("packets")
+                .long("packets")
+                .short("n")
+                .value_name("INT")
+                .default_value("0")
+                .help("The number of packets to send. 0 sends packets until the target fails.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("check-interval")
+                .long("check-interval")
+                .value_name("INT")
+                .default_value("100")
+                .help("The number of packets sent between checks that the target is alive.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("liveness-timeout")
+                .long("liveness-timeout")
+                .value_name("SECONDS")
+                .default_value("5")
+                .help("The time the target has to answer a liveness check.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("delay")
+                .long("delay")
+                .value_name("MILLISECONDS")
+                .default_value("1")
+                .help("The time between packets.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .short("s")
+                .value_name("UINT")
+                .help("The seed of the packet mutations. A random seed is used and logged if not set.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .value_name("DIR")
+                .default_value("fuzz-failures")
+                .help("The directory the packets preceding a liveness failure are saved to.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-address")
+                .value_name("IP-ADDRESS")
+                .long("listen-address")
+                .short("l")
+                .help("Specifies the listening address of the node checking liveness.")
+                .default_value("0.0.0.0")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listen-port")
+                .value_name("PORT")
+                .long("listen-port")
+                .short("p")
+                .help("Specifies the listening UDP port of the node checking liveness.")
+                .default_value("9001")
+                .takes_value(true),
+        )
+}
+
+fn handshake_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("handshake")
+        .about("Explains the discv5 handshake")
+        .subcommand(
+            App::new("simulate")
+                .about("Walks through a handshake between two keys offline, printing every intermediate value")
+                .arg(
+                    Arg::with_name("initiator-key")
+                        .long("initiator-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The secp256k1 secret key of the node starting the handshake.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("recipient-key")
+                        .long("recipient-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The secp256k1 secret key of the node challenging the initiator.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ephemeral-key")
+                        .long("ephemeral-key")
+                        .value_name("HEX-KEY")
+                        .help("The ephemeral secret key of the initiator. A random key is used if not set.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("known-enr-seq")
+                        .long("known-enr-seq")
+                        .value_name("UINT")
+                        .default_value("0")
+                        .help("The sequence number of the initiator's ENR known to the recipient. The ENR is sent if it is older.")
+                        .takes_value(true),
+                ),
+        )
+}
+
+fn crypto_cli<'a, 'b>() -> App<'a, 'b> {
+    App::new("crypto")
+        .about("Computes the session keys and id-signatures of discv5 handshakes")
+        .subcommand(
+            App::new("derive-keys")
+                .about("Derives the session keys from an ECDH between an ephemeral secret key and a static public key")
+                .arg(
+                    Arg::with_name("ephemeral-key")
+                        .long("ephemeral-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The ephemeral secret key of the initiator.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("public-key")
+                        .long("public-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The static public key of the recipient.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("challenge-data")
+                        .long("challenge-data")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("The challenge data: the IV and unmasked header of the WHOAREYOU, as printed by packet decode.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("initiator-id")
+                        .long("initiator-id")
+                        .value_name("HEX-NODE-ID")
+                        .required(true)
+                        .help("The node id of the node that sent the handshake.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("recipient-id")
+                        .long("recipient-id")
+                        .value_name("HEX-NODE-ID")
+                        .required(true)
+                        .help("The node id of the node that sent the WHOAREYOU.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("id-sign")
+                .about("Signs the challenge of a handshake with the initiator's static key")
+                .arg(
+                    Arg::with_name("static-key")
+                        .long("static-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The static secret key of the initiator.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("challenge-data")
+                        .long("challenge-data")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("The challenge data: the IV and unmasked header of the WHOAREYOU, as printed by packet decode.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ephemeral-pubkey")
+                        .long("ephemeral-pubkey")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The compressed ephemeral public key sent in the handshake.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dst-id")
+                        .long("dst-id")
+                        .value_name("HEX-NODE-ID")
+                        .required(true)
+                        .help("The node id of the recipient of the handshake.")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            App::new("id-verify")
+                .about("Checks an id-signature against the initiator's static public key")
+                .arg(
+                    Arg::with_name("public-key")
+                        .long("public-key")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The static public key of the initiator.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("signature")
+                        .long("signature")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("The 64 byte id-signature.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("challenge-data")
+                        .long("challenge-data")
+                        .value_name("HEX")
+                        .required(true)
+                        .help("The challenge data: the IV and unmasked header of the WHOAREYOU, as printed by packet decode.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("ephemeral-pubkey")
+                        .long("ephemeral-pubkey")
+                        .value_name("HEX-KEY")
+                        .required(true)
+                        .help("The compressed ephemeral public key sent in the handshake.")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("dst-id")
+                        .long("dst-id")
+                        .value_name("HEX-NODE-ID")
+                        .required(true)
+                        .help("The node id of the recipient of the handshake.")
+                        .takes_value(true),
+                ),
+        )
+}
+
+/// Parses the value of an argument, failing if it was not supplied.
+pub fn value<T>(matches: &ArgMatches, name: &str) -> Result<T>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    optional_value(matches, name)?
+        .ok_or_else(|| Error::Usage(format!("A value for {} must be supplied", name)))
+}
+
+/// Parses the value of an argument, if it was supplied.
+pub fn optional_value<T>(matches: &ArgMatches, name: &str) -> Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    matches
+        .value_of(name)
+        .map(|value| {
+            value
+                .parse::<T>()
+                .map_err(|e| Error::Parse(format!("Invalid {} \"{}\": {}", name, value, e)))
+        })
+        .transpose()
+}
+
+/// Encodes bytes as `0x` prefixed hex, the form `hex_value` accepts.
+pub fn hex0x(bytes: &[u8]) -> String {
+    format!("0x{}", hex::encode(bytes))
+}
+
+/// Decodes the hex bytes of an argument, if it was supplied. A `0x` prefix is allowed.
+pub fn hex_value(matches: &ArgMatches, name: &str) -> Result<Option<Vec<u8>>> {
+    matches
+        .value_of(name)
+        .map(|value| {
+            hex::decode(value.trim_start_matches("0x"))
+                .map_err(|e| Error::Parse(format!("Invalid hex bytes for {}: {}", name, e)))
+        })
+        .transpose()
+}
@@ -0,0 +1,359 @@
+//! The cryptography of discv5 sessions: ECDH, session key derivation, id-signatures and message
+//! encryption.
+//!
+//! discv5 keeps its implementation private, so these follow the specification and are checked
+//! against its test vectors by `packet vectors verify`.
+
+use crate::cli;
+use crate::error::{Error, Result};
+use aes_gcm::aead::{Aead, NewAead, Payload};
+use aes_gcm::Aes128Gcm;
+use clap::ArgMatches;
+use discv5::enr::NodeId;
+use hkdf::Hkdf;
+use log::info;
+use sha2::{Digest, Sha256};
+
+/// The prefix of the HKDF info of the session keys.
+pub const KEY_AGREEMENT_STRING: &[u8] = b"discovery v5 key agreement";
+/// The prefix of the hash signed by an id-signature.
+pub const ID_SIGNATURE_TEXT: &[u8] = b"discovery v5 identity proof";
+/// The length of a session key.
+pub const KEY_LENGTH: usize = 16;
+/// The length of an AES-GCM message nonce.
+pub const NONCE_LENGTH: usize = 12;
+
+/// The keys of a session. The initiator encrypts with the initiator key and the recipient with
+/// the recipient key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionKeys {
+    pub initiator_key: [u8; KEY_LENGTH],
+    pub recipient_key: [u8; KEY_LENGTH],
+}
+
+/// Derives the session keys of a handshake based on the CLI options.
+pub fn derive_keys_cmd(matches: &ArgMatches) -> Result<()> {
+    let ephemeral_key = secret_key(&required_hex(matches, "ephemeral-key")?)?;
+    let public_key = public_key(&required_hex(matches, "public-key")?)?;
+    let challenge_data = required_hex(matches, "challenge-data")?;
+    let initiator_id = node_id_value(matches, "initiator-id")?;
+    let recipient_id = node_id_value(matches, "recipient-id")?;
+
+    let shared_secret = ecdh(&public_key, &ephemeral_key)?;
+    let keys = derive_keys(
+        &public_key,
+        &ephemeral_key,
+        &challenge_data,
+        &initiator_id,
+        &recipient_id,
+    )?;
+    info!("shared-secret = 0x{}", hex::encode(shared_secret));
+    info!("initiator-key = 0x{}", hex::encode(keys.initiator_key));
+    info!("recipient-key = 0x{}", hex::encode(keys.recipient_key));
+    Ok(())
+}
+
+/// Creates an id-signature based on the CLI options.
+pub fn id_sign_cmd(matches: &ArgMatches) -> Result<()> {
+    let static_key = secret_key(&required_hex(matches, "static-key")?)?;
+    let challenge_data = required_hex(matches, "challenge-data")?;
+    let ephemeral_pubkey = required_hex(matches, "ephemeral-pubkey")?;
+    let dst_id = node_id_value(matches, "dst-id")?;
+
+    let signature = id_sign(&static_key, &challenge_data, &ephemeral_pubkey, &dst_id);
+    info!("id-signature = 0x{}", hex::encode(signature));
+    Ok(())
+}
+
+/// Checks an id-signature based on the CLI options.
+pub fn id_verify_cmd(matches: &ArgMatches) -> Result<()> {
+    let public_key = public_key(&required_hex(matches, "public-key")?)?;
+    let signature = required_hex(matches, "signature")?;
+    let challenge_data = required_hex(matches, "challenge-data")?;
+    let ephemeral_pubkey = required_hex(matches, "ephemeral-pubkey")?;
+    let dst_id = node_id_value(matches, "dst-id")?;
+
+    if id_verify(
+        &public_key,
+        &signature,
+        &challenge_data,
+        &ephemeral_pubkey,
+        &dst_id,
+    )? {
+        info!("The id-signature is valid");
+        Ok(())
+    } else {
+        Err(Error::CheckFailed("The id-signature is invalid".into()))
+    }
+}
+
+fn required_hex(matches: &ArgMatches, name: &str) -> Result<Vec<u8>> {
+    cli::hex_value(matches, name)?
+        .ok_or_else(|| Error::Usage(format!("A value for {} must be supplied", name)))
+}
+
+fn node_id_value(matches: &ArgMatches, name: &str) -> Result<NodeId> {
+    NodeId::parse(&required_hex(matches, name)?)
+        .map_err(|e| Error::Parse(format!("Invalid node id for {}: {}", name, e)))
+}
+
+/// Parses a raw secp256k1 secret key.
+pub fn secret_key(bytes: &[u8]) -> Result<secp256k1::SecretKey> {
+    secp256k1::SecretKey::parse_slice(bytes)
+        .map_err(|_| Error::Parse("Invalid secp256k1 secret key".into()))
+}
+
+/// Generates a random secp256k1 secret key.
+pub fn random_secret_key() -> secp256k1::SecretKey {
+    loop {
+        // 32 random bytes are a valid key with overwhelming probability
+        if let Ok(key) = secp256k1::SecretKey::parse(&rand::random()) {
+            return key;
+        }
+    }
+}
+
+/// Parses a compressed or uncompressed secp256k1 public key.
+pub fn public_key(bytes: &[u8]) -> Result<secp256k1::PublicKey> {
+    secp256k1::PublicKey::parse_slice(bytes, None)
+        .map_err(|_| Error::Parse("Invalid secp256k1 public key".into()))
+}
+
+/// The shared secret of `public_key` and `secret_key`: the compressed point of their product.
+pub fn ecdh(
+    public_key: &secp256k1::PublicKey,
+    secret_key: &secp256k1::SecretKey,
+) -> Result<[u8; 33]> {
+    let mut point = public_key.clone();
+    point
+        .tweak_mul_assign(secret_key)
+        .map_err(|_| Error::Protocol("ECDH produced an invalid point".into()))?;
+    Ok(point.serialize_compressed())
+}
+
+/// Derives the session keys from the initiator's ephemeral secret key and the recipient's static
+/// public key, or equally from the recipient's static secret key and the ephemeral public key.
+pub fn derive_keys(
+    public_key: &secp256k1::PublicKey,
+    secret_key: &secp256k1::SecretKey,
+    challenge_data: &[u8],
+    initiator_id: &NodeId,
+    recipient_id: &NodeId,
+) -> Result<SessionKeys> {
+    let shared_secret = ecdh(public_key, secret_key)?;
+
+    let mut info = KEY_AGREEMENT_STRING.to_vec();
+    info.extend_from_slice(&initiator_id.raw());
+    info.extend_from_slice(&recipient_id.raw());
+
+    let mut okm = [0u8; 2 * KEY_LENGTH];
+    Hkdf::<Sha256>::new(Some(challenge_data), &shared_secret)
+        .expand(&info, &mut okm)
+        .map_err(|_| Error::Protocol("HKDF could not expand the session keys".into()))?;
+
+    let mut keys = SessionKeys {
+        initiator_key: [0u8; KEY_LENGTH],
+        recipient_key: [0u8; KEY_LENGTH],
+    };
+    keys.initiator_key.copy_from_slice(&okm[..KEY_LENGTH]);
+    keys.recipient_key.copy_from_slice(&okm[KEY_LENGTH..]);
+    Ok(keys)
+}
+
+/// The hash an id-signature signs: the identity proof text, the challenge data, the ephemeral
+/// public key and the node id of the recipient.
+fn id_signature_hash(challenge_data: &[u8], ephemeral_pubkey: &[u8], dst_id: &NodeId) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(ID_SIGNATURE_TEXT);
+    hasher.update(challenge_data);
+    hasher.update(ephemeral_pubkey);
+    hasher.update(dst_id.raw());
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&hasher.finalize());
+    hash
+}
+
+/// Signs the handshake with the static key of the initiator. Returns the 64 byte `r || s`
+/// signature.
+pub fn id_sign(
+    static_key: &secp256k1::SecretKey,
+    challenge_data: &[u8],
+    ephemeral_pubkey: &[u8],
+    dst_id: &NodeId,
+) -> [u8; 64] {
+    let hash = id_signature_hash(challenge_data, ephemeral_pubkey, dst_id);
+    let (signature, _) = secp256k1::sign(&secp256k1::Message::parse(&hash), static_key);
+    signature.serialize()
+}
+
+/// Checks an id-signature against the static public key of the initiator.
+pub fn id_verify(
+    public_key: &secp256k1::PublicKey,
+    signature: &[u8],
+    challenge_data: &[u8],
+    ephemeral_pubkey: &[u8],
+    dst_id: &NodeId,
+) -> Result<bool> {
+    let signature = secp256k1::Signature::parse_slice(signature)
+        .map_err(|_| Error::Parse("An id-signature must be 64 bytes".into()))?;
+    let hash = id_signature_hash(challenge_data, ephemeral_pubkey, dst_id);
+    Ok(secp256k1::verify(
+        &secp256k1::Message::parse(&hash),
+        &signature,
+        public_key,
+    ))
+}
+
+/// Encrypts a message with AES-128-GCM. `aad` is the masking IV followed by the unmasked header.
+pub fn encrypt(
+    key: &[u8; KEY_LENGTH],
+    nonce: &[u8; NONCE_LENGTH],
+    plaintext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    Aes128Gcm::new(key.into())
+        .encrypt(
+            nonce.into(),
+            Payload {
+                msg: plaintext,
+                aad,
+            },
+        )
+        .map_err(|_| Error::Protocol("The message could not be encrypted".into()))
+}
+
+/// Decrypts and authenticates a message encrypted with [`encrypt`].
+pub fn decrypt(
+    key: &[u8; KEY_LENGTH],
+    nonce: &[u8; NONCE_LENGTH],
+    ciphertext: &[u8],
+    aad: &[u8],
+) -> Result<Vec<u8>> {
+    Aes128Gcm::new(key.into())
+        .decrypt(
+            nonce.into(),
+            Payload {
+                msg: ciphertext,
+                aad,
+            },
+        )
+        .map_err(|_| {
+            Error::Protocol("The message could not be decrypted with the given key".into())
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::vectors::{CHALLENGE_DATA, NODE_A_ID, NODE_B_ID};
+
+    /// The secret key of the spec's ECDH, key derivation and id-signature vectors.
+    const SECRET_KEY: &str = "fb757dc581730490a1d7a00deea65e9b1936924caaea8f44d476014856b68736";
+    /// The public key of the ECDH vector and the ephemeral public key of the id-signature vector.
+    const PUBLIC_KEY: &str = "039961e4c2356d61bedb83052c115d311acb3a96f5777296dcf297351130266231";
+
+    fn bytes(hex_value: &str) -> Vec<u8> {
+        hex::decode(hex_value).unwrap()
+    }
+
+    fn node_id(hex_value: &str) -> NodeId {
+        NodeId::parse(&bytes(hex_value)).unwrap()
+    }
+
+    #[test]
+    fn computes_the_ecdh_vector() {
+        let shared_secret = ecdh(
+            &public_key(&bytes(PUBLIC_KEY)).unwrap(),
+            &secret_key(&bytes(SECRET_KEY)).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            hex::encode(shared_secret),
+            "033b11a2a1f214567e1537ce5e509ffd9b21373247f2a3ff6841f4976f53165e7e"
+        );
+    }
+
+    #[test]
+    fn derives_the_key_derivation_vector() {
+        let dest_pubkey = "0317931e6e0840220642f230037d285d122bc59063221ef3226b1f403ddc69ca91";
+        let keys = derive_keys(
+            &public_key(&bytes(dest_pubkey)).unwrap(),
+            &secret_key(&bytes(SECRET_KEY)).unwrap(),
+            &bytes(CHALLENGE_DATA),
+            &node_id(NODE_A_ID),
+            &node_id(NODE_B_ID),
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                hex::encode(keys.initiator_key),
+                hex::encode(keys.recipient_key)
+            ),
+            (
+                "dccc82d81bd610f4f76d3ebe97a40571".to_string(),
+                "ac74bb8773749920b0d3a8881c173ec5".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn signs_and_verifies_the_id_signature_vector() {
+        let static_key = secret_key(&bytes(SECRET_KEY)).unwrap();
+        let static_pubkey = secp256k1::PublicKey::from_secret_key(&static_key);
+        let challenge_data = bytes(CHALLENGE_DATA);
+        let ephemeral_pubkey = bytes(PUBLIC_KEY);
+        let dst_id = node_id(NODE_B_ID);
+
+        let signature = id_sign(&static_key, &challenge_data, &ephemeral_pubkey, &dst_id);
+        assert_eq!(
+            hex::encode(signature),
+            "94852a1e2318c4e5e9d422c98eaf19d1d90d876b29cd06ca7cb7546d0fff7b484fe86c09a064fe72bdbef73ba8e9c34df0cd2b53e9d65528c2c7f336d5dfc6e6"
+        );
+        assert!(id_verify(
+            &static_pubkey,
+            &signature,
+            &challenge_data,
+            &ephemeral_pubkey,
+            &dst_id
+        )
+        .unwrap());
+        // the signature is bound to the recipient
+        assert!(!id_verify(
+            &static_pubkey,
+            &signature,
+            &challenge_data,
+            &ephemeral_pubkey,
+            &node_id(NODE_A_ID)
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn encrypts_and_decrypts_the_encryption_vector() {
+        let key = [
+            0x9f, 0x2d, 0x77, 0xdb, 0x70, 0x04, 0xbf, 0x8a, 0x1a, 0x85, 0x10, 0x7a, 0xc6, 0x86,
+            0x99, 0x0b,
+        ];
+        let nonce = [
+            0x27, 0xb5, 0xaf, 0x76, 0x3c, 0x44, 0x6a, 0xcd, 0x27, 0x49, 0xfe, 0x8e,
+        ];
+        let ad = bytes("93a7400fa0d6a694ebc24d5cf570f65d04215b6ac00757875e3f3a5f42107903");
+
+        let ciphertext = encrypt(&key, &nonce, &bytes("01c20101"), &ad).unwrap();
+        assert_eq!(
+            hex::encode(&ciphertext),
+            "a5d12a2d94b8ccb3ba55558229867dc13bfa3648"
+        );
+        assert_eq!(
+            decrypt(&key, &nonce, &ciphertext, &ad).unwrap(),
+            bytes("01c20101")
+        );
+
+        let mut wrong_key = key;
+        wrong_key[0] ^= 1;
+        assert!(matches!(
+            decrypt(&wrong_key, &nonce, &ciphertext, &ad),
+            Err(Error::Protocol(_))
+        ));
+    }
+}
@@ -0,0 +1,292 @@
+//! Builds and verifies EIP-1459 DNS node lists.
+
+use crate::cli;
+use crate::error::{Error, Result};
+use clap::ArgMatches;
+use discv5::enr::{self, CombinedKey};
+use log::{error, info, warn};
+use std::collections::{BTreeMap, HashSet};
+
+mod tree;
+use tree::{Entry, Root, Tree};
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// Builds a signed tree of ENRs and links and writes it as a zone file.
+pub fn build(matches: &ArgMatches) -> Result<()> {
+    let domain = matches
+        .value_of("domain")
+        .ok_or_else(|| Error::Usage("A domain must be supplied".into()))?
+        .trim_end_matches('.');
+
+    let raw_key = cli::hex_value(matches, "secp256k1-key")?
+        .ok_or_else(|| Error::Usage("A key must be supplied".into()))?;
+    let key = secp256k1::SecretKey::parse_slice(&raw_key)
+        .map_err(|_| Error::Parse("Invalid secp256k1 key".into()))?;
+
+    let seq: u64 = cli::value(matches, "seq")?;
+
+    let mut enr_strings: Vec<String> = matches
+        .values_of("enr")
+        .map(|enrs| enrs.map(String::from).collect())
+        .unwrap_or_default();
+    if let Some(path) = matches.value_of("enr-file") {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Io(format!("Could not read the ENR file {}: {}", path, e)))?;
+        enr_strings.extend(
+            contents
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                .map(String::from),
+        );
+    }
+    let enrs = enr_strings
+        .iter()
+        .map(|enr| {
+            enr.parse::<Enr>()
+                .map_err(|e| Error::Parse(format!("Invalid ENR {}: {}", enr, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let links: Vec<String> = matches
+        .values_of("link")
+        .map(|links| links.map(String::from).collect())
+        .unwrap_or_default();
+    for link in &links {
+        tree::parse_link(link)
+            .map_err(|e| Error::Parse(format!("Invalid link {}: {}", link, e)))?;
+    }
+
+    info!(
+        "Building tree. ENRs: {}, links: {}",
+        enrs.len(),
+        links.len()
+    );
+    let tree = Tree::build(enrs, links, seq, &key);
+    let zone = tree.to_zone_file(domain);
+
+    match matches.value_of("output") {
+        Some(path) => {
+            std::fs::write(path, zone)
+                .map_err(|e| Error::Io(format!("Could not write the zone file {}: {}", path, e)))?;
+            info!("Zone file written to {}", path);
+        }
+        None => print!("{}", zone),
+    }
+    info!(
+        "Tree link: {}",
+        tree::link(&secp256k1::PublicKey::from_secret_key(&key), domain)
+    );
+    Ok(())
+}
+
+/// Walks the tree in a zone file, checking the root signature and the entry hashes, and prints
+/// the ENRs and links it contains.
+pub fn verify(matches: &ArgMatches) -> Result<()> {
+    let domain = matches
+        .value_of("domain")
+        .ok_or_else(|| Error::Usage("A domain must be supplied".into()))?
+        .trim_end_matches('.')
+        .to_lowercase();
+
+    let path = matches
+        .value_of("zone-file")
+        .ok_or_else(|| Error::Usage("A zone file must be supplied".into()))?;
+    let zone = std::fs::read_to_string(path)
+        .map_err(|e| Error::Io(format!("Could not read the zone file {}: {}", path, e)))?;
+    let records = tree::parse_zone_file(&zone)
+        .map_err(|e| Error::Parse(format!("Invalid zone file: {}", e)))?;
+
+    let root = records
+        .get(&domain)
+        .ok_or_else(|| format!("No root record at {}", domain))
+        .and_then(|text| Root::parse(text))
+        .map_err(|e| Error::CheckFailed(format!("Invalid root: {}", e)))?;
+
+    let signer = root
+        .recover_public_key()
+        .map_err(|e| Error::CheckFailed(format!("Invalid root signature: {}", e)))?;
+    // the key is either given on its own or as the link to the tree
+    if let Some(public_key) = matches.value_of("public-key") {
+        let expected = if public_key.starts_with(tree::LINK_PREFIX) {
+            tree::parse_link(public_key).map(|(key, _)| key)
+        } else {
+            tree::parse_public_key(public_key)
+        };
+        let expected = expected.map_err(|e| Error::Parse(format!("Invalid public key: {}", e)))?;
+        if expected != signer {
+            return Err(Error::CheckFailed(format!(
+                "The root is not signed by the given key. Signer: {}",
+                tree::link(&signer, &domain)
+            )));
+        }
+        info!("Root signature valid");
+    } else {
+        warn!("No public key given, the root signature is only checked to be well-formed");
+    }
+    info!("Tree link: {}", tree::link(&signer, &domain));
+    info!("Sequence number: {}", root.seq);
+
+    let mut visited = HashSet::new();
+    let enrs = walk(&records, &domain, &root.enr_root, &mut visited)
+        .map_err(|e| Error::CheckFailed(format!("Invalid ENR subtree: {}", e)))?;
+    let links = walk(&records, &domain, &root.link_root, &mut visited)
+        .map_err(|e| Error::CheckFailed(format!("Invalid link subtree: {}", e)))?;
+
+    let mut valid = true;
+    info!("ENRs: {}", enrs.len());
+    for entry in &enrs {
+        match entry {
+            Entry::Enr(enr) => info!("Node: {}, ENR: {}", enr.node_id(), enr.to_base64()),
+            other => {
+                error!("Unexpected entry in the ENR subtree: {}", other.text());
+                valid = false;
+            }
+        }
+    }
+    info!("Links: {}", links.len());
+    for entry in &links {
+        match entry {
+            Entry::Link(link) => info!("Link: {}", link),
+            other => {
+                error!("Unexpected entry in the link subtree: {}", other.text());
+                valid = false;
+            }
+        }
+    }
+
+    // the root and every reachable entry account for all records of the domain
+    let unreferenced = records
+        .keys()
+        .filter(|name| name.ends_with(&format!(".{}", domain)) && !visited.contains(*name))
+        .count();
+    if unreferenced > 0 {
+        warn!("Records not referenced by the tree: {}", unreferenced);
+    }
+
+    if !valid {
+        return Err(Error::CheckFailed(
+            "The tree has entries of the wrong type".into(),
+        ));
+    }
+    info!("Tree verified");
+    Ok(())
+}
+
+/// Collects the leaves below the entry with `hash`, checking that every record matches its hash.
+fn walk(
+    records: &BTreeMap<String, String>,
+    domain: &str,
+    hash: &str,
+    visited: &mut HashSet<String>,
+) -> std::result::Result<Vec<Entry>, String> {
+    let name = format!("{}.{}", hash, domain).to_lowercase();
+    if !visited.insert(name.clone()) {
+        return Err(format!("Entry {} is referenced more than once", hash));
+    }
+    let text = records
+        .get(&name)
+        .ok_or_else(|| format!("Missing record {}", name))?;
+    if !tree::hash(text).eq_ignore_ascii_case(hash) {
+        return Err(format!("The record {} does not match its hash", name));
+    }
+    match Entry::parse(text)? {
+        Entry::Branch(children) => {
+            let mut leaves = Vec::new();
+            for child in children {
+                leaves.extend(walk(records, domain, &child, visited)?);
+            }
+            Ok(leaves)
+        }
+        leaf => Ok(vec![leaf]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto;
+
+    const DOMAIN: &str = "nodes.example.org";
+
+    /// Builds a tree with enough ENRs to need more than one level of branches.
+    fn build_zone(key: &secp256k1::SecretKey) -> (Vec<Enr>, Vec<String>, String) {
+        let enrs: Vec<Enr> = (0..30)
+            .map(|_| {
+                enr::EnrBuilder::new("v4")
+                    .build(&CombinedKey::generate_secp256k1())
+                    .unwrap()
+            })
+            .collect();
+        let link_key = secp256k1::PublicKey::from_secret_key(&crypto::random_secret_key());
+        let links = vec![tree::link(&link_key, "other.example.org")];
+        let zone = Tree::build(enrs.clone(), links.clone(), 3, key).to_zone_file(DOMAIN);
+        (enrs, links, zone)
+    }
+
+    fn root(records: &BTreeMap<String, String>) -> Root {
+        Root::parse(&records[DOMAIN]).unwrap()
+    }
+
+    #[test]
+    fn built_trees_verify() {
+        let key = crypto::random_secret_key();
+        let (enrs, links, zone) = build_zone(&key);
+        let records = tree::parse_zone_file(&zone).unwrap();
+        let root = root(&records);
+        assert_eq!(root.seq, 3);
+        assert_eq!(
+            root.recover_public_key().unwrap(),
+            secp256k1::PublicKey::from_secret_key(&key)
+        );
+
+        let mut visited = HashSet::new();
+        let mut found: Vec<_> = walk(&records, DOMAIN, &root.enr_root, &mut visited)
+            .unwrap()
+            .iter()
+            .map(Entry::text)
+            .collect();
+        let mut expected: Vec<_> = enrs.iter().map(Enr::to_base64).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        let found: Vec<_> = walk(&records, DOMAIN, &root.link_root, &mut visited)
+            .unwrap()
+            .iter()
+            .map(Entry::text)
+            .collect();
+        assert_eq!(found, links);
+        // every record but the root is part of the tree
+        assert_eq!(visited.len(), records.len() - 1);
+    }
+
+    #[test]
+    fn altered_records_fail_verification() {
+        let key = crypto::random_secret_key();
+        let (_, _, zone) = build_zone(&key);
+        let mut records = tree::parse_zone_file(&zone).unwrap();
+        let root = root(&records);
+
+        // an entry replaced by another ENR no longer matches its hash
+        let (name, _) = records
+            .iter()
+            .find(|(_, text)| text.starts_with(tree::ENR_PREFIX))
+            .map(|(name, text)| (name.clone(), text.clone()))
+            .unwrap();
+        let other = enr::EnrBuilder::new("v4")
+            .build(&CombinedKey::generate_secp256k1())
+            .unwrap();
+        records.insert(name, other.to_base64());
+        assert!(walk(&records, DOMAIN, &root.enr_root, &mut HashSet::new()).is_err());
+
+        // a root with a changed sequence number recovers a different signer
+        let mut altered = root.clone();
+        altered.seq += 1;
+        assert_ne!(
+            altered.recover_public_key().ok(),
+            Some(secp256k1::PublicKey::from_secret_key(&key))
+        );
+    }
+}
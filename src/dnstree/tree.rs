@@ -0,0 +1,357 @@
+//! The EIP-1459 node list tree and its TXT record encoding.
+//!
+//! Every entry of the tree is published as a TXT record at `<hash>.<domain>`, where the hash is
+//! the base32 encoded first 16 bytes of the keccak256 hash of the record text. The root record,
+//! published at the domain itself, points to the roots of the ENR and link subtrees and is signed
+//! with the key of the list.
+
+use data_encoding::{BASE32_NOPAD, BASE64URL_NOPAD};
+use discv5::enr::{self, CombinedKey};
+use sha3::{Digest, Keccak256};
+use std::collections::BTreeMap;
+
+type Enr = enr::Enr<CombinedKey>;
+
+pub const ROOT_PREFIX: &str = "enrtree-root:v1";
+pub const BRANCH_PREFIX: &str = "enrtree-branch:";
+pub const LINK_PREFIX: &str = "enrtree://";
+pub const ENR_PREFIX: &str = "enr:";
+
+/// The maximum number of children of a branch, such that the record fits in a 370 byte TXT
+/// record.
+const MAX_CHILDREN: usize = 370 / (HASH_LENGTH + 1);
+/// The length of a base32 encoded entry hash.
+const HASH_LENGTH: usize = 26;
+/// The maximum length of a single character-string of a TXT record.
+const MAX_TXT_STRING: usize = 255;
+/// The TTL of the records in the zone file.
+const TTL: u32 = 86400;
+
+/// A non-root entry of the tree.
+#[derive(Debug, Clone)]
+pub enum Entry {
+    Branch(Vec<String>),
+    Enr(Box<Enr>),
+    Link(String),
+}
+
+impl Entry {
+    /// Parses the text of a TXT record.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if let Some(children) = text.strip_prefix(BRANCH_PREFIX) {
+            let children: Vec<String> = children
+                .split(',')
+                .filter(|hash| !hash.is_empty())
+                .map(String::from)
+                .collect();
+            if let Some(hash) = children.iter().find(|hash| !is_hash(hash)) {
+                return Err(format!("Invalid child hash in branch: {}", hash));
+            }
+            Ok(Entry::Branch(children))
+        } else if text.starts_with(LINK_PREFIX) {
+            parse_link(text)?;
+            Ok(Entry::Link(text.into()))
+        } else if text.starts_with(ENR_PREFIX) {
+            text.parse::<Enr>()
+                .map(|enr| Entry::Enr(Box::new(enr)))
+                .map_err(|e| format!("Invalid ENR entry: {}", e))
+        } else {
+            Err(format!("Unknown entry: {}", text))
+        }
+    }
+
+    /// The text of the TXT record of the entry.
+    pub fn text(&self) -> String {
+        match self {
+            Entry::Branch(children) => format!("{}{}", BRANCH_PREFIX, children.join(",")),
+            Entry::Enr(enr) => enr.to_base64(),
+            Entry::Link(link) => link.clone(),
+        }
+    }
+
+    /// The subdomain the entry is published at.
+    pub fn hash(&self) -> String {
+        hash(&self.text())
+    }
+}
+
+/// The signed root record of a tree.
+#[derive(Debug, Clone)]
+pub struct Root {
+    pub enr_root: String,
+    pub link_root: String,
+    pub seq: u64,
+    /// The 65 byte recoverable secp256k1 signature.
+    pub signature: Vec<u8>,
+}
+
+impl Root {
+    /// Parses the text of the root TXT record.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let fields = text
+            .strip_prefix(ROOT_PREFIX)
+            .ok_or_else(|| format!("The root record must start with {}", ROOT_PREFIX))?;
+        let mut enr_root = None;
+        let mut link_root = None;
+        let mut seq = None;
+        let mut signature = None;
+        for field in fields.split_whitespace() {
+            match field.split_once('=') {
+                Some(("e", hash)) => enr_root = Some(hash.to_string()),
+                Some(("l", hash)) => link_root = Some(hash.to_string()),
+                Some(("seq", value)) => {
+                    seq = Some(value.parse::<u64>().map_err(|_| "Invalid root seq")?)
+                }
+                Some(("sig", value)) => {
+                    signature = Some(
+                        BASE64URL_NOPAD
+                            .decode(value.as_bytes())
+                            .map_err(|_| "Invalid root signature encoding")?,
+                    )
+                }
+                _ => return Err(format!("Unknown root field: {}", field)),
+            }
+        }
+        let root = Root {
+            enr_root: enr_root.ok_or("The root has no e= field")?,
+            link_root: link_root.ok_or("The root has no l= field")?,
+            seq: seq.ok_or("The root has no seq= field")?,
+            signature: signature.ok_or("The root has no sig= field")?,
+        };
+        if !is_hash(&root.enr_root) || !is_hash(&root.link_root) {
+            return Err("Invalid subtree root hash".into());
+        }
+        if root.signature.len() != 65 {
+            return Err(format!(
+                "The root signature must be 65 bytes, got {}",
+                root.signature.len()
+            ));
+        }
+        Ok(root)
+    }
+
+    /// The signed part of the root record.
+    fn signed_text(&self) -> String {
+        format!(
+            "{} e={} l={} seq={}",
+            ROOT_PREFIX, self.enr_root, self.link_root, self.seq
+        )
+    }
+
+    /// The text of the root TXT record.
+    pub fn text(&self) -> String {
+        format!(
+            "{} sig={}",
+            self.signed_text(),
+            BASE64URL_NOPAD.encode(&self.signature)
+        )
+    }
+
+    /// Signs the root with the secp256k1 key of the list.
+    pub fn sign(&mut self, key: &secp256k1::SecretKey) {
+        let message = secp256k1::Message::parse(&keccak256(self.signed_text().as_bytes()));
+        let (signature, recovery_id) = secp256k1::sign(&message, key);
+        let mut bytes = signature.serialize().to_vec();
+        bytes.push(recovery_id.serialize());
+        self.signature = bytes;
+    }
+
+    /// Recovers the public key that signed the root.
+    pub fn recover_public_key(&self) -> Result<secp256k1::PublicKey, String> {
+        let message = secp256k1::Message::parse(&keccak256(self.signed_text().as_bytes()));
+        let signature = secp256k1::Signature::parse_slice(&self.signature[..64])
+            .map_err(|_| "Invalid root signature")?;
+        let recovery_id =
+            secp256k1::RecoveryId::parse(self.signature[64]).map_err(|_| "Invalid recovery id")?;
+        secp256k1::recover(&message, &signature, &recovery_id)
+            .map_err(|_| "Failed to recover the root signer".into())
+    }
+}
+
+/// A complete tree, ready to be written as a zone file.
+pub struct Tree {
+    pub root: Root,
+    /// The entries by their hash.
+    pub entries: BTreeMap<String, Entry>,
+}
+
+impl Tree {
+    /// Builds and signs a tree of the ENRs and links.
+    pub fn build(
+        mut enrs: Vec<Enr>,
+        mut links: Vec<String>,
+        seq: u64,
+        key: &secp256k1::SecretKey,
+    ) -> Self {
+        // sort the entries so the tree of the same list is always the same
+        enrs.sort_by_key(|enr| enr.node_id().raw());
+        enrs.dedup_by_key(|enr| enr.node_id());
+        links.sort();
+        links.dedup();
+
+        let mut entries = BTreeMap::new();
+        let enr_root = subtree(
+            &mut entries,
+            enrs.into_iter()
+                .map(|enr| Entry::Enr(Box::new(enr)))
+                .collect(),
+        );
+        let link_root = subtree(&mut entries, links.into_iter().map(Entry::Link).collect());
+
+        let mut root = Root {
+            enr_root,
+            link_root,
+            seq,
+            signature: Vec::new(),
+        };
+        root.sign(key);
+        Tree { root, entries }
+    }
+
+    /// Encodes the tree as the TXT records of a DNS zone file.
+    pub fn to_zone_file(&self, domain: &str) -> String {
+        let mut zone = format!("; EIP-1459 node list of {}\n", domain);
+        zone.push_str(&txt_record(domain, &self.root.text()));
+        for (hash, entry) in &self.entries {
+            zone.push_str(&txt_record(&format!("{}.{}", hash, domain), &entry.text()));
+        }
+        zone
+    }
+}
+
+/// Adds the entries to the tree below a single subtree root and returns the hash of the root.
+fn subtree(tree: &mut BTreeMap<String, Entry>, entries: Vec<Entry>) -> String {
+    let root = build_branches(tree, entries);
+    let hash = root.hash();
+    tree.insert(hash.clone(), root);
+    hash
+}
+
+/// Groups the entries into branches of at most `MAX_CHILDREN` until a single entry is left.
+fn build_branches(tree: &mut BTreeMap<String, Entry>, mut entries: Vec<Entry>) -> Entry {
+    if entries.len() == 1 {
+        return entries.remove(0);
+    }
+    if entries.len() <= MAX_CHILDREN {
+        let children = entries
+            .into_iter()
+            .map(|entry| {
+                let hash = entry.hash();
+                tree.insert(hash.clone(), entry);
+                hash
+            })
+            .collect();
+        return Entry::Branch(children);
+    }
+    let mut subtrees = Vec::new();
+    while !entries.is_empty() {
+        let rest = entries.split_off(MAX_CHILDREN.min(entries.len()));
+        subtrees.push(build_branches(tree, entries));
+        entries = rest;
+    }
+    build_branches(tree, subtrees)
+}
+
+/// Formats a TXT record of a zone file, splitting the text into 255 byte strings.
+fn txt_record(name: &str, text: &str) -> String {
+    let strings: Vec<String> = text
+        .as_bytes()
+        .chunks(MAX_TXT_STRING)
+        .map(|chunk| format!("\"{}\"", String::from_utf8_lossy(chunk)))
+        .collect();
+    format!("{}. {} IN TXT {}\n", name, TTL, strings.join(" "))
+}
+
+/// Parses the TXT records of a zone file into their text by name. The character-strings of a
+/// record are concatenated.
+pub fn parse_zone_file(zone: &str) -> Result<BTreeMap<String, String>, String> {
+    let mut records = BTreeMap::new();
+    for (line_no, line) in zone.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let (name, rest) = line
+            .split_once(char::is_whitespace)
+            .ok_or_else(|| format!("Line {}: Invalid record", line_no + 1))?;
+        // the TTL and class are optional, the type precedes the first character-string
+        let txt = match rest.find('"') {
+            Some(index)
+                if rest[..index]
+                    .split_whitespace()
+                    .any(|field| field.eq_ignore_ascii_case("TXT")) =>
+            {
+                &rest[index..]
+            }
+            _ => continue,
+        };
+        let mut text = String::new();
+        let mut in_string = false;
+        for c in txt.chars() {
+            match c {
+                '"' => in_string = !in_string,
+                c if in_string => text.push(c),
+                c if c.is_whitespace() => {}
+                c => {
+                    return Err(format!(
+                        "Line {}: Unexpected character outside a TXT string: {}",
+                        line_no + 1,
+                        c
+                    ))
+                }
+            }
+        }
+        if in_string {
+            return Err(format!("Line {}: Unterminated TXT string", line_no + 1));
+        }
+        let name = name.trim_end_matches('.').to_lowercase();
+        records.insert(name, text);
+    }
+    Ok(records)
+}
+
+/// Parses an `enrtree://<public-key>@<domain>` link into its public key and domain.
+pub fn parse_link(link: &str) -> Result<(secp256k1::PublicKey, String), String> {
+    let (key, domain) = link
+        .strip_prefix(LINK_PREFIX)
+        .and_then(|link| link.split_once('@'))
+        .ok_or_else(|| format!("A link must have the form {}<key>@<domain>", LINK_PREFIX))?;
+    Ok((parse_public_key(key)?, domain.to_string()))
+}
+
+/// Parses a base32 encoded compressed secp256k1 public key.
+pub fn parse_public_key(key: &str) -> Result<secp256k1::PublicKey, String> {
+    let bytes = BASE32_NOPAD
+        .decode(key.as_bytes())
+        .map_err(|_| "Invalid base32 public key")?;
+    secp256k1::PublicKey::parse_slice(&bytes, Some(secp256k1::PublicKeyFormat::Compressed))
+        .map_err(|_| "Invalid compressed secp256k1 public key".into())
+}
+
+/// The link to the tree of `domain` signed by `public_key`.
+pub fn link(public_key: &secp256k1::PublicKey, domain: &str) -> String {
+    format!(
+        "{}{}@{}",
+        LINK_PREFIX,
+        BASE32_NOPAD.encode(&public_key.serialize_compressed()),
+        domain
+    )
+}
+
+/// The subdomain of a record: the base32 encoded first 16 bytes of its keccak256 hash.
+pub fn hash(text: &str) -> String {
+    BASE32_NOPAD.encode(&keccak256(text.as_bytes())[..16])
+}
+
+fn is_hash(hash: &str) -> bool {
+    hash.len() == HASH_LENGTH
+        && BASE32_NOPAD
+            .decode(hash.as_bytes())
+            .is_ok_and(|bytes| bytes.len() == 16)
+}
+
+fn keccak256(data: &[u8]) -> [u8; 32] {
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&Keccak256::digest(data));
+    hash
+}
@@ -0,0 +1,255 @@
+//! Conversion between discv4 `enode://` URLs and ENRs.
+//!
+//! An enode URL has the form `enode://<public-key>@<ip>:<tcp-port>?discport=<udp-port>`, where the
+//! public key is the 64 byte uncompressed secp256k1 key in hex and `discport` is only given if the
+//! UDP port differs from the TCP port.
+
+use crate::request_enr::enr_ext::CombinedKeyPublicExt;
+use discv5::enr::{self, k256, CombinedKey, CombinedPublicKey, EnrKey, EnrPublicKey, NodeId};
+use libp2p_core::{multiaddr::Protocol, Multiaddr};
+use libp2p_identity::PeerId;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The scheme prefix of an enode URL.
+const ENODE_PREFIX: &str = "enode://";
+
+/// A parsed discv4 enode URL.
+#[derive(Debug, Clone)]
+pub struct Enode {
+    /// The secp256k1 public key of the node.
+    pub public_key: k256::ecdsa::VerifyingKey,
+    pub ip: IpAddr,
+    pub tcp_port: u16,
+    pub udp_port: u16,
+}
+
+impl Enode {
+    /// Returns true if the string looks like an enode URL rather than an ENR or multiaddr.
+    pub fn is_enode(s: &str) -> bool {
+        s.starts_with(ENODE_PREFIX)
+    }
+
+    /// Builds the enode URL of an ENR. The ENR must have a secp256k1 key, an IP and a port.
+    pub fn from_enr(enr: &Enr) -> Result<Self, String> {
+        let public_key = match enr.public_key() {
+            CombinedPublicKey::Secp256k1(public_key) => public_key,
+            _ => return Err("Only secp256k1 ENRs can be converted to an enode".into()),
+        };
+        let (ip, tcp_port, udp_port) = if let Some(ip) = enr.ip4() {
+            (IpAddr::V4(ip), enr.tcp4(), enr.udp4())
+        } else if let Some(ip6) = enr.ip6() {
+            (IpAddr::V6(ip6), enr.tcp6(), enr.udp6())
+        } else {
+            return Err("The ENR has no IP address".into());
+        };
+        let (tcp_port, udp_port) = match (tcp_port, udp_port) {
+            (Some(tcp), Some(udp)) => (tcp, udp),
+            (Some(port), None) | (None, Some(port)) => (port, port),
+            (None, None) => return Err("The ENR has no TCP or UDP port".into()),
+        };
+        Ok(Enode {
+            public_key,
+            ip,
+            tcp_port,
+            udp_port,
+        })
+    }
+
+    /// The node id of the enode, the keccak256 hash of its uncompressed public key.
+    pub fn node_id(&self) -> NodeId {
+        self.combined_public_key().into()
+    }
+
+    /// The libp2p `PeerId` of the enode, or an error if libp2p rejects its key.
+    pub fn peer_id(&self) -> crate::error::Result<PeerId> {
+        self.combined_public_key().try_into_peer_id()
+    }
+
+    /// The UDP multiaddr of the enode, which can be used to request its ENR.
+    pub fn multiaddr(&self) -> crate::error::Result<Multiaddr> {
+        let mut multiaddr: Multiaddr = self.ip.into();
+        multiaddr.push(Protocol::Udp(self.udp_port));
+        multiaddr.push(Protocol::P2p(self.peer_id()?.into()));
+        Ok(multiaddr)
+    }
+
+    /// Builds an ENR with the address of the enode, signed with `key` which must be the enode's
+    /// private key.
+    pub fn to_enr(&self, key: &CombinedKey, seq_no: Option<u64>) -> Result<Enr, String> {
+        if key.public().encode() != self.combined_public_key().encode() {
+            return Err("The key does not match the public key of the enode".into());
+        }
+        let mut builder = enr::EnrBuilder::new("v4");
+        builder.ip(self.ip);
+        match self.ip {
+            IpAddr::V4(_) => {
+                builder.tcp4(self.tcp_port);
+                builder.udp4(self.udp_port);
+            }
+            IpAddr::V6(_) => {
+                builder.tcp6(self.tcp_port);
+                builder.udp6(self.udp_port);
+            }
+        }
+        if let Some(seq_no) = seq_no {
+            builder.seq(seq_no);
+        }
+        builder
+            .build(key)
+            .map_err(|e| format!("Failed to build the ENR: {:?}", e))
+    }
+
+    fn combined_public_key(&self) -> CombinedPublicKey {
+        CombinedPublicKey::Secp256k1(self.public_key)
+    }
+}
+
+impl FromStr for Enode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s
+            .strip_prefix(ENODE_PREFIX)
+            .ok_or("An enode must start with enode://")?;
+        let (public_key_hex, address) = s
+            .split_once('@')
+            .ok_or("An enode must contain a public key and an address separated by @")?;
+
+        let public_key_bytes =
+            hex::decode(public_key_hex).map_err(|_| "Invalid enode public key hex")?;
+        if public_key_bytes.len() != 64 {
+            return Err("An enode public key must be 64 bytes".into());
+        }
+        // prefix the uncompressed SEC1 tag
+        let mut sec1_bytes = vec![4u8];
+        sec1_bytes.extend_from_slice(&public_key_bytes);
+        let public_key = k256::ecdsa::VerifyingKey::from_sec1_bytes(&sec1_bytes)
+            .map_err(|_| "Invalid enode public key")?;
+
+        let (socket, query) = match address.split_once('?') {
+            Some((socket, query)) => (socket, Some(query)),
+            None => (address, None),
+        };
+        let socket = socket
+            .parse::<SocketAddr>()
+            .map_err(|_| "Invalid enode IP address and port")?;
+
+        let mut udp_port = socket.port();
+        if let Some(query) = query {
+            for parameter in query.split('&') {
+                if let Some(port) = parameter.strip_prefix("discport=") {
+                    udp_port = port.parse().map_err(|_| "Invalid enode discport")?;
+                }
+            }
+        }
+
+        Ok(Enode {
+            public_key,
+            ip: socket.ip(),
+            tcp_port: socket.port(),
+            udp_port,
+        })
+    }
+}
+
+impl std::fmt::Display for Enode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let public_key = self.combined_public_key().encode_uncompressed();
+        write!(
+            f,
+            "{}{}@{}",
+            ENODE_PREFIX,
+            hex::encode(public_key),
+            SocketAddr::new(self.ip, self.tcp_port)
+        )?;
+        if self.udp_port != self.tcp_port {
+            write!(f, "?discport={}", self.udp_port)?;
+        }
+        Ok(())
+    }
+}
+
+/// A node given on the command line, either as a base64 ENR or as an enode URL.
+#[derive(Debug, Clone)]
+pub enum EnrOrEnode {
+    Enr(Enr),
+    Enode(Enode),
+}
+
+impl FromStr for EnrOrEnode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if Enode::is_enode(s) {
+            s.parse().map(EnrOrEnode::Enode)
+        } else {
+            s.parse::<Enr>()
+                .map(EnrOrEnode::Enr)
+                .map_err(|e| format!("Invalid base64 encoded ENR: {}", e))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The secp256k1 key of the discv5 spec test vectors, node A.
+    const SECRET_KEY: &str = "eef77acb6c6a6eebc5b363a475ac583ec7eccdb42b6481424c60f59aa326547f";
+
+    fn public_key_hex() -> String {
+        let key = k256::ecdsa::SigningKey::from_bytes(&hex::decode(SECRET_KEY).unwrap()).unwrap();
+        hex::encode(CombinedKey::from(key).public().encode_uncompressed())
+    }
+
+    #[test]
+    fn parses_and_displays_enodes() {
+        let same_ports = format!("enode://{}@10.0.0.1:30303", public_key_hex());
+        let enode: Enode = same_ports.parse().unwrap();
+        assert_eq!(enode.ip, "10.0.0.1".parse::<IpAddr>().unwrap());
+        assert_eq!((enode.tcp_port, enode.udp_port), (30303, 30303));
+        assert_eq!(enode.to_string(), same_ports);
+
+        let discport = format!("enode://{}@[::1]:30303?discport=30301", public_key_hex());
+        let enode: Enode = discport.parse().unwrap();
+        assert_eq!((enode.tcp_port, enode.udp_port), (30303, 30301));
+        assert_eq!(enode.to_string(), discport);
+    }
+
+    #[test]
+    fn rejects_invalid_enodes() {
+        let public_key = public_key_hex();
+        for invalid in [
+            format!("{}@10.0.0.1:30303", public_key),
+            format!("enode://{}10.0.0.1:30303", public_key),
+            format!("enode://{}@10.0.0.1:30303", &public_key[2..]),
+            format!("enode://{}@10.0.0.1", public_key),
+            format!("enode://{}@10.0.0.1:30303?discport=x", public_key),
+        ] {
+            assert!(invalid.parse::<Enode>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn converts_to_and_from_enrs() {
+        let key = k256::ecdsa::SigningKey::from_bytes(&hex::decode(SECRET_KEY).unwrap()).unwrap();
+        let key = CombinedKey::from(key);
+        let enode: Enode = format!("enode://{}@10.0.0.1:30303?discport=30301", public_key_hex())
+            .parse()
+            .unwrap();
+
+        let enr = enode.to_enr(&key, Some(2)).unwrap();
+        assert_eq!(enr.node_id(), enode.node_id());
+        assert_eq!(enr.seq(), 2);
+        assert_eq!(
+            Enode::from_enr(&enr).unwrap().to_string(),
+            enode.to_string()
+        );
+        assert!(enode
+            .to_enr(&CombinedKey::generate_secp256k1(), None)
+            .is_err());
+    }
+}
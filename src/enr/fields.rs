@@ -0,0 +1,161 @@
+//! Interpretation of the well-known ENR keys.
+
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The length of the SSZ encoded `ENRForkID` of the `eth2` key.
+pub const ETH2_LENGTH: usize = 16;
+/// The length of the SSZ encoded attestation subnet bitvector.
+pub const ATTNETS_LENGTH: usize = 8;
+/// The length of the SSZ encoded sync committee subnet bitvector.
+pub const SYNCNETS_LENGTH: usize = 1;
+
+/// Decodes the value of a known ENR key into a readable form. Returns `None` for unknown keys.
+pub fn interpret(key: &[u8], value: &[u8]) -> Option<Result<String, String>> {
+    let interpreted = match key {
+        b"id" => std::str::from_utf8(value)
+            .map(String::from)
+            .map_err(|_| "not valid UTF-8".to_string()),
+        b"ip" => <[u8; 4]>::try_from(value)
+            .map(|octets| Ipv4Addr::from(octets).to_string())
+            .map_err(|_| format!("expected 4 bytes, got {}", value.len())),
+        b"ip6" => <[u8; 16]>::try_from(value)
+            .map(|octets| Ipv6Addr::from(octets).to_string())
+            .map_err(|_| format!("expected 16 bytes, got {}", value.len())),
+        b"tcp" | b"udp" | b"tcp6" | b"udp6" | b"quic" | b"quic6" => {
+            port(value).map(|port| port.to_string())
+        }
+        b"secp256k1" => Ok(format!("public key 0x{}", hex::encode(value))),
+        b"eth2" => eth2(value),
+        b"attnets" => subnets(value, ATTNETS_LENGTH),
+        b"syncnets" => subnets(value, SYNCNETS_LENGTH),
+        _ => return None,
+    };
+    Some(interpreted)
+}
+
+/// Encodes a value given on the command line for an ENR key. Addresses and ports of the known keys
+/// are given in their usual notation, any other value as hex bytes.
+pub fn encode(key: &str, value: &str) -> Result<Vec<u8>, String> {
+    match key {
+        "id" | "secp256k1" => Err(format!("The {} key is set when signing the record", key)),
+        "ip" => match value.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => Ok(ip.octets().to_vec()),
+            _ => Err(format!("Invalid IPv4 address: {}", value)),
+        },
+        "ip6" => match value.parse::<IpAddr>() {
+            Ok(IpAddr::V6(ip)) => Ok(ip.octets().to_vec()),
+            _ => Err(format!("Invalid IPv6 address: {}", value)),
+        },
+        "tcp" | "udp" | "tcp6" | "udp6" | "quic" | "quic6" => value
+            .parse::<u16>()
+            .map(|port| {
+                // an RLP integer has no leading zero bytes
+                port.to_be_bytes()
+                    .iter()
+                    .skip_while(|byte| **byte == 0)
+                    .copied()
+                    .collect()
+            })
+            .map_err(|_| format!("Invalid port: {}", value)),
+        _ => hex::decode(value.trim_start_matches("0x"))
+            .map_err(|_| format!("Invalid hex value for {}: {}", key, value)),
+    }
+}
+
+/// Decodes an RLP integer port, which has its leading zero bytes removed.
+pub fn port(value: &[u8]) -> Result<u16, String> {
+    if value.len() > 2 {
+        return Err(format!("expected at most 2 bytes, got {}", value.len()));
+    }
+    Ok(value
+        .iter()
+        .fold(0u16, |port, byte| (port << 8) | u16::from(*byte)))
+}
+
+/// Decodes the SSZ `ENRForkID`: the fork digest, next fork version and next fork epoch.
+fn eth2(value: &[u8]) -> Result<String, String> {
+    if value.len() != ETH2_LENGTH {
+        return Err(format!(
+            "expected {} bytes, got {}",
+            ETH2_LENGTH,
+            value.len()
+        ));
+    }
+    let mut epoch = [0u8; 8];
+    epoch.copy_from_slice(&value[8..]);
+    Ok(format!(
+        "fork digest 0x{}, next fork version 0x{}, next fork epoch {}",
+        hex::encode(&value[..4]),
+        hex::encode(&value[4..8]),
+        u64::from_le_bytes(epoch)
+    ))
+}
+
+/// Lists the subnets set in an SSZ bitvector.
+fn subnets(value: &[u8], length: usize) -> Result<String, String> {
+    if value.len() != length {
+        return Err(format!("expected {} bytes, got {}", length, value.len()));
+    }
+    let subnets: Vec<String> = (0..length * 8)
+        .filter(|index| value[index / 8] & (1 << (index % 8)) != 0)
+        .map(|index| index.to_string())
+        .collect();
+    Ok(format!("subnets [{}]", subnets.join(", ")))
+}
+
+/// Formats a value as its hex bytes followed by its interpretation, if the key is known.
+pub fn display(key: &[u8], value: &[u8]) -> String {
+    match interpret(key, value) {
+        Some(Ok(interpreted)) => format!("0x{} ({})", hex::encode(value), interpreted),
+        Some(Err(e)) => format!("0x{} (invalid: {})", hex::encode(value), e),
+        None => format!("0x{}", hex::encode(value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interprets_known_keys() {
+        assert_eq!(display(b"ip", &[10, 0, 0, 1]), "0x0a000001 (10.0.0.1)");
+        assert_eq!(display(b"udp", &[0x23, 0x28]), "0x2328 (9000)");
+        assert_eq!(
+            display(b"attnets", &[0b0000_0101, 0, 0, 0, 0, 0, 0, 0x80]),
+            "0x0500000000000080 (subnets [0, 2, 63])"
+        );
+        let mut eth2 = vec![0xb5, 0x30, 0x3f, 0x2a, 0x02, 0, 0, 0];
+        eth2.extend_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(
+            interpret(b"eth2", &eth2),
+            Some(Ok(format!(
+                "fork digest 0xb5303f2a, next fork version 0x02000000, next fork epoch {}",
+                u64::MAX
+            )))
+        );
+        assert_eq!(
+            display(b"ip", &[10, 0, 0]),
+            "0x0a0000 (invalid: expected 4 bytes, got 3)"
+        );
+        assert_eq!(display(b"custom", &[1, 2]), "0x0102");
+    }
+
+    #[test]
+    fn encodes_edits_as_interpreted() {
+        assert_eq!(encode("ip", "10.0.0.1").unwrap(), vec![10, 0, 0, 1]);
+        assert_eq!(encode("udp", "9000").unwrap(), vec![0x23, 0x28]);
+        assert_eq!(encode("tcp", "80").unwrap(), vec![80]);
+        assert_eq!(encode("custom", "0x0102").unwrap(), vec![1, 2]);
+        for (key, value) in [("ip6", "::1"), ("udp6", "30303"), ("quic", "1")] {
+            let encoded = encode(key, value).unwrap();
+            assert_eq!(interpret(key.as_bytes(), &encoded), Some(Ok(value.into())));
+        }
+
+        assert!(encode("ip", "::1").is_err());
+        assert!(encode("udp", "65536").is_err());
+        assert!(encode("custom", "xyz").is_err());
+        assert!(encode("id", "v4").is_err());
+        assert!(encode("secp256k1", "0x02").is_err());
+    }
+}
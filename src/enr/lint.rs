@@ -0,0 +1,218 @@
+//! Checks an ENR against the spec and common operational mistakes.
+
+use super::fields;
+use discv5::enr::{self, CombinedKey};
+use std::net::IpAddr;
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The maximum size of an encoded ENR.
+const MAX_ENR_SIZE: usize = 300;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+impl std::fmt::Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Severity::Warning => write!(f, "warning"),
+            Severity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// A problem found in a record.
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl Finding {
+    fn error(message: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Error,
+            message: message.into(),
+        }
+    }
+
+    fn warning(message: impl Into<String>) -> Self {
+        Finding {
+            severity: Severity::Warning,
+            message: message.into(),
+        }
+    }
+}
+
+/// Lints a record and returns every problem found.
+pub fn check(enr: &Enr) -> Vec<Finding> {
+    let mut findings = Vec::new();
+
+    if !enr.verify() {
+        findings.push(Finding::error("Invalid signature"));
+    }
+    if enr.size() > MAX_ENR_SIZE {
+        findings.push(Finding::error(format!(
+            "The record is {} bytes, the maximum is {}",
+            enr.size(),
+            MAX_ENR_SIZE
+        )));
+    }
+    match enr.id() {
+        Some(id) if id == "v4" => {}
+        Some(id) => findings.push(Finding::error(format!("Unknown identity scheme: {}", id))),
+        None => findings.push(Finding::error("The record has no identity scheme")),
+    }
+
+    // the values of the known keys must be well-formed
+    for (key, _) in enr.iter() {
+        if let Some(value) = enr.get(key) {
+            if let Some(Err(e)) = fields::interpret(key, value) {
+                findings.push(Finding::error(format!(
+                    "Malformed {}: {}",
+                    String::from_utf8_lossy(key),
+                    e
+                )));
+            }
+        }
+    }
+
+    for port_key in ["tcp", "udp", "tcp6", "udp6", "quic", "quic6"].iter() {
+        if let Some(Ok(0)) = enr.get(port_key).map(fields::port) {
+            findings.push(Finding::error(format!("The {} port is 0", port_key)));
+        }
+    }
+
+    check_address_family(&mut findings, enr, "ip", "udp", "tcp");
+    check_address_family(&mut findings, enr, "ip6", "udp6", "tcp6");
+
+    if let Some(ip) = enr.ip4() {
+        check_public_ip(&mut findings, IpAddr::V4(ip));
+    }
+    if let Some(ip6) = enr.ip6() {
+        check_public_ip(&mut findings, IpAddr::V6(ip6));
+    }
+
+    findings
+}
+
+/// An address family needs both an IP and a UDP port to be reachable for discovery.
+fn check_address_family(
+    findings: &mut Vec<Finding>,
+    enr: &Enr,
+    ip_key: &str,
+    udp_key: &str,
+    tcp_key: &str,
+) {
+    let has_ip = enr.get(ip_key).is_some();
+    let has_udp = enr.get(udp_key).is_some();
+    if has_ip && !has_udp {
+        findings.push(Finding::error(format!(
+            "The record has an {} but no {} port",
+            ip_key, udp_key
+        )));
+    }
+    for port_key in [udp_key, tcp_key].iter() {
+        if !has_ip && enr.get(port_key).is_some() {
+            findings.push(Finding::error(format!(
+                "The record has a {} port but no {}",
+                port_key, ip_key
+            )));
+        }
+    }
+}
+
+/// Flags addresses that other nodes on the internet cannot reach.
+fn check_public_ip(findings: &mut Vec<Finding>, ip: IpAddr) {
+    if ip.is_unspecified() {
+        findings.push(Finding::error(format!("Unspecified IP address: {}", ip)));
+    } else if ip.is_loopback() {
+        findings.push(Finding::error(format!("Loopback IP address: {}", ip)));
+    } else if is_private(&ip) {
+        findings.push(Finding::warning(format!(
+            "Private IP address in a public record: {}",
+            ip
+        )));
+    }
+}
+
+fn is_private(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            // unique local fc00::/7 and link local fe80::/10
+            (ip.segments()[0] & 0xfe00) == 0xfc00 || (ip.segments()[0] & 0xffc0) == 0xfe80
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(ip: &str, udp_port: Option<u16>) -> Enr {
+        let mut builder = enr::EnrBuilder::new("v4");
+        builder.ip(ip.parse().unwrap());
+        if let Some(udp_port) = udp_port {
+            builder.udp4(udp_port);
+        }
+        builder.build(&CombinedKey::generate_secp256k1()).unwrap()
+    }
+
+    fn findings(enr: &Enr) -> Vec<(Severity, String)> {
+        check(enr)
+            .into_iter()
+            .map(|finding| (finding.severity, finding.message))
+            .collect()
+    }
+
+    #[test]
+    fn accepts_a_public_record() {
+        assert!(findings(&record("1.2.3.4", Some(9000))).is_empty());
+    }
+
+    #[test]
+    fn flags_unreachable_addresses() {
+        assert_eq!(
+            findings(&record("1.2.3.4", None)),
+            vec![(
+                Severity::Error,
+                "The record has an ip but no udp port".to_string()
+            )]
+        );
+        assert_eq!(
+            findings(&record("1.2.3.4", Some(0))),
+            vec![(Severity::Error, "The udp port is 0".to_string())]
+        );
+        assert_eq!(
+            findings(&record("127.0.0.1", Some(9000))),
+            vec![(
+                Severity::Error,
+                "Loopback IP address: 127.0.0.1".to_string()
+            )]
+        );
+        assert_eq!(
+            findings(&record("192.168.1.1", Some(9000))),
+            vec![(
+                Severity::Warning,
+                "Private IP address in a public record: 192.168.1.1".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn flags_ports_without_an_ip() {
+        let mut builder = enr::EnrBuilder::new("v4");
+        builder.udp6(9000);
+        let enr = builder.build(&CombinedKey::generate_secp256k1()).unwrap();
+        assert_eq!(
+            findings(&enr),
+            vec![(
+                Severity::Error,
+                "The record has a udp6 port but no ip6".to_string()
+            )]
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Tools for inspecting and converting ENRs.
+
+use crate::cli;
+use crate::error::{Error, Result};
+use crate::request_enr::enr_ext::EnrExt;
+use crate::server;
+use clap::ArgMatches;
+use discv5::{
+    enr::{CombinedKey, EnrBuilder, EnrKey, EnrPublicKey},
+    Discv5,
+};
+use log::{error, info, warn};
+use std::collections::{BTreeMap, BTreeSet};
+
+pub mod enode;
+pub mod fields;
+mod lint;
+pub use enode::{Enode, EnrOrEnode};
+
+type Enr = discv5::enr::Enr<CombinedKey>;
+
+/// Converts an enode URL into its node id and, given the private key, a signed ENR.
+pub fn from_enode(matches: &ArgMatches) -> Result<()> {
+    let enode: Enode = cli::value(matches, "enode")?;
+
+    info!("Node Id: {}", enode.node_id());
+    info!("Libp2p PeerId: {}", enode.peer_id()?);
+    info!("Multiaddr: {}", enode.multiaddr()?);
+
+    if let Some(raw_key) = cli::hex_value(matches, "secp256k1-key")? {
+        let enr_key = server::key_from_bytes(&raw_key)?;
+        let seq_no = cli::optional_value(matches, "enr-seq-no")?;
+        let enr = enode.to_enr(&enr_key, seq_no).map_err(Error::Parse)?;
+        info!("Base64 ENR: {}", enr.to_base64());
+    }
+    Ok(())
+}
+
+/// Converts an ENR into an enode URL.
+pub fn to_enode(matches: &ArgMatches) -> Result<()> {
+    let enr: Enr = cli::value(matches, "enr")?;
+    let enode = Enode::from_enr(&enr).map_err(Error::Parse)?;
+    info!("Enode: {}", enode);
+    Ok(())
+}
+
+/// Reports the differences between two ENRs: their identity, sequence numbers and every added,
+/// removed or changed key.
+pub fn diff(matches: &ArgMatches) -> Result<()> {
+    let a: Enr = cli::value(matches, "enr-a")?;
+    let b: Enr = cli::value(matches, "enr-b")?;
+
+    if a.public_key().encode() == b.public_key().encode() {
+        info!("Same identity. Node Id: {}", a.node_id());
+    } else {
+        info!(
+            "Different identities. Node Ids: {} -> {}",
+            a.node_id(),
+            b.node_id()
+        );
+    }
+    info!(
+        "Seq: {} -> {} ({:+})",
+        a.seq(),
+        b.seq(),
+        i128::from(b.seq()) - i128::from(a.seq())
+    );
+
+    let keys: BTreeSet<Vec<u8>> = a
+        .iter()
+        .chain(b.iter())
+        .map(|(key, _)| key.clone())
+        .collect();
+    let mut differences = 0;
+    for key in keys {
+        let name = String::from_utf8_lossy(&key);
+        match (a.get(&key), b.get(&key)) {
+            (Some(old), Some(new)) if old != new => info!(
+                "Changed {}: {} -> {}",
+                name,
+                fields::display(&key, old),
+                fields::display(&key, new)
+            ),
+            (Some(old), None) => info!("Removed {}: {}", name, fields::display(&key, old)),
+            (None, Some(new)) => info!("Added {}: {}", name, fields::display(&key, new)),
+            _ => continue,
+        }
+        differences += 1;
+    }
+    if differences == 0 {
+        info!("The records have the same keys and values");
+    }
+    Ok(())
+}
+
+/// Applies edits to an ENR and re-signs it with its key. The sequence number is incremented unless
+/// one is given.
+pub fn update(matches: &ArgMatches) -> Result<()> {
+    let enr: Enr = cli::value(matches, "enr")?;
+
+    let raw_key = cli::hex_value(matches, "secp256k1-key")?
+        .ok_or_else(|| Error::Usage("A key must be supplied".into()))?;
+    let enr_key = server::key_from_bytes(&raw_key)?;
+    if enr_key.public().encode() != enr.public_key().encode() {
+        return Err(Error::Usage(
+            "The key does not match the public key of the ENR".into(),
+        ));
+    }
+
+    let seq_no = match cli::optional_value(matches, "enr-seq-no")? {
+        Some(seq_no) => seq_no,
+        None => enr.seq().saturating_add(1),
+    };
+
+    // the current values, without the identity keys the builder adds when signing
+    let mut values: BTreeMap<Vec<u8>, Vec<u8>> = enr
+        .iter()
+        .filter(|(key, _)| key.as_slice() != b"id" && key.as_slice() != b"secp256k1")
+        .filter_map(|(key, _)| enr.get(key).map(|value| (key.clone(), value.to_vec())))
+        .collect();
+
+    for edit in matches.values_of("set").into_iter().flatten() {
+        let (key, value) = edit.split_once('=').ok_or_else(|| {
+            Error::Parse(format!(
+                "An edit must have the form <key>=<value>: {}",
+                edit
+            ))
+        })?;
+        let value = fields::encode(key, value)
+            .map_err(|e| Error::Parse(format!("Invalid edit {}: {}", edit, e)))?;
+        info!("Set {}: {}", key, fields::display(key.as_bytes(), &value));
+        values.insert(key.as_bytes().to_vec(), value);
+    }
+    for key in matches.values_of("remove").into_iter().flatten() {
+        if values.remove(key.as_bytes()).is_none() {
+            return Err(Error::Usage(format!(
+                "The ENR has no {} key to remove",
+                key
+            )));
+        }
+        info!("Removed {}", key);
+    }
+
+    let mut builder = EnrBuilder::new("v4");
+    for (key, value) in &values {
+        builder.add_value(key, value);
+    }
+    builder.seq(seq_no);
+    let updated = server::sign_enr(&mut builder, &enr_key)?;
+    info!("Seq: {} -> {}", enr.seq(), updated.seq());
+    info!("Base64 ENR: {}", updated.to_base64());
+    Ok(())
+}
+
+/// Lints ENRs, failing if any has errors. With `--strict`, warnings count as errors.
+pub fn lint(matches: &ArgMatches) -> Result<()> {
+    let strict = matches.is_present("strict");
+    let mut failed = 0;
+
+    let enrs: Vec<&str> = matches.values_of("enr").into_iter().flatten().collect();
+    for enr_string in &enrs {
+        let enr = match enr_string.parse::<Enr>() {
+            Ok(enr) => enr,
+            Err(e) => {
+                error!("{}: Invalid ENR. Error: {}", enr_string, e);
+                failed += 1;
+                continue;
+            }
+        };
+
+        let findings = lint::check(&enr);
+        for finding in &findings {
+            match finding.severity {
+                lint::Severity::Error => error!("{}: {}", enr.node_id(), finding.message),
+                lint::Severity::Warning => warn!("{}: {}", enr.node_id(), finding.message),
+            }
+        }
+        let failing = findings
+            .iter()
+            .any(|finding| strict || finding.severity == lint::Severity::Error);
+        if failing {
+            failed += 1;
+        } else {
+            info!("{}: OK", enr.node_id());
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::CheckFailed(format!(
+            "{} of {} ENRs failed the lint",
+            failed,
+            enrs.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Obtains the ENR of a node given as an ENR or an enode URL. The ENR of an enode is requested
+/// from the node itself over discv5, so the server must be started. Nodes that only speak discv4
+/// do not answer and the request times out.
+pub async fn resolve(discv5: &mut Discv5, node: EnrOrEnode) -> Result<Enr> {
+    match node {
+        EnrOrEnode::Enr(enr) => Ok(enr),
+        EnrOrEnode::Enode(enode) => {
+            info!("Requesting ENR for enode: {}", enode);
+            let multiaddr = enode.multiaddr()?;
+            let enr = discv5
+                .request_enr(multiaddr.to_string())
+                .await
+                .map_err(|e| Error::request("Failed to obtain the ENR of the enode", e))?;
+            match enr.try_multiaddr() {
+                Ok(multiaddrs) => info!(
+                    "Obtained ENR: {}, multiaddrs: {:?}",
+                    enr.to_base64(),
+                    multiaddrs
+                ),
+                Err(e) => warn!(
+                    "Obtained ENR: {}, but failed to build its multiaddrs. Error: {}",
+                    enr.to_base64(),
+                    e
+                ),
+            }
+            Ok(enr)
+        }
+    }
+}
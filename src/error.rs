@@ -0,0 +1,69 @@
+//! The error type of the CLI and the exit codes it maps to.
+//!
+//! The codes and their meaning are listed in the "Exit codes" section of the README.
+
+use discv5::RequestError;
+
+pub const EXIT_CHECK_FAILED: i32 = 1;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_PARSE: i32 = 3;
+pub const EXIT_IO: i32 = 4;
+pub const EXIT_TIMEOUT: i32 = 5;
+pub const EXIT_PROTOCOL: i32 = 6;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// An error of any subcommand. Each variant carries a human-readable message.
+#[derive(Debug)]
+pub enum Error {
+    /// A check found problems, which have already been reported.
+    CheckFailed(String),
+    /// The arguments were invalid in combination or a subcommand was missing.
+    Usage(String),
+    /// An argument or input could not be parsed.
+    Parse(String),
+    /// A file or socket operation failed.
+    Io(String),
+    /// A peer did not respond in time.
+    Timeout(String),
+    /// A peer or packet violated the protocol, a request was rejected or a record could not be
+    /// signed.
+    Protocol(String),
+}
+
+impl Error {
+    /// The exit code of the process when it fails with this error.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::CheckFailed(_) => EXIT_CHECK_FAILED,
+            Error::Usage(_) => EXIT_USAGE,
+            Error::Parse(_) => EXIT_PARSE,
+            Error::Io(_) => EXIT_IO,
+            Error::Timeout(_) => EXIT_TIMEOUT,
+            Error::Protocol(_) => EXIT_PROTOCOL,
+        }
+    }
+
+    /// Classifies a failed discv5 request, prefixing the message with `context`.
+    pub fn request(context: &str, error: RequestError) -> Self {
+        match error {
+            RequestError::Timeout => Error::Timeout(format!("{}: the request timed out", context)),
+            e => Error::Protocol(format!("{}: {}", context, e)),
+        }
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::CheckFailed(message)
+            | Error::Usage(message)
+            | Error::Parse(message)
+            | Error::Io(message)
+            | Error::Timeout(message)
+            | Error::Protocol(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,164 @@
+//! Sends mutated discv5 packets to a target node and checks it stays responsive.
+//!
+//! Packets are encoded by discv5 and then have header fields, authdata, nonces, lengths or
+//! message bodies mutated. After every batch the target's ENR is requested over a normal session,
+//! as discv5 has no public PING. If the target stops answering, the batch sent since the last
+//! successful check is saved, one hex file per packet, so the failure can be replayed.
+
+use crate::cli;
+use crate::error::{Error, Result};
+use crate::request_enr::enr_ext::EnrExt;
+use crate::server;
+use clap::ArgMatches;
+use discv5::{enr::CombinedKey, Discv5, Discv5ConfigBuilder};
+use log::{error, info, warn};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+mod mutator;
+use mutator::{FuzzInput, Mutator};
+
+type Enr = discv5::enr::Enr<CombinedKey>;
+
+/// The number of liveness requests that must fail before the target is considered down.
+const LIVENESS_ATTEMPTS: usize = 2;
+
+/// Runs the fuzzer based on the CLI options.
+pub async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let target: Enr = cli::value(matches, "enr")?;
+    let listen_address: IpAddr = cli::value(matches, "listen-address")?;
+    let listen_port: u16 = cli::value(matches, "listen-port")?;
+    let packets: u64 = cli::value(matches, "packets")?;
+    let check_interval: usize = cli::value(matches, "check-interval")?;
+    let delay = Duration::from_millis(cli::value(matches, "delay")?);
+    let liveness_timeout = Duration::from_secs(cli::value(matches, "liveness-timeout")?);
+    let output = PathBuf::from(cli::value::<String>(matches, "output")?);
+    let seed = cli::optional_value(matches, "seed")?.unwrap_or_else(rand::random::<u64>);
+
+    if check_interval == 0 {
+        return Err(Error::Usage("The check interval must be at least 1".into()));
+    }
+
+    let target_socket = target
+        .udp4_socket()
+        .map(SocketAddr::V4)
+        .or_else(|| target.udp6_socket().map(SocketAddr::V6))
+        .ok_or_else(|| Error::Usage("The target ENR has no UDP address".into()))?;
+    let target_multiaddr = target
+        .discovery_multiaddrs()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| Error::Usage("The target ENR has no discovery multiaddr".into()))?
+        .to_string();
+
+    // a normal node to check the target is still alive
+    let enr_key = CombinedKey::generate_secp256k1();
+    let enr = server::EnrConfig::default().build(&enr_key)?;
+    let config = Discv5ConfigBuilder::new().build();
+    let mut discv5 = Discv5::new(enr, enr_key, config)
+        .map_err(|e| Error::Protocol(format!("Could not create the server: {}", e)))?;
+    server::start(&mut discv5, SocketAddr::new(listen_address, listen_port)).await?;
+
+    // the fuzzed packets come from their own socket and identity, so they cannot disturb the
+    // session of the liveness checks
+    let fuzz_address = match target_socket {
+        SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    let socket = UdpSocket::bind(SocketAddr::new(fuzz_address, 0))
+        .await
+        .map_err(|e| Error::Io(format!("Could not bind the fuzzing socket: {}", e)))?;
+    let mut rng = ChaCha8Rng::seed_from_u64(seed);
+    let fuzz_key = loop {
+        // 32 random bytes are a valid key with overwhelming probability
+        if let Ok(key) = server::key_from_bytes(&rng.gen::<[u8; 32]>()) {
+            break key;
+        }
+    };
+    let fuzz_enr = server::EnrConfig::default().build(&fuzz_key)?;
+    let mut mutator = Mutator::new(rng, fuzz_enr, target.node_id());
+
+    info!(
+        "Fuzzing node {} at {}. Seed: {}",
+        target.node_id(),
+        target_socket,
+        seed
+    );
+    if !is_alive(&mut discv5, &target_multiaddr, liveness_timeout).await {
+        return Err(Error::Timeout(
+            "The target did not respond before fuzzing started".into(),
+        ));
+    }
+
+    let mut batch: Vec<FuzzInput> = Vec::with_capacity(check_interval);
+    let mut sent = 0;
+    while packets == 0 || sent < packets {
+        let input = mutator.next_input();
+        socket
+            .send_to(&input.bytes, target_socket)
+            .await
+            .map_err(|e| Error::Io(format!("Could not send a packet: {}", e)))?;
+        sent += 1;
+        batch.push(input);
+
+        let last_packet = packets != 0 && sent == packets;
+        if batch.len() == check_interval || last_packet {
+            if !is_alive(&mut discv5, &target_multiaddr, liveness_timeout).await {
+                save_inputs(&output, &batch)?;
+                return Err(Error::CheckFailed(format!(
+                    "The target stopped responding after {} packets. The last {} were saved to {}",
+                    sent,
+                    batch.len(),
+                    output.display()
+                )));
+            }
+            info!("Sent {} packets. The target is alive", sent);
+            batch.clear();
+        }
+        tokio::time::sleep(delay).await;
+    }
+
+    info!("Fuzzing completed. Sent {} packets", sent);
+    Ok(())
+}
+
+/// Requests the target's ENR, retrying once, and returns whether it answered.
+async fn is_alive(discv5: &mut Discv5, multiaddr: &str, timeout: Duration) -> bool {
+    for attempt in 1..=LIVENESS_ATTEMPTS {
+        match tokio::time::timeout(timeout, discv5.request_enr(multiaddr.to_string())).await {
+            Ok(Ok(_)) => return true,
+            Ok(Err(e)) => warn!("Liveness check {} failed: {}", attempt, e),
+            Err(_) => warn!("Liveness check {} timed out", attempt),
+        }
+    }
+    false
+}
+
+/// Writes each input as hex to its own file, named after its index, packet kind and mutation.
+fn save_inputs(output: &Path, inputs: &[FuzzInput]) -> Result<()> {
+    std::fs::create_dir_all(output).map_err(|e| {
+        Error::Io(format!(
+            "Could not create the directory {}: {}",
+            output.display(),
+            e
+        ))
+    })?;
+    for input in inputs {
+        let path = output.join(format!(
+            "{:08}-{}-{}.hex",
+            input.index, input.kind, input.mutation
+        ));
+        std::fs::write(&path, hex::encode(&input.bytes))
+            .map_err(|e| Error::Io(format!("Could not write to {}: {}", path.display(), e)))?;
+    }
+    error!(
+        "Saved {} packets preceding the failure to {}",
+        inputs.len(),
+        output.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,359 @@
+//! Generates valid discv5 packets addressed to the target and mutates them.
+
+use crate::packet::{
+    self, AUTHDATA_SIZE_OFFSET, FLAG_OFFSET, MAX_PACKET_SIZE, NONCE_OFFSET, PROTOCOL_ID,
+    VERSION_OFFSET,
+};
+use crate::server;
+use discv5::enr::{CombinedKey, EnrKey, EnrPublicKey, NodeId};
+use discv5::packet::{Packet, PacketHeader, PacketKind, IV_LENGTH, STATIC_HEADER_LENGTH};
+use rand::{seq::SliceRandom, Rng};
+use std::ops::RangeInclusive;
+
+type Enr = discv5::enr::Enr<CombinedKey>;
+
+/// The lengths of the random ciphertexts of message and handshake packets.
+const BODY_LENGTH: RangeInclusive<usize> = 16..=256;
+
+/// A change made to an encoded packet.
+#[derive(Debug, Clone, Copy)]
+pub enum Mutation {
+    /// The packet is sent as encoded.
+    None,
+    /// A byte of the protocol id is replaced.
+    ProtocolId,
+    /// The version is replaced.
+    Version,
+    /// The flag is replaced by another, possibly valid, flag.
+    Flag,
+    /// The message nonce is replaced.
+    Nonce,
+    /// The authdata size is changed so it no longer matches the authdata.
+    AuthdataSize,
+    /// Bytes of the authdata are replaced.
+    Authdata,
+    /// The packet is cut short.
+    Truncate,
+    /// Random bytes are appended, possibly beyond the maximum packet size.
+    Extend,
+    /// The message body is replaced with random bytes of a random length.
+    Body,
+    /// Random bits of the masked packet are flipped.
+    BitFlips,
+}
+
+const MUTATIONS: [Mutation; 11] = [
+    Mutation::None,
+    Mutation::ProtocolId,
+    Mutation::Version,
+    Mutation::Flag,
+    Mutation::Nonce,
+    Mutation::AuthdataSize,
+    Mutation::Authdata,
+    Mutation::Truncate,
+    Mutation::Extend,
+    Mutation::Body,
+    Mutation::BitFlips,
+];
+
+impl std::fmt::Display for Mutation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Mutation::None => "none",
+            Mutation::ProtocolId => "protocol-id",
+            Mutation::Version => "version",
+            Mutation::Flag => "flag",
+            Mutation::Nonce => "nonce",
+            Mutation::AuthdataSize => "authdata-size",
+            Mutation::Authdata => "authdata",
+            Mutation::Truncate => "truncate",
+            Mutation::Extend => "extend",
+            Mutation::Body => "body",
+            Mutation::BitFlips => "bit-flips",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// A packet sent to the target.
+pub struct FuzzInput {
+    /// The position of the packet in the run, starting at 1.
+    pub index: u64,
+    /// The kind of packet that was encoded before mutating it.
+    pub kind: &'static str,
+    pub mutation: Mutation,
+    pub bytes: Vec<u8>,
+}
+
+/// Produces mutated packets from a seeded RNG, so a run can be repeated from its seed. Only the
+/// signature of the ENR in handshake packets differs, as ENRs are signed with OS randomness.
+pub struct Mutator<R> {
+    rng: R,
+    /// The ENR sent in handshake packets.
+    enr: Enr,
+    /// The node id of the target, which masks packet headers.
+    dst_id: NodeId,
+    generated: u64,
+}
+
+impl<R: Rng> Mutator<R> {
+    pub fn new(rng: R, enr: Enr, dst_id: NodeId) -> Self {
+        Mutator {
+            rng,
+            enr,
+            dst_id,
+            generated: 0,
+        }
+    }
+
+    /// Encodes a random packet and applies a random mutation to it.
+    pub fn next_input(&mut self) -> FuzzInput {
+        let (kind, packet) = self.generate();
+        let mut bytes = packet.encode(&self.dst_id);
+        let mutation = *MUTATIONS
+            .choose(&mut self.rng)
+            .expect("There are mutations to choose from");
+        self.mutate(mutation, &mut bytes);
+
+        self.generated += 1;
+        FuzzInput {
+            index: self.generated,
+            kind,
+            mutation,
+            bytes,
+        }
+    }
+
+    /// Builds a valid message, WHOAREYOU or handshake packet. Without a session the ciphertexts
+    /// and signatures are random, but every header field is well-formed.
+    fn generate(&mut self) -> (&'static str, Packet) {
+        let mut message_nonce = [0u8; 12];
+        self.rng.fill(&mut message_nonce);
+        let src_id = self.enr.node_id();
+
+        let (name, kind, message) = match self.rng.gen_range(0..3) {
+            0 => (
+                "message",
+                PacketKind::Message { src_id },
+                self.random_bytes(BODY_LENGTH),
+            ),
+            1 => {
+                let mut id_nonce = [0u8; 16];
+                self.rng.fill(&mut id_nonce);
+                let kind = PacketKind::WhoAreYou {
+                    id_nonce,
+                    enr_seq: self.rng.gen(),
+                };
+                ("whoareyou", kind, Vec::new())
+            }
+            _ => {
+                let enr_record = if self.rng.gen_bool(0.5) {
+                    Some(self.enr.clone())
+                } else {
+                    None
+                };
+                let kind = PacketKind::Handshake {
+                    src_id,
+                    id_nonce_sig: self.random_bytes(64..=64),
+                    ephem_pubkey: self.ephemeral_public_key(),
+                    enr_record,
+                };
+                ("handshake", kind, self.random_bytes(BODY_LENGTH))
+            }
+        };
+
+        let packet = Packet {
+            iv: self.rng.gen(),
+            header: PacketHeader {
+                message_nonce,
+                kind,
+            },
+            message,
+        };
+        (name, packet)
+    }
+
+    fn mutate(&mut self, mutation: Mutation, bytes: &mut Vec<u8>) {
+        match mutation {
+            Mutation::None => {}
+            Mutation::Truncate => {
+                let length = self.rng.gen_range(0..bytes.len());
+                bytes.truncate(length);
+            }
+            Mutation::Extend => {
+                let max_extra = MAX_PACKET_SIZE.saturating_sub(bytes.len()) + 64;
+                bytes.extend(self.random_bytes(1..=max_extra));
+            }
+            Mutation::BitFlips => {
+                for _ in 0..self.rng.gen_range(1..=8) {
+                    let bit = self.rng.gen_range(0..bytes.len() * 8);
+                    bytes[bit / 8] ^= 1 << (bit % 8);
+                }
+            }
+            Mutation::Body => {
+                if let Some(header_length) = self.header_length(bytes) {
+                    bytes.truncate(IV_LENGTH + header_length);
+                    let max_length = MAX_PACKET_SIZE.saturating_sub(bytes.len());
+                    bytes.extend(self.random_bytes(0..=max_length));
+                }
+            }
+            header_mutation => self.mutate_header(header_mutation, bytes),
+        }
+    }
+
+    /// Changes a field of the unmasked header and masks it again.
+    fn mutate_header(&mut self, mutation: Mutation, bytes: &mut [u8]) {
+        let (unmasked, header_length) = packet::unmask_header(&self.dst_id, bytes);
+        let header_length = match header_length {
+            Some(header_length) if IV_LENGTH + header_length <= bytes.len() => header_length,
+            _ => return,
+        };
+        bytes.copy_from_slice(&unmasked);
+        let header = &mut bytes[IV_LENGTH..IV_LENGTH + header_length];
+
+        match mutation {
+            Mutation::ProtocolId => {
+                let index = self.rng.gen_range(0..PROTOCOL_ID.len());
+                header[index] = self.rng.gen();
+            }
+            Mutation::Version => {
+                let version: u16 = self.rng.gen();
+                header[VERSION_OFFSET..FLAG_OFFSET].copy_from_slice(&version.to_be_bytes());
+            }
+            Mutation::Flag => {
+                // valid flags with the wrong authdata are as interesting as unknown flags
+                header[FLAG_OFFSET] = if self.rng.gen_bool(0.5) {
+                    self.rng.gen_range(0..3)
+                } else {
+                    self.rng.gen()
+                };
+            }
+            Mutation::Nonce => self
+                .rng
+                .fill(&mut header[NONCE_OFFSET..AUTHDATA_SIZE_OFFSET]),
+            Mutation::AuthdataSize => {
+                let size = (header_length - STATIC_HEADER_LENGTH) as u16;
+                let new_size = *[0, size.wrapping_sub(1), size.wrapping_add(1), u16::MAX]
+                    .choose(&mut self.rng)
+                    .expect("There are sizes to choose from");
+                header[AUTHDATA_SIZE_OFFSET..STATIC_HEADER_LENGTH]
+                    .copy_from_slice(&new_size.to_be_bytes());
+            }
+            Mutation::Authdata if header_length > STATIC_HEADER_LENGTH => {
+                for _ in 0..self.rng.gen_range(1..=4) {
+                    let index = self.rng.gen_range(STATIC_HEADER_LENGTH..header_length);
+                    header[index] = self.rng.gen();
+                }
+            }
+            _ => {}
+        }
+
+        let mut iv = [0u8; IV_LENGTH];
+        iv.copy_from_slice(&bytes[..IV_LENGTH]);
+        packet::apply_header_mask(
+            &self.dst_id,
+            &iv,
+            &mut bytes[IV_LENGTH..IV_LENGTH + header_length],
+        );
+    }
+
+    /// The length of the header of an encoded packet.
+    fn header_length(&self, bytes: &[u8]) -> Option<usize> {
+        packet::unmask_header(&self.dst_id, bytes)
+            .1
+            .filter(|header_length| IV_LENGTH + header_length <= bytes.len())
+    }
+
+    /// A compressed secp256k1 public key derived from the RNG.
+    fn ephemeral_public_key(&mut self) -> Vec<u8> {
+        let mut secret = [0u8; 32];
+        self.rng.fill(&mut secret);
+        match server::key_from_bytes(&secret) {
+            Ok(key) => key.public().encode(),
+            // a random secret is out of range with negligible probability
+            Err(_) => self.random_bytes(33..=33),
+        }
+    }
+
+    fn random_bytes(&mut self, length: RangeInclusive<usize>) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.rng.gen_range(length)];
+        self.rng.fill(&mut bytes[..]);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    /// The ENR of the mutators. ENR signatures are not derived from the seed, so mutators that
+    /// are compared share one.
+    fn enr() -> Enr {
+        let key = server::key_from_bytes(&[1; 32]).unwrap();
+        server::EnrConfig::default().build(&key).unwrap()
+    }
+
+    fn mutator(enr: &Enr, seed: u64) -> Mutator<ChaCha8Rng> {
+        Mutator::new(
+            ChaCha8Rng::seed_from_u64(seed),
+            enr.clone(),
+            NodeId::new(&[0xbb; 32]),
+        )
+    }
+
+    fn inputs(enr: &Enr, seed: u64) -> Vec<(u64, &'static str, String, Vec<u8>)> {
+        let mut mutator = mutator(enr, seed);
+        (0..100)
+            .map(|_| {
+                let input = mutator.next_input();
+                (
+                    input.index,
+                    input.kind,
+                    input.mutation.to_string(),
+                    input.bytes,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn repeats_runs_from_their_seed() {
+        let enr = enr();
+        assert_eq!(inputs(&enr, 7), inputs(&enr, 7));
+        assert_ne!(inputs(&enr, 7), inputs(&enr, 8));
+    }
+
+    #[test]
+    fn keeps_the_header_length_unless_mutating_it() {
+        let mut mutator = mutator(&enr(), 7);
+        let keeping = [
+            Mutation::None,
+            Mutation::ProtocolId,
+            Mutation::Version,
+            Mutation::Flag,
+            Mutation::Nonce,
+            Mutation::Authdata,
+            Mutation::Extend,
+            Mutation::Body,
+        ];
+        for _ in 0..50 {
+            for mutation in keeping {
+                let (kind, packet) = mutator.generate();
+                let mut bytes = packet.encode(&mutator.dst_id);
+                let header_length = mutator.header_length(&bytes);
+                assert!(header_length.is_some(), "{} packet", kind);
+
+                mutator.mutate(mutation, &mut bytes);
+                assert_eq!(
+                    mutator.header_length(&bytes),
+                    header_length,
+                    "{} mutation of a {} packet",
+                    mutation,
+                    kind
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,341 @@
+//! Walks through a discv5 handshake between two local keys, printing every intermediate value.
+
+use crate::cli::{self, hex0x};
+use crate::crypto;
+use crate::error::{Error, Result};
+use crate::packet;
+use crate::server;
+use clap::ArgMatches;
+use discv5::enr::NodeId;
+use discv5::packet::{Packet, PacketHeader, PacketKind};
+use log::info;
+
+/// The request id of the PING sent in the handshake.
+const PING_REQUEST_ID: [u8; 4] = [0, 0, 0, 1];
+
+/// Simulates the handshake of an initiator that sends a message to a recipient it has no session
+/// with, based on the CLI options.
+pub fn simulate(matches: &ArgMatches) -> Result<()> {
+    let initiator_raw = cli::hex_value(matches, "initiator-key")?
+        .ok_or_else(|| Error::Usage("An initiator key must be supplied".into()))?;
+    let recipient_raw = cli::hex_value(matches, "recipient-key")?
+        .ok_or_else(|| Error::Usage("A recipient key must be supplied".into()))?;
+    let known_enr_seq: u64 = cli::value(matches, "known-enr-seq")?;
+
+    let initiator_key = crypto::secret_key(&initiator_raw)?;
+    let recipient_key = crypto::secret_key(&recipient_raw)?;
+    let ephemeral_key = match cli::hex_value(matches, "ephemeral-key")? {
+        Some(raw_key) => crypto::secret_key(&raw_key)?,
+        None => crypto::random_secret_key(),
+    };
+
+    let initiator_enr =
+        server::EnrConfig::default().build(&server::key_from_bytes(&initiator_raw)?)?;
+    let recipient_enr =
+        server::EnrConfig::default().build(&server::key_from_bytes(&recipient_raw)?)?;
+    let initiator_id = initiator_enr.node_id();
+    let recipient_id = recipient_enr.node_id();
+    let initiator_public = secp256k1::PublicKey::from_secret_key(&initiator_key);
+    let recipient_public = secp256k1::PublicKey::from_secret_key(&recipient_key);
+
+    info!("Initiator node id: {}", hex0x(&initiator_id.raw()));
+    info!(
+        "Initiator public key: {}",
+        hex0x(&initiator_public.serialize_compressed())
+    );
+    info!("Initiator ENR: {}", initiator_enr.to_base64());
+    info!("Recipient node id: {}", hex0x(&recipient_id.raw()));
+    info!(
+        "Recipient public key: {}",
+        hex0x(&recipient_public.serialize_compressed())
+    );
+
+    // 1. without a session the initiator sends a packet with random contents
+    info!("Step 1: The initiator sends a random packet, having no session with the recipient");
+    let random_packet = Packet::new_random(&initiator_id)
+        .map_err(|e| Error::Protocol(format!("Could not create a random packet: {}", e)))?;
+    let request_nonce = random_packet.header.message_nonce;
+    info!("  message-nonce = {}", hex0x(&request_nonce));
+    info!("  random-ciphertext = {}", hex0x(&random_packet.message));
+    info!("  packet = {}", hex0x(&random_packet.encode(&recipient_id)));
+
+    // 2. the recipient cannot decrypt it and challenges the initiator
+    info!("Step 2: The recipient cannot decrypt the message and answers with WHOAREYOU");
+    let whoareyou = Packet {
+        iv: rand::random(),
+        header: PacketHeader {
+            message_nonce: request_nonce,
+            kind: PacketKind::WhoAreYou {
+                id_nonce: rand::random(),
+                enr_seq: known_enr_seq,
+            },
+        },
+        message: Vec::new(),
+    };
+    let challenge_data = whoareyou.authenticated_data();
+    if let PacketKind::WhoAreYou { id_nonce, enr_seq } = &whoareyou.header.kind {
+        info!("  whoareyou.request-nonce = {}", hex0x(&request_nonce));
+        info!("  whoareyou.id-nonce = {}", hex0x(id_nonce));
+        info!("  whoareyou.enr-seq = {}", enr_seq);
+    }
+    info!("  challenge-data = {}", hex0x(&challenge_data));
+    info!("  packet = {}", hex0x(&whoareyou.encode(&initiator_id)));
+
+    // 3. the initiator agrees on a secret with an ephemeral key
+    info!(
+        "Step 3: The initiator performs ECDH with an ephemeral key and the recipient's public key"
+    );
+    let ephemeral_pubkey =
+        secp256k1::PublicKey::from_secret_key(&ephemeral_key).serialize_compressed();
+    let shared_secret = crypto::ecdh(&recipient_public, &ephemeral_key)?;
+    info!("  ephemeral-key = {}", hex0x(&ephemeral_key.serialize()));
+    info!("  ephemeral-pubkey = {}", hex0x(&ephemeral_pubkey));
+    info!("  shared-secret = {}", hex0x(&shared_secret));
+
+    // 4. both session keys are derived from the shared secret
+    info!("Step 4: The initiator derives the session keys with HKDF");
+    let keys = crypto::derive_keys(
+        &recipient_public,
+        &ephemeral_key,
+        &challenge_data,
+        &initiator_id,
+        &recipient_id,
+    )?;
+    let mut info_bytes = crypto::KEY_AGREEMENT_STRING.to_vec();
+    info_bytes.extend_from_slice(&initiator_id.raw());
+    info_bytes.extend_from_slice(&recipient_id.raw());
+    info!("  hkdf-salt = {}", hex0x(&challenge_data));
+    info!("  hkdf-info = {}", hex0x(&info_bytes));
+    info!("  initiator-key = {}", hex0x(&keys.initiator_key));
+    info!("  recipient-key = {}", hex0x(&keys.recipient_key));
+
+    // 5. the initiator proves its identity
+    info!("Step 5: The initiator signs the challenge with its static key");
+    let id_signature = crypto::id_sign(
+        &initiator_key,
+        &challenge_data,
+        &ephemeral_pubkey,
+        &recipient_id,
+    );
+    info!("  id-signature = {}", hex0x(&id_signature));
+
+    // 6. the handshake packet carries the first real message
+    info!("Step 6: The initiator sends the handshake packet with an encrypted PING");
+    let enr_record = if known_enr_seq < initiator_enr.seq() {
+        info!(
+            "  The recipient knows ENR sequence number {} but the initiator's is {}, so the ENR is included",
+            known_enr_seq,
+            initiator_enr.seq()
+        );
+        Some(initiator_enr.clone())
+    } else {
+        None
+    };
+    let plaintext = packet::encode_ping(&PING_REQUEST_ID, initiator_enr.seq());
+    let handshake = handshake_packet(
+        rand::random(),
+        rand::random(),
+        PacketKind::Handshake {
+            src_id: initiator_id,
+            id_nonce_sig: id_signature.to_vec(),
+            ephem_pubkey: ephemeral_pubkey.to_vec(),
+            enr_record,
+        },
+        &keys.initiator_key,
+        &plaintext,
+    )?;
+    let authenticated_data = handshake.authenticated_data();
+    info!(
+        "  message-nonce = {}",
+        hex0x(&handshake.header.message_nonce)
+    );
+    info!("  message-plaintext = {}", hex0x(&plaintext));
+    info!("  message-ad = {}", hex0x(&authenticated_data));
+    info!("  message-ciphertext = {}", hex0x(&handshake.message));
+    let handshake_bytes = handshake.encode(&recipient_id);
+    info!("  packet = {}", hex0x(&handshake_bytes));
+
+    // 7. the recipient repeats the computations from its side
+    info!("Step 7: The recipient decodes the handshake, derives the same keys and checks the signature");
+    recipient_checks(
+        &handshake_bytes,
+        &recipient_key,
+        &initiator_public,
+        &challenge_data,
+        &initiator_id,
+        &recipient_id,
+        &keys,
+    )?;
+    info!("Handshake complete. Both nodes share the session keys");
+    Ok(())
+}
+
+/// Builds the handshake packet of the initiator, encrypting `plaintext` with its session key and
+/// authenticating the header.
+fn handshake_packet(
+    iv: u128,
+    message_nonce: [u8; crypto::NONCE_LENGTH],
+    kind: PacketKind,
+    initiator_key: &[u8; crypto::KEY_LENGTH],
+    plaintext: &[u8],
+) -> Result<Packet> {
+    let mut handshake = Packet {
+        iv,
+        header: PacketHeader {
+            message_nonce,
+            kind,
+        },
+        message: Vec::new(),
+    };
+    handshake.message = crypto::encrypt(
+        initiator_key,
+        &handshake.header.message_nonce,
+        plaintext,
+        &handshake.authenticated_data(),
+    )?;
+    Ok(handshake)
+}
+
+/// Decodes the handshake packet as the recipient and checks that it can authenticate the
+/// initiator and read its message.
+fn recipient_checks(
+    handshake_bytes: &[u8],
+    recipient_key: &secp256k1::SecretKey,
+    initiator_public: &secp256k1::PublicKey,
+    challenge_data: &[u8],
+    initiator_id: &NodeId,
+    recipient_id: &NodeId,
+    initiator_keys: &crypto::SessionKeys,
+) -> Result<()> {
+    let (decoded, authenticated_data) = packet::decode_packet(recipient_id, handshake_bytes)?;
+    let (id_signature, ephemeral_pubkey) = match &decoded.header.kind {
+        PacketKind::Handshake {
+            id_nonce_sig,
+            ephem_pubkey,
+            ..
+        } => (id_nonce_sig, ephem_pubkey),
+        _ => {
+            return Err(Error::Protocol(
+                "The handshake packet did not decode as a handshake".into(),
+            ))
+        }
+    };
+
+    let keys = crypto::derive_keys(
+        &crypto::public_key(ephemeral_pubkey)?,
+        recipient_key,
+        challenge_data,
+        initiator_id,
+        recipient_id,
+    )?;
+    info!(
+        "  recipient-derived initiator-key = {}",
+        hex0x(&keys.initiator_key)
+    );
+    info!(
+        "  recipient-derived recipient-key = {}",
+        hex0x(&keys.recipient_key)
+    );
+    if keys != *initiator_keys {
+        return Err(Error::Protocol(
+            "The recipient derived different session keys".into(),
+        ));
+    }
+
+    if !crypto::id_verify(
+        initiator_public,
+        id_signature,
+        challenge_data,
+        ephemeral_pubkey,
+        recipient_id,
+    )? {
+        return Err(Error::Protocol("The id-signature is invalid".into()));
+    }
+    info!("  id-signature valid: true");
+
+    let plaintext = crypto::decrypt(
+        &keys.initiator_key,
+        &decoded.header.message_nonce,
+        &decoded.message,
+        &authenticated_data,
+    )?;
+    info!("  decrypted-plaintext = {}", hex0x(&plaintext));
+    info!(
+        "  decrypted-message = {}",
+        packet::describe_message(&plaintext)
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::vectors::{
+        CHALLENGE_DATA_SEQ_1, HANDSHAKE_EPHEMERAL_KEY, HANDSHAKE_PACKET, NODE_A_ID, NODE_A_KEY,
+        NODE_B_ID, NODE_B_KEY,
+    };
+
+    fn bytes(hex_value: &str) -> Vec<u8> {
+        hex::decode(hex_value).unwrap()
+    }
+
+    fn node_id(hex_value: &str) -> NodeId {
+        NodeId::parse(&bytes(hex_value)).unwrap()
+    }
+
+    /// Builds the spec's "Ping handshake message packet" from its inputs and checks that the
+    /// recipient accepts it.
+    #[test]
+    fn builds_the_spec_handshake() {
+        let initiator_key = crypto::secret_key(&bytes(NODE_A_KEY)).unwrap();
+        let recipient_key = crypto::secret_key(&bytes(NODE_B_KEY)).unwrap();
+        let ephemeral_key = crypto::secret_key(&bytes(HANDSHAKE_EPHEMERAL_KEY)).unwrap();
+        let initiator_public = secp256k1::PublicKey::from_secret_key(&initiator_key);
+        let recipient_public = secp256k1::PublicKey::from_secret_key(&recipient_key);
+        let ephemeral_pubkey =
+            secp256k1::PublicKey::from_secret_key(&ephemeral_key).serialize_compressed();
+        let challenge_data = bytes(CHALLENGE_DATA_SEQ_1);
+        let (initiator_id, recipient_id) = (node_id(NODE_A_ID), node_id(NODE_B_ID));
+
+        let keys = crypto::derive_keys(
+            &recipient_public,
+            &ephemeral_key,
+            &challenge_data,
+            &initiator_id,
+            &recipient_id,
+        )
+        .unwrap();
+        let id_signature = crypto::id_sign(
+            &initiator_key,
+            &challenge_data,
+            &ephemeral_pubkey,
+            &recipient_id,
+        );
+        let handshake = handshake_packet(
+            0,
+            [0xff; crypto::NONCE_LENGTH],
+            PacketKind::Handshake {
+                src_id: initiator_id,
+                id_nonce_sig: id_signature.to_vec(),
+                ephem_pubkey: ephemeral_pubkey.to_vec(),
+                enr_record: None,
+            },
+            &keys.initiator_key,
+            &packet::encode_ping(&PING_REQUEST_ID, 1),
+        )
+        .unwrap();
+        let handshake_bytes = handshake.encode(&recipient_id);
+
+        assert_eq!(hex0x(&handshake_bytes), format!("0x{}", HANDSHAKE_PACKET));
+        recipient_checks(
+            &handshake_bytes,
+            &recipient_key,
+            &initiator_public,
+            &challenge_data,
+            &initiator_id,
+            &recipient_id,
+            &keys,
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,32 @@
+//! Tools for creating and debugging discovery v5 servers, packets and ENRs.
+//!
+//! The `discv5-cli` binary is a thin wrapper around this library. Each subcommand has a module,
+//! and the parts that are useful on their own are exported here:
+//!
+//! - [`ServerConfig`] and [`server::run`] run a discv5 server that regularly performs queries.
+//!   [`server::run_with_shutdown`] stops it when a future completes instead and installs no signal
+//!   handlers.
+//! - [`EnrConfig`] and [`key_from_bytes`] build and sign ENRs.
+//! - [`decode_packet`] decodes discv5 packets.
+//! - [`EnrExt`], [`CombinedKeyExt`] and [`CombinedKeyPublicExt`] convert between ENRs and libp2p
+//!   types.
+
+pub mod cli;
+pub mod crypto;
+pub mod dnstree;
+pub mod enr;
+pub mod error;
+pub mod fuzz;
+pub mod handshake;
+pub mod lookup;
+pub mod packet;
+pub mod request_enr;
+pub mod search;
+pub mod server;
+pub mod simulation;
+pub mod topology;
+
+pub use error::{Error, Result};
+pub use packet::decode_packet;
+pub use request_enr::enr_ext::{CombinedKeyExt, CombinedKeyPublicExt, EnrExt};
+pub use server::{key_from_bytes, EnrConfig, ServerConfig};
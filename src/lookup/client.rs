@@ -0,0 +1,372 @@
+//! A minimal discv5 client that sends `FINDNODE` requests from a throwaway identity.
+//!
+//! `Discv5::find_node` hides which peer returned which node, so the client performs the handshake
+//! itself: a random packet, the WHOAREYOU challenge, and a handshake packet carrying the
+//! `FINDNODE` request. Every request uses a new socket and session, which the responder treats as
+//! a new, unreachable node since the client's ENR has no address.
+
+use super::{Lookup, LookupStats};
+use crate::crypto;
+use crate::error::{Error, Result};
+use crate::server;
+use discv5::enr::{self, CombinedKey, EnrPublicKey, NodeId};
+use discv5::packet::{Packet, PacketHeader, PacketKind};
+use discv5::rpc::{Message, Request, RequestBody, RequestId, ResponseBody};
+use discv5::Key;
+use futures::stream::{FuturesUnordered, StreamExt};
+use log::debug;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The number of log2-distances requested from each peer, as in discv5.
+const DISTANCES_PER_REQUEST: usize = 3;
+/// The largest log2-distance between two node ids.
+const MAX_LOG2_DISTANCE: u64 = 256;
+/// The largest datagram the client reads.
+const MAX_DATAGRAM_SIZE: usize = 1280;
+
+/// The nodes a lookup found and which peer returned which node.
+#[derive(Debug, Clone)]
+pub struct Found {
+    /// The closest nodes that responded, closest first.
+    pub enrs: Vec<Enr>,
+    /// `(A, B)` for every node B that peer A returned in a `NODES` response.
+    pub edges: Vec<(NodeId, NodeId)>,
+    /// The peers that answered a request.
+    pub responded: Vec<NodeId>,
+    pub stats: LookupStats,
+}
+
+/// Sends `FINDNODE` requests and runs lookups over UDP.
+pub struct LookupClient {
+    key: secp256k1::SecretKey,
+    enr: Enr,
+    /// The number of concurrent requests of a lookup.
+    parallelism: usize,
+    /// The time after which a request without a complete response fails.
+    timeout: Duration,
+}
+
+impl LookupClient {
+    /// Creates a client with a random identity.
+    pub fn new(parallelism: usize, timeout: Duration) -> Result<Self> {
+        let key = crypto::random_secret_key();
+        let enr = server::EnrConfig::default().build(&server::key_from_bytes(&key.serialize())?)?;
+        Ok(LookupClient {
+            key,
+            enr,
+            parallelism,
+            timeout,
+        })
+    }
+
+    /// The node id of the client.
+    pub fn node_id(&self) -> NodeId {
+        self.enr.node_id()
+    }
+
+    /// Looks up the nodes closest to `target`, starting from `peers`.
+    pub async fn lookup(&self, target: NodeId, peers: Vec<Enr>) -> Found {
+        let local_id = self.node_id();
+        let mut lookup = Lookup::new(
+            target,
+            self.parallelism,
+            peers.into_iter().map(|enr| (enr.node_id(), enr)),
+        );
+        let mut edges = Vec::new();
+        let mut responded = Vec::new();
+        let mut requests = FuturesUnordered::new();
+        loop {
+            while let Some((node_id, peer)) = lookup.next_request() {
+                let distances = lookup_distances(&target, &node_id);
+                requests.push(async move {
+                    let result = self.find_node(&peer, distances).await;
+                    (node_id, result)
+                });
+            }
+            let (from, result) = match requests.next().await {
+                Some(response) => response,
+                None => break,
+            };
+            match result {
+                Ok(nodes) => {
+                    responded.push(from);
+                    let nodes: Vec<_> = nodes
+                        .into_iter()
+                        .map(|enr| (enr.node_id(), enr))
+                        .filter(|(node_id, _)| *node_id != from)
+                        .inspect(|(node_id, _)| edges.push((from, *node_id)))
+                        .filter(|(node_id, _)| *node_id != local_id)
+                        .collect();
+                    lookup.on_response(&from, nodes);
+                }
+                Err(e) => {
+                    debug!("FINDNODE to {} failed: {}", from, e);
+                    lookup.on_failure(&from);
+                }
+            }
+        }
+        let (enrs, stats) = lookup.finish();
+        Found {
+            enrs,
+            edges,
+            responded,
+            stats,
+        }
+    }
+
+    /// Requests the nodes at the log2-`distances` from `peer`, establishing a new session.
+    pub async fn find_node(&self, peer: &Enr, distances: Vec<u64>) -> Result<Vec<Enr>> {
+        let socket_addr = peer
+            .udp4_socket()
+            .map(SocketAddr::V4)
+            .or_else(|| peer.udp6_socket().map(SocketAddr::V6))
+            .ok_or_else(|| Error::Protocol(format!("{} has no UDP socket", peer.node_id())))?;
+        tokio::time::timeout(self.timeout, self.request(peer, socket_addr, distances))
+            .await
+            .map_err(|_| Error::Timeout(format!("FINDNODE to {} timed out", peer.node_id())))?
+    }
+
+    async fn request(
+        &self,
+        peer: &Enr,
+        socket_addr: SocketAddr,
+        distances: Vec<u64>,
+    ) -> Result<Vec<Enr>> {
+        let local_id = self.node_id();
+        let remote_id = peer.node_id();
+        let remote_public = crypto::public_key(&peer.public_key().encode())?;
+        let bind_addr: SocketAddr = match socket_addr {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        };
+        let socket = UdpSocket::bind(bind_addr)
+            .await
+            .map_err(|e| Error::Io(format!("Could not bind a UDP socket: {}", e)))?;
+        socket
+            .connect(socket_addr)
+            .await
+            .map_err(|e| Error::Io(format!("Could not connect to {}: {}", socket_addr, e)))?;
+
+        // without a session the first packet is random and answered with a challenge
+        let random_packet = Packet::new_random(&local_id)
+            .map_err(|e| Error::Protocol(format!("Could not create a random packet: {}", e)))?;
+        let request_nonce = random_packet.header.message_nonce;
+        send(&socket, random_packet.encode(&remote_id)).await?;
+        let (enr_seq, challenge_data) = loop {
+            let (packet, authenticated_data) = receive(&socket, &local_id).await?;
+            if let PacketKind::WhoAreYou { enr_seq, .. } = packet.header.kind {
+                if packet.header.message_nonce == request_nonce {
+                    break (enr_seq, authenticated_data);
+                }
+            }
+        };
+
+        // answer the challenge with the request
+        let ephemeral_key = crypto::random_secret_key();
+        let ephemeral_pubkey =
+            secp256k1::PublicKey::from_secret_key(&ephemeral_key).serialize_compressed();
+        let keys = crypto::derive_keys(
+            &remote_public,
+            &ephemeral_key,
+            &challenge_data,
+            &local_id,
+            &remote_id,
+        )?;
+        let id_signature =
+            crypto::id_sign(&self.key, &challenge_data, &ephemeral_pubkey, &remote_id);
+        let request = Request {
+            id: RequestId::random(),
+            body: RequestBody::FindNode { distances },
+        };
+        let request_id = request.id.clone();
+        let mut handshake = Packet {
+            iv: rand::random(),
+            header: PacketHeader {
+                message_nonce: rand::random(),
+                kind: PacketKind::Handshake {
+                    src_id: local_id,
+                    id_nonce_sig: id_signature.to_vec(),
+                    ephem_pubkey: ephemeral_pubkey.to_vec(),
+                    enr_record: (enr_seq < self.enr.seq()).then(|| self.enr.clone()),
+                },
+            },
+            message: Vec::new(),
+        };
+        handshake.message = crypto::encrypt(
+            &keys.initiator_key,
+            &handshake.header.message_nonce,
+            &request.encode(),
+            &handshake.authenticated_data(),
+        )?;
+        send(&socket, handshake.encode(&remote_id)).await?;
+
+        // a response may be split over several NODES messages
+        let mut nodes = Vec::new();
+        let mut responses = 0;
+        loop {
+            let (packet, authenticated_data) = receive(&socket, &local_id).await?;
+            match packet.header.kind {
+                PacketKind::Message { src_id } if src_id == remote_id => {}
+                _ => continue,
+            }
+            let plaintext = crypto::decrypt(
+                &keys.recipient_key,
+                &packet.header.message_nonce,
+                &packet.message,
+                &authenticated_data,
+            )?;
+            let response = match Message::decode(&plaintext) {
+                Ok(Message::Response(response)) if response.id == request_id => response,
+                Ok(_) => continue,
+                Err(e) => {
+                    return Err(Error::Protocol(format!(
+                        "Invalid message from {}: {}",
+                        remote_id, e
+                    )))
+                }
+            };
+            match response.body {
+                ResponseBody::Nodes {
+                    total,
+                    nodes: mut response_nodes,
+                } => {
+                    nodes.append(&mut response_nodes);
+                    responses += 1;
+                    if responses >= total {
+                        return Ok(nodes);
+                    }
+                }
+                body => {
+                    return Err(Error::Protocol(format!(
+                        "Unexpected response from {}: {}",
+                        remote_id, body
+                    )))
+                }
+            }
+        }
+    }
+}
+
+/// The log2-distances to request from `peer` for `target`: the distance between them and the
+/// neighbouring distances, as discv5 requests them. Distance 0 would only return the peer itself,
+/// so a peer that is the target is asked for the closest distances instead.
+fn lookup_distances(target: &NodeId, peer: &NodeId) -> Vec<u64> {
+    let distance = Key::from(*peer)
+        .log2_distance(&Key::from(*target))
+        .unwrap_or(1);
+    let mut distances = vec![distance];
+    let mut difference = 1;
+    while distances.len() < DISTANCES_PER_REQUEST {
+        if distance + difference <= MAX_LOG2_DISTANCE {
+            distances.push(distance + difference);
+        }
+        if distances.len() < DISTANCES_PER_REQUEST && distance > difference {
+            distances.push(distance - difference);
+        }
+        difference += 1;
+    }
+    distances
+}
+
+async fn send(socket: &UdpSocket, bytes: Vec<u8>) -> Result<()> {
+    socket
+        .send(&bytes)
+        .await
+        .map_err(|e| Error::Io(format!("Could not send a packet: {}", e)))?;
+    Ok(())
+}
+
+/// Receives the next packet that decodes, skipping any that do not.
+async fn receive(socket: &UdpSocket, local_id: &NodeId) -> Result<(Packet, Vec<u8>)> {
+    let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+    loop {
+        let length = socket
+            .recv(&mut buffer)
+            .await
+            .map_err(|e| Error::Io(format!("Could not receive a packet: {}", e)))?;
+        if let Ok(decoded) = Packet::decode(local_id, &buffer[..length]) {
+            return Ok(decoded);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discv5::{Discv5, Discv5ConfigBuilder};
+
+    #[test]
+    fn requests_distances_from_1_to_256() {
+        let target = NodeId::new(&[0; 32]);
+        let mut raw = [0; 32];
+        raw[31] = 1;
+        assert_eq!(lookup_distances(&target, &NodeId::new(&raw)), vec![1, 2, 3]);
+        assert_eq!(lookup_distances(&target, &target), vec![1, 2, 3]);
+        raw[0] = 0x80;
+        assert_eq!(
+            lookup_distances(&target, &NodeId::new(&raw)),
+            vec![256, 255, 254]
+        );
+        raw[0] = 0x01;
+        assert_eq!(
+            lookup_distances(&target, &NodeId::new(&raw)),
+            vec![249, 250, 248]
+        );
+    }
+
+    #[tokio::test]
+    async fn finds_the_nodes_of_a_peer() {
+        let port = std::net::UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let enr_key = CombinedKey::generate_secp256k1();
+        let enr = server::EnrConfig {
+            ip: Some("127.0.0.1".parse().unwrap()),
+            udp_port: Some(port),
+            ..Default::default()
+        }
+        .build(&enr_key)
+        .unwrap();
+        let config = Discv5ConfigBuilder::new().build();
+        let mut discv5 = Discv5::new(enr.clone(), enr_key, config).unwrap();
+        discv5.start(([127, 0, 0, 1], port).into()).await.unwrap();
+
+        // nodes on 127.0.0.1 are in the peer's table at log2-distances near 256
+        let mut known = Vec::new();
+        for index in 0..3u16 {
+            let key = CombinedKey::generate_secp256k1();
+            let node = server::EnrConfig {
+                ip: Some("127.0.0.1".parse().unwrap()),
+                udp_port: Some(30000 + index),
+                ..Default::default()
+            }
+            .build(&key)
+            .unwrap();
+            discv5.add_enr(node.clone()).unwrap();
+            known.push(node);
+        }
+
+        let client = LookupClient::new(3, Duration::from_secs(5)).unwrap();
+        let local_key: Key<NodeId> = enr.node_id().into();
+        let mut distances: Vec<u64> = known
+            .iter()
+            .filter_map(|node| local_key.log2_distance(&node.node_id().into()))
+            .collect();
+        distances.sort_unstable();
+        distances.dedup();
+        let found = client.find_node(&enr, distances).await.unwrap();
+
+        let mut found: Vec<_> = found.iter().map(|node| node.node_id().raw()).collect();
+        let mut known: Vec<_> = known.iter().map(|node| node.node_id().raw()).collect();
+        found.sort_unstable();
+        known.sort_unstable();
+        assert_eq!(found, known);
+        discv5.shutdown();
+    }
+}
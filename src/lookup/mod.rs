@@ -0,0 +1,182 @@
+//! An iterative `FINDNODE` lookup that records which peer returned which node.
+//!
+//! discv5 runs its lookups internally and only reports the nodes found, so the server and the
+//! simulation drive this state machine over their own transports. The transport sends a request
+//! for every peer returned by [`Lookup::next_request`] and reports the outcome with
+//! [`Lookup::on_response`] or [`Lookup::on_failure`].
+
+use discv5::{enr::NodeId, kbucket::Distance, Key};
+use std::collections::{BTreeMap, HashSet};
+
+pub mod client;
+
+/// The maximum number of nodes in a `NODES` response and returned by a lookup.
+pub const MAX_NODES_RESPONSE: usize = 16;
+
+/// The number of requests a lookup sent, how many failed and the longest chain of responses.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    /// The longest chain of responses that led to a result.
+    pub hops: u64,
+    /// The number of `FINDNODE` requests sent.
+    pub messages: u64,
+    /// The number of requests that failed or timed out.
+    pub timeouts: u64,
+}
+
+/// A peer the lookup learnt about. `T` is the transport's handle of the peer.
+struct Candidate<T> {
+    node_id: NodeId,
+    peer: T,
+    /// The number of responses that led to this peer, 0 for the initial peers.
+    hop: u64,
+}
+
+/// The state of a lookup of a single target.
+pub struct Lookup<T> {
+    target: Key<NodeId>,
+    parallelism: usize,
+    /// The known peers, ordered by their distance to the target.
+    candidates: BTreeMap<Distance, Candidate<T>>,
+    queried: HashSet<NodeId>,
+    responded: HashSet<NodeId>,
+    in_flight: usize,
+    stats: LookupStats,
+}
+
+impl<T: Clone> Lookup<T> {
+    /// Starts a lookup of `target` from `peers`, keeping at most `parallelism` requests in flight.
+    pub fn new(
+        target: NodeId,
+        parallelism: usize,
+        peers: impl IntoIterator<Item = (NodeId, T)>,
+    ) -> Self {
+        let mut lookup = Lookup {
+            target: target.into(),
+            parallelism,
+            candidates: BTreeMap::new(),
+            queried: HashSet::new(),
+            responded: HashSet::new(),
+            in_flight: 0,
+            stats: LookupStats::default(),
+        };
+        for (node_id, peer) in peers {
+            lookup.add_candidate(node_id, peer, 0);
+        }
+        lookup
+    }
+
+    /// The next peer to query: the closest unqueried of the closest candidates, or `None` if
+    /// `parallelism` requests are in flight or no candidate is left.
+    pub fn next_request(&mut self) -> Option<(NodeId, T)> {
+        if self.in_flight >= self.parallelism {
+            return None;
+        }
+        let queried = &self.queried;
+        let next = self
+            .candidates
+            .values()
+            .take(MAX_NODES_RESPONSE)
+            .find(|candidate| !queried.contains(&candidate.node_id))?;
+        let next = (next.node_id, next.peer.clone());
+        self.queried.insert(next.0);
+        self.in_flight += 1;
+        self.stats.messages += 1;
+        Some(next)
+    }
+
+    /// Records the nodes `from` returned. The caller leaves out the local node.
+    pub fn on_response(&mut self, from: &NodeId, nodes: impl IntoIterator<Item = (NodeId, T)>) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        let hop = self.candidate(from).map_or(0, |candidate| candidate.hop) + 1;
+        self.responded.insert(*from);
+        self.stats.hops = self.stats.hops.max(hop);
+        for (node_id, peer) in nodes {
+            self.add_candidate(node_id, peer, hop);
+        }
+    }
+
+    /// Records that the request to `from` failed, dropping it from the candidates.
+    pub fn on_failure(&mut self, from: &NodeId) {
+        self.in_flight = self.in_flight.saturating_sub(1);
+        self.stats.timeouts += 1;
+        let distance = self.distance(from);
+        self.candidates.remove(&distance);
+    }
+
+    /// Whether no request is in flight and no candidate is left to query.
+    pub fn is_finished(&self) -> bool {
+        self.in_flight == 0
+            && !self
+                .candidates
+                .values()
+                .take(MAX_NODES_RESPONSE)
+                .any(|candidate| !self.queried.contains(&candidate.node_id))
+    }
+
+    /// The closest peers that responded, closest first, and the statistics of the lookup.
+    pub fn finish(self) -> (Vec<T>, LookupStats) {
+        let responded = self.responded;
+        let found = self
+            .candidates
+            .into_values()
+            .filter(|candidate| responded.contains(&candidate.node_id))
+            .take(MAX_NODES_RESPONSE)
+            .map(|candidate| candidate.peer)
+            .collect();
+        (found, self.stats)
+    }
+
+    fn add_candidate(&mut self, node_id: NodeId, peer: T, hop: u64) {
+        let distance = self.distance(&node_id);
+        self.candidates
+            .entry(distance)
+            .or_insert(Candidate { node_id, peer, hop });
+    }
+
+    fn candidate(&self, node_id: &NodeId) -> Option<&Candidate<T>> {
+        self.candidates.get(&self.distance(node_id))
+    }
+
+    fn distance(&self, node_id: &NodeId) -> Distance {
+        Key::from(*node_id).distance(&self.target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id(byte: u8) -> NodeId {
+        NodeId::new(&[byte; 32])
+    }
+
+    #[test]
+    fn finds_the_nodes_that_responded() {
+        let target = node_id(0);
+        let mut lookup = Lookup::new(target, 2, vec![(node_id(1), 1u8), (node_id(2), 2)]);
+
+        let (first, _) = lookup.next_request().unwrap();
+        let (second, _) = lookup.next_request().unwrap();
+        assert!(lookup.next_request().is_none(), "parallelism is respected");
+
+        lookup.on_response(&first, vec![(node_id(3), 3)]);
+        lookup.on_failure(&second);
+        let (third, peer) = lookup.next_request().unwrap();
+        assert_eq!((third, peer), (node_id(3), 3));
+        lookup.on_response(&third, vec![]);
+        assert!(lookup.is_finished());
+
+        let (mut found, stats) = lookup.finish();
+        found.sort_unstable();
+        assert_eq!(found, vec![first.raw()[0], 3]);
+        assert_eq!(
+            stats,
+            LookupStats {
+                hops: 2,
+                messages: 3,
+                timeouts: 1
+            }
+        );
+    }
+}
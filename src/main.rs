@@ -53,10 +53,7 @@
 //! PeerId: QmdFQ2frpcyD3DiaMcM6XP37X3xCAV81GoB3jRusAa7kxu
 //! ```
 
-mod cli;
-mod packet;
-mod request_enr;
-mod server;
+use discv5_cli::cli;
 use log::error;
 
 #[tokio::main]
@@ -83,22 +80,8 @@ async fn main() {
         .init()
         .expect("Could not build the logger");
 
-    // Parse the CLI parameters.
-    if let Some(server_matches) = cli_matches.subcommand_matches("server") {
-        server::run(server_matches).await;
-    } else if let Some(enr_matches) = cli_matches.subcommand_matches("request-enr") {
-        request_enr::run(enr_matches).await;
-    } else if let Some(packet_matches) = cli_matches.subcommand_matches("packet") {
-        if let Some(decode_matches) = packet_matches.subcommand_matches("decode") {
-            packet::decode(decode_matches);
-        } else {
-            // Currently no encode sub command
-            error!("A packet subcommand must be supplied. See --help for options");
-            return;
-        }
-    } else {
-        // No subcommand supplied
-        error!("A subcommand must be supplied. See --help for options");
-        return;
+    if let Err(e) = cli::run(&cli_matches).await {
+        error!("{}", e);
+        std::process::exit(e.exit_code());
     }
 }
@@ -0,0 +1,281 @@
+//! Explains why a packet cannot be decoded.
+//!
+//! discv5 only reports which check failed, so the bytes are re-examined here to name the likely
+//! cause and what to try next.
+
+use super::layout;
+use super::{
+    unmask_header, FLAG_OFFSET, HANDSHAKE_FLAG, MAX_PACKET_SIZE, MESSAGE_FLAG, MIN_PACKET_SIZE,
+    PROTOCOL_ID, PROTOCOL_VERSION, WHOAREYOU_FLAG,
+};
+use discv5::enr::NodeId;
+use discv5::packet::{IV_LENGTH, STATIC_HEADER_LENGTH};
+use sha2::{Digest, Sha256};
+
+/// The length of the tag or magic that starts every discv5.0 packet.
+const LEGACY_TAG_LENGTH: usize = 32;
+/// The RLP encoded scheme of a discv5.0 auth-header, which follows the tag, the list prefix, the
+/// auth-tag and the id-nonce.
+const LEGACY_SCHEME: &[u8] = b"\x83gcm";
+/// The offset of the scheme in a discv5.0 handshake packet.
+const LEGACY_SCHEME_OFFSET: usize = LEGACY_TAG_LENGTH + 2 + 13 + 33;
+
+/// The most likely reason a packet failed to decode.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Cause {
+    /// The packet is shorter than the smallest packet, a WHOAREYOU.
+    TooSmall(usize),
+    /// The packet is larger than discv5 accepts.
+    TooLarge(usize),
+    /// The header does not unmask to the protocol id, so the packet was not sent to this node id.
+    WrongNodeId,
+    /// The packet has the layout of the discv5.0 wire protocol, which has no masked header.
+    LegacyProtocol,
+    /// The header unmasks correctly but has a version discv5 does not speak.
+    UnsupportedVersion(u16),
+    /// The flag is not one of the packet types.
+    UnknownFlag(u8),
+    /// The authdata cannot hold the fields of the packet type.
+    ImpossibleAuthdataSize { flag: u8, size: usize },
+    /// The header declares more authdata than the packet contains.
+    TruncatedAuthdata { declared: usize, available: usize },
+    /// A WHOAREYOU is followed by message bytes.
+    WhoareyouWithMessage(usize),
+}
+
+impl std::fmt::Display for Cause {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Cause::TooSmall(length) => write!(
+                f,
+                "The packet is {} bytes, under the minimum of {} bytes",
+                length, MIN_PACKET_SIZE
+            ),
+            Cause::TooLarge(length) => write!(
+                f,
+                "The packet is {} bytes, over the maximum of {} bytes",
+                length, MAX_PACKET_SIZE
+            ),
+            Cause::WrongNodeId => write!(
+                f,
+                "The header does not unmask to the protocol id, so the packet was not addressed to the node id used"
+            ),
+            Cause::LegacyProtocol => write!(
+                f,
+                "The packet looks like a discv5.0 packet, which this version of the protocol cannot decode"
+            ),
+            Cause::UnsupportedVersion(version) => write!(
+                f,
+                "The header has protocol version {}, but only version {} is supported",
+                version, PROTOCOL_VERSION
+            ),
+            Cause::UnknownFlag(flag) => write!(f, "The flag {} is not a packet type", flag),
+            Cause::ImpossibleAuthdataSize { flag, size } => write!(
+                f,
+                "An authdata-size of {} bytes is impossible for a {} packet",
+                size,
+                layout::flag_name(*flag)
+            ),
+            Cause::TruncatedAuthdata {
+                declared,
+                available,
+            } => write!(
+                f,
+                "The header declares {} bytes of authdata, but the packet only has {}",
+                declared, available
+            ),
+            Cause::WhoareyouWithMessage(length) => write!(
+                f,
+                "The WHOAREYOU packet is followed by {} message bytes",
+                length
+            ),
+        }
+    }
+}
+
+impl Cause {
+    /// What to check or try next to decode the packet.
+    pub fn next_step(&self) -> &'static str {
+        match self {
+            Cause::TooSmall(_) | Cause::TruncatedAuthdata { .. } => {
+                "Check the packet was captured whole, for example that the capture did not cut off the datagram"
+            }
+            Cause::TooLarge(_) => {
+                "Check the capture holds a single datagram and not several packets joined together"
+            }
+            Cause::WrongNodeId => {
+                "Pass the node id of the packet's recipient. A WHOAREYOU is addressed to the node that sent the challenged message"
+            }
+            Cause::LegacyProtocol => {
+                "Upgrade the sender to discv5.1, or decode the packet with a discv5.0 tool"
+            }
+            Cause::UnsupportedVersion(_) => {
+                "Check the version of the sender's client and which protocol version it speaks"
+            }
+            Cause::UnknownFlag(_)
+            | Cause::ImpossibleAuthdataSize { .. }
+            | Cause::WhoareyouWithMessage(_) => {
+                "The header unmasks correctly, so the sender encoded it incorrectly. Compare it with packet decode --layout"
+            }
+        }
+    }
+}
+
+/// Checks the layout of a packet sent to `dst_id` and returns the first reason it cannot decode,
+/// or `None` if the layout is sound and any failure lies in the contents, such as the ENR.
+pub fn diagnose(dst_id: &NodeId, packet: &[u8]) -> Option<Cause> {
+    if packet.len() < MIN_PACKET_SIZE {
+        return Some(Cause::TooSmall(packet.len()));
+    }
+    if packet.len() > MAX_PACKET_SIZE {
+        return Some(Cause::TooLarge(packet.len()));
+    }
+
+    let (unmasked, header_length) = unmask_header(dst_id, packet);
+    let header = &unmasked[IV_LENGTH..];
+    if &header[..PROTOCOL_ID.len()] != PROTOCOL_ID {
+        if is_legacy(dst_id, packet) {
+            return Some(Cause::LegacyProtocol);
+        }
+        return Some(Cause::WrongNodeId);
+    }
+    let version = u16::from_be_bytes([header[6], header[7]]);
+    if version != PROTOCOL_VERSION {
+        return Some(Cause::UnsupportedVersion(version));
+    }
+
+    let flag = header[FLAG_OFFSET];
+    let authdata_size = header_length.expect("The minimum packet size covers the static header")
+        - STATIC_HEADER_LENGTH;
+    let authdata_start = IV_LENGTH + STATIC_HEADER_LENGTH;
+    let available = packet.len() - authdata_start;
+    let size_possible = match flag {
+        MESSAGE_FLAG => authdata_size == 32,
+        WHOAREYOU_FLAG => authdata_size == 24,
+        HANDSHAKE_FLAG => authdata_size >= 34,
+        _ => return Some(Cause::UnknownFlag(flag)),
+    };
+    if !size_possible {
+        return Some(Cause::ImpossibleAuthdataSize {
+            flag,
+            size: authdata_size,
+        });
+    }
+    if authdata_size > available {
+        return Some(Cause::TruncatedAuthdata {
+            declared: authdata_size,
+            available,
+        });
+    }
+    if flag == HANDSHAKE_FLAG {
+        let sig_size = unmasked[authdata_start + 32] as usize;
+        let key_size = unmasked[authdata_start + 33] as usize;
+        if authdata_size < 34 + sig_size + key_size {
+            return Some(Cause::ImpossibleAuthdataSize {
+                flag,
+                size: authdata_size,
+            });
+        }
+    }
+    if flag == WHOAREYOU_FLAG && available > authdata_size {
+        return Some(Cause::WhoareyouWithMessage(available - authdata_size));
+    }
+    None
+}
+
+/// Whether the packet starts like a discv5.0 WHOAREYOU, with the magic of `dst_id`, or a
+/// discv5.0 handshake, with the RLP auth-header naming the "gcm" scheme. discv5.0 message packets
+/// have no fixed bytes to recognise them by.
+fn is_legacy(dst_id: &NodeId, packet: &[u8]) -> bool {
+    let mut hasher = Sha256::new();
+    hasher.update(dst_id.raw());
+    hasher.update(b"WHOAREYOU");
+    let magic = hasher.finalize();
+    packet[..LEGACY_TAG_LENGTH] == magic[..]
+        || packet.get(LEGACY_SCHEME_OFFSET..LEGACY_SCHEME_OFFSET + LEGACY_SCHEME.len())
+            == Some(LEGACY_SCHEME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::vectors::{
+        HANDSHAKE_ENR_PACKET, HANDSHAKE_PACKET, NODE_A_ID, NODE_B_ID, PING_PACKET, WHOAREYOU_PACKET,
+    };
+    use super::*;
+
+    fn node_id(hex_id: &str) -> NodeId {
+        NodeId::parse(&hex::decode(hex_id).unwrap()).unwrap()
+    }
+
+    fn node_b() -> NodeId {
+        node_id(NODE_B_ID)
+    }
+
+    /// The ping packet with an unmasked header byte flipped. The mask is a keystream, so flipping
+    /// a masked byte flips the unmasked byte.
+    fn altered_ping(offset: usize, bits: u8) -> Vec<u8> {
+        let mut packet = hex::decode(PING_PACKET).unwrap();
+        packet[IV_LENGTH + offset] ^= bits;
+        packet
+    }
+
+    #[test]
+    fn finds_no_cause_in_the_spec_vectors() {
+        for packet in [
+            PING_PACKET,
+            WHOAREYOU_PACKET,
+            HANDSHAKE_PACKET,
+            HANDSHAKE_ENR_PACKET,
+        ] {
+            assert_eq!(diagnose(&node_b(), &hex::decode(packet).unwrap()), None);
+        }
+    }
+
+    #[test]
+    fn blames_the_node_id_for_a_bad_protocol_id() {
+        // a wrong protocol id and a wrong node id both unmask to something other than "discv5"
+        assert_eq!(
+            diagnose(&node_b(), &altered_ping(0, 0xff)),
+            Some(Cause::WrongNodeId)
+        );
+        // the ping is sent by node A to node B
+        assert_eq!(
+            diagnose(&node_id(NODE_A_ID), &hex::decode(PING_PACKET).unwrap()),
+            Some(Cause::WrongNodeId)
+        );
+    }
+
+    #[test]
+    fn finds_a_bad_version() {
+        // the low byte of version 1
+        assert_eq!(
+            diagnose(&node_b(), &altered_ping(7, 0x03)),
+            Some(Cause::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn finds_truncated_packets() {
+        let packet = hex::decode(PING_PACKET).unwrap();
+        assert_eq!(
+            diagnose(&node_b(), &packet[..30]),
+            Some(Cause::TooSmall(30))
+        );
+        // the static header is whole but only 24 of the 32 bytes of authdata are
+        assert_eq!(
+            diagnose(&node_b(), &packet[..MIN_PACKET_SIZE]),
+            Some(Cause::TruncatedAuthdata {
+                declared: 32,
+                available: 24
+            })
+        );
+    }
+
+    #[test]
+    fn finds_a_bad_flag() {
+        assert_eq!(
+            diagnose(&node_b(), &altered_ping(FLAG_OFFSET, 0x07)),
+            Some(Cause::UnknownFlag(7))
+        );
+    }
+}
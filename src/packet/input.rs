@@ -0,0 +1,227 @@
+//! Reads packets from the command line, files or stdin, in the encodings other tools produce.
+
+use crate::error::{Error, Result};
+use data_encoding::{Encoding, BASE64, BASE64URL, BASE64URL_NOPAD, BASE64_NOPAD};
+use std::io::Read;
+use std::str::FromStr;
+
+/// The base64 variants accepted, padded or not and with either alphabet.
+const BASE64_ENCODINGS: [&Encoding; 4] = [&BASE64, &BASE64_NOPAD, &BASE64URL, &BASE64URL_NOPAD];
+
+/// The encoding of packet input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// Binary input is a single packet, text input is hex or base64 per line.
+    Auto,
+    Hex,
+    Base64,
+    /// The raw bytes of a single packet.
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Format::Auto),
+            "hex" => Ok(Format::Hex),
+            "base64" => Ok(Format::Base64),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("Unknown packet format {}", s)),
+        }
+    }
+}
+
+/// Reads the packets given by a `packet` argument: `-` reads stdin, `@PATH` reads a file and
+/// anything else is a single encoded packet. With `wrapped`, text input holds one packet per block
+/// of lines.
+pub fn read_packets(argument: &str, format: Format, wrapped: bool) -> Result<Vec<Vec<u8>>> {
+    if argument == "-" {
+        let mut input = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut input)
+            .map_err(|e| Error::Io(format!("Could not read stdin: {}", e)))?;
+        parse_packets(&input, format, wrapped)
+    } else if let Some(path) = argument.strip_prefix('@') {
+        let input = std::fs::read(path)
+            .map_err(|e| Error::Io(format!("Could not read the packet file {}: {}", path, e)))?;
+        parse_packets(&input, format, wrapped)
+    } else if format == Format::Binary {
+        Err(Error::Usage(
+            "Binary packets must be read from a file or stdin".into(),
+        ))
+    } else {
+        Ok(vec![parse_packet(argument, format)?])
+    }
+}
+
+/// Splits input into packets. Text holds one packet per line and `#` comments and blank lines are
+/// skipped. With `wrapped`, packets are instead separated by blank lines and the lines of a packet
+/// wrapped over several lines, as hexdumps do, are joined. Binary input is a single packet.
+pub fn parse_packets(input: &[u8], format: Format, wrapped: bool) -> Result<Vec<Vec<u8>>> {
+    let text = match format {
+        Format::Binary => return Ok(vec![input.to_vec()]),
+        Format::Auto if !is_text(input) => return Ok(vec![input.to_vec()]),
+        _ => std::str::from_utf8(input)
+            .map_err(|_| Error::Parse("The packet input is not text".into()))?,
+    };
+
+    // the packets with the line number each starts at
+    let mut blocks: Vec<(usize, String)> = Vec::new();
+    let mut in_block = false;
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            in_block = false;
+        } else if !line.starts_with('#') {
+            match blocks.last_mut() {
+                Some((_, block)) if wrapped && in_block => {
+                    block.push('\n');
+                    block.push_str(line);
+                }
+                _ => blocks.push((index + 1, line.to_string())),
+            }
+            in_block = true;
+        }
+    }
+    blocks
+        .iter()
+        .map(|(line_number, block)| {
+            parse_packet(block, format).map_err(|e| {
+                if wrapped {
+                    Error::Parse(format!("Packet starting at line {}: {}", line_number, e))
+                } else {
+                    Error::Parse(format!("Packet at line {}: {}", line_number, e))
+                }
+            })
+        })
+        .collect()
+}
+
+/// Parses a packet encoded as text. Whitespace and `0x` prefixes, as found in hexdumps, are
+/// ignored. In the auto format hex is tried before base64.
+pub fn parse_packet(text: &str, format: Format) -> Result<Vec<u8>> {
+    match format {
+        Format::Hex => parse_hex(text),
+        Format::Base64 => parse_base64(text),
+        _ => parse_hex(text)
+            .or_else(|_| parse_base64(text))
+            .map_err(|_| Error::Parse("The packet is neither hex nor base64".into())),
+    }
+}
+
+fn parse_hex(text: &str) -> Result<Vec<u8>> {
+    let digits: String = text
+        .split_whitespace()
+        .map(|word| word.trim_start_matches("0x").trim_start_matches("0X"))
+        .collect();
+    hex::decode(digits).map_err(|e| Error::Parse(format!("Invalid hex packet: {}", e)))
+}
+
+fn parse_base64(text: &str) -> Result<Vec<u8>> {
+    let characters: String = text.split_whitespace().collect();
+    BASE64_ENCODINGS
+        .iter()
+        .find_map(|encoding| encoding.decode(characters.as_bytes()).ok())
+        .ok_or_else(|| Error::Parse("Invalid base64 packet".into()))
+}
+
+/// Whether the input is printable ASCII text rather than raw packet bytes.
+fn is_text(input: &[u8]) -> bool {
+    input
+        .iter()
+        .all(|byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The ping message packet of the discv5 spec test vectors.
+    const PING_PACKET: &str = "00000000000000000000000000000000088b3d4342774649325f313964a39e55\
+                               ea96c005ad52be8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d3\
+                               4c4f53245d08dab84102ed931f66d1492acb308fa1c6715b9d139b81acbdcc";
+
+    fn ping_packet() -> Vec<u8> {
+        hex::decode(PING_PACKET).unwrap()
+    }
+
+    #[test]
+    fn parses_hex_and_base64() {
+        let packet = ping_packet();
+        let hex = format!("0x{}", PING_PACKET);
+        let base64 = BASE64.encode(&packet);
+        let base64url = BASE64URL_NOPAD.encode(&packet);
+
+        assert_eq!(parse_packet(&hex, Format::Hex).unwrap(), packet);
+        assert_eq!(parse_packet(&hex, Format::Auto).unwrap(), packet);
+        assert_eq!(parse_packet(&base64, Format::Base64).unwrap(), packet);
+        assert_eq!(parse_packet(&base64url, Format::Auto).unwrap(), packet);
+        assert!(parse_packet(&base64, Format::Hex).is_err());
+        assert!(parse_packet("not a packet!", Format::Auto).is_err());
+    }
+
+    #[test]
+    fn tolerates_hexdump_whitespace_and_prefixes() {
+        let spaced: Vec<String> = PING_PACKET
+            .as_bytes()
+            .chunks(2)
+            .map(|byte| format!("0x{}", std::str::from_utf8(byte).unwrap()))
+            .collect();
+        assert_eq!(
+            parse_packet(&spaced.join(" "), Format::Hex).unwrap(),
+            ping_packet()
+        );
+    }
+
+    #[test]
+    fn reads_one_packet_per_line() {
+        let base64 = BASE64.encode(&ping_packet());
+        let input = format!(
+            "# captured packets\n{}\n\n  \n{}\n# end\n{}\n",
+            PING_PACKET, base64, PING_PACKET
+        );
+
+        let packets = parse_packets(input.as_bytes(), Format::Auto, false).unwrap();
+        assert_eq!(packets, vec![ping_packet(); 3]);
+    }
+
+    #[test]
+    fn joins_wrapped_packets_when_asked() {
+        // `xxd -p` wraps at 60 hex digits per line
+        let wrapped: Vec<&str> = PING_PACKET
+            .as_bytes()
+            .chunks(60)
+            .map(|line| std::str::from_utf8(line).unwrap())
+            .collect();
+        let input = format!("{}\n\n{}\n", wrapped.join("\n"), PING_PACKET);
+
+        let packets = parse_packets(input.as_bytes(), Format::Auto, true).unwrap();
+        assert_eq!(packets, vec![ping_packet(), ping_packet()]);
+        let unwrapped = parse_packets(input.as_bytes(), Format::Hex, false).unwrap();
+        assert_eq!(unwrapped.len(), wrapped.len() + 1);
+    }
+
+    #[test]
+    fn reports_the_line_of_an_invalid_packet() {
+        let input = format!("{}\n\nzz\n", PING_PACKET);
+        let error = parse_packets(input.as_bytes(), Format::Hex, false).unwrap_err();
+        assert!(error.to_string().starts_with("Packet at line 3"));
+        let error = parse_packets(input.as_bytes(), Format::Hex, true).unwrap_err();
+        assert!(error.to_string().starts_with("Packet starting at line 3"));
+    }
+
+    #[test]
+    fn reads_binary_input_as_one_packet() {
+        let packet = ping_packet();
+        assert_eq!(
+            parse_packets(&packet, Format::Auto, false).unwrap(),
+            vec![packet.clone()]
+        );
+        assert_eq!(
+            parse_packets(&packet, Format::Binary, false).unwrap(),
+            vec![packet]
+        );
+    }
+}
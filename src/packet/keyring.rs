@@ -0,0 +1,168 @@
+//! A list of the nodes of a test network, used to find which node a packet was addressed to.
+
+use super::{unmask_header, PROTOCOL_ID};
+use crate::error::{Error, Result};
+use discv5::enr::{CombinedKey, NodeId};
+use discv5::packet::IV_LENGTH;
+
+type Enr = discv5::enr::Enr<CombinedKey>;
+
+/// The length of a raw node id.
+const NODE_ID_LENGTH: usize = 32;
+
+/// A node of the keyring.
+pub struct KeyringEntry {
+    pub name: Option<String>,
+    pub node_id: NodeId,
+}
+
+impl std::fmt::Display for KeyringEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.name {
+            Some(name) => write!(f, "{} ({})", name, self.node_id),
+            None => write!(f, "{}", self.node_id),
+        }
+    }
+}
+
+/// The candidate destinations of packets.
+pub struct Keyring {
+    entries: Vec<KeyringEntry>,
+}
+
+impl Keyring {
+    /// Reads a keyring file. Each line holds an ENR or hex node id, optionally preceded by a
+    /// name. Blank lines and `#` comments are skipped.
+    pub fn from_file(path: &str) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| Error::Io(format!("Could not read the keyring {}: {}", path, e)))?;
+        Keyring::parse(&contents)
+    }
+
+    /// Parses the contents of a keyring file.
+    pub fn parse(contents: &str) -> Result<Self> {
+        let entries = contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+            .map(|(index, line)| {
+                parse_entry(line)
+                    .map_err(|e| Error::Parse(format!("Keyring line {}: {}", index + 1, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Keyring { entries })
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The first node whose id unmasks the packet header to the protocol id.
+    pub fn find_destination(&self, packet: &[u8]) -> Option<&KeyringEntry> {
+        self.entries.iter().find(|entry| {
+            let (unmasked, _) = unmask_header(&entry.node_id, packet);
+            unmasked.get(IV_LENGTH..IV_LENGTH + PROTOCOL_ID.len()) == Some(PROTOCOL_ID)
+        })
+    }
+
+    /// The node id with the name the keyring gives it, if any.
+    pub fn label(&self, node_id: &NodeId) -> String {
+        match self.entries.iter().find(|entry| entry.node_id == *node_id) {
+            Some(entry) => entry.to_string(),
+            None => format!("{} (not in the keyring)", node_id),
+        }
+    }
+}
+
+fn parse_entry(line: &str) -> Result<KeyringEntry> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let (name, node) = match words.as_slice() {
+        [node] => (None, *node),
+        [name, node] => (Some(name.to_string()), *node),
+        _ => {
+            return Err(Error::Parse(
+                "Expected an ENR or node id, optionally preceded by a name".into(),
+            ))
+        }
+    };
+
+    // anything that is not hex is taken to be an ENR
+    let node_id = match hex::decode(node.trim_start_matches("0x")) {
+        // discv5 pads short node ids with zeros, which would never match a destination
+        Ok(bytes) if bytes.len() != NODE_ID_LENGTH => {
+            return Err(Error::Parse(format!(
+                "A node id must be {} bytes, got {}",
+                NODE_ID_LENGTH,
+                bytes.len()
+            )))
+        }
+        Ok(bytes) => {
+            NodeId::parse(&bytes).map_err(|e| Error::Parse(format!("Invalid node id: {}", e)))?
+        }
+        Err(_) => node
+            .parse::<Enr>()
+            .map_err(|e| Error::Parse(format!("Invalid ENR or node id: {}", e)))?
+            .node_id(),
+    };
+    Ok(KeyringEntry { name, node_id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE_A_ID: &str = "aaaa8419e9f49d0083561b48287df592939a8d19947d8c0ef88f2a4856a69fbb";
+    const NODE_B_ID: &str = "bbbb9d047f0488c0b5a93c1c3f2d8bafc7c8ff337024a55434a0d0555de64db9";
+    /// The ping message packet of the discv5 spec test vectors, sent from node A to node B.
+    const PING_PACKET: &str = "00000000000000000000000000000000088b3d4342774649325f313964a39e55\
+                               ea96c005ad52be8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d3\
+                               4c4f53245d08dab84102ed931f66d1492acb308fa1c6715b9d139b81acbdcc";
+
+    fn node_id(hex_id: &str) -> NodeId {
+        NodeId::parse(&hex::decode(hex_id).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn finds_the_destination_of_a_packet() {
+        let enr = discv5::enr::EnrBuilder::new("v4")
+            .build(&CombinedKey::generate_secp256k1())
+            .unwrap();
+        let keyring = Keyring::parse(&format!(
+            "# spec test vector nodes\nnode-a 0x{}\n\nnode-b {}\n{}\n",
+            NODE_A_ID,
+            NODE_B_ID,
+            enr.to_base64()
+        ))
+        .unwrap();
+        assert_eq!(keyring.len(), 3);
+
+        let packet = hex::decode(PING_PACKET).unwrap();
+        let destination = keyring.find_destination(&packet).unwrap();
+        assert_eq!(destination.node_id, node_id(NODE_B_ID));
+        assert_eq!(destination.name.as_deref(), Some("node-b"));
+
+        assert!(keyring.label(&node_id(NODE_A_ID)).starts_with("node-a ("));
+        assert!(keyring
+            .label(&NodeId::new(&[0; 32]))
+            .ends_with("(not in the keyring)"));
+    }
+
+    #[test]
+    fn finds_no_destination_outside_the_keyring() {
+        let keyring = Keyring::parse(&format!("node-a {}\n", NODE_A_ID)).unwrap();
+        let packet = hex::decode(PING_PACKET).unwrap();
+        assert!(keyring.find_destination(&packet).is_none());
+        assert!(keyring.find_destination(&packet[..20]).is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        assert!(Keyring::parse("node-a 0xaaaa").is_err());
+        assert!(Keyring::parse("node-a not-a-node").is_err());
+        assert!(Keyring::parse(&format!("a b {}", NODE_A_ID)).is_err());
+    }
+}
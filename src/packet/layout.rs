@@ -0,0 +1,302 @@
+//! Labels the bytes of an encoded packet with the fields they belong to.
+//!
+//! The layout is read from the unmasked bytes directly rather than from a decoded packet, so
+//! packets that discv5 rejects can be inspected too.
+
+use super::{
+    unmask_header, AUTHDATA_SIZE_OFFSET, FLAG_OFFSET, HANDSHAKE_FLAG, MESSAGE_FLAG, NONCE_OFFSET,
+    VERSION_OFFSET, WHOAREYOU_FLAG,
+};
+use discv5::enr::NodeId;
+use discv5::packet::{IV_LENGTH, STATIC_HEADER_LENGTH};
+use log::info;
+use std::ops::Range;
+
+/// The length of the AES-GCM tag at the end of a message.
+pub const TAG_LENGTH: usize = 16;
+/// The length of a node id in authdata.
+const NODE_ID_LENGTH: usize = 32;
+
+/// The fields of the static header and their offsets within it.
+const STATIC_HEADER_FIELDS: [(&str, usize, usize); 5] = [
+    ("protocol-id", 0, VERSION_OFFSET),
+    ("version", VERSION_OFFSET, FLAG_OFFSET),
+    ("flag", FLAG_OFFSET, NONCE_OFFSET),
+    ("message-nonce", NONCE_OFFSET, AUTHDATA_SIZE_OFFSET),
+    ("authdata-size", AUTHDATA_SIZE_OFFSET, STATIC_HEADER_LENGTH),
+];
+
+/// A labelled range of a packet.
+pub struct Field {
+    pub name: &'static str,
+    /// The bytes of the field, cut short if the packet or authdata ends early.
+    pub range: Range<usize>,
+    /// The length the field should have.
+    pub expected_length: usize,
+    /// Whether the field is part of the masked header.
+    pub masked: bool,
+    /// The meaning of the value, if it has one beyond its bytes.
+    pub note: Option<String>,
+}
+
+impl Field {
+    fn new(name: &'static str, start: usize, length: usize, limit: usize, masked: bool) -> Self {
+        Field {
+            name,
+            range: start.min(limit)..(start + length).min(limit),
+            expected_length: length,
+            masked,
+            note: None,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.range.len() == self.expected_length
+    }
+}
+
+/// Splits a packet sent to `dst_id` into its fields, in order.
+pub fn fields(dst_id: &NodeId, packet: &[u8]) -> Vec<Field> {
+    let (unmasked, header_length) = unmask_header(dst_id, packet);
+    let length = packet.len();
+    let mut fields = vec![Field::new("masking-iv", 0, IV_LENGTH, length, false)];
+
+    for (name, start, end) in STATIC_HEADER_FIELDS.iter() {
+        let mut field = Field::new(name, IV_LENGTH + start, end - start, length, true);
+        if field.is_complete() {
+            field.note = static_header_note(name, &unmasked[field.range.clone()]);
+        }
+        fields.push(field);
+    }
+    let header_length = match header_length {
+        Some(header_length) => header_length,
+        None => return fields,
+    };
+
+    let flag = unmasked[IV_LENGTH + FLAG_OFFSET];
+    let authdata_start = IV_LENGTH + STATIC_HEADER_LENGTH;
+    let authdata_end = IV_LENGTH + header_length;
+    let limit = length.min(authdata_end);
+    let mut offset = authdata_start;
+
+    match flag {
+        MESSAGE_FLAG => fields.push(next_field(&mut offset, limit, "src-id", NODE_ID_LENGTH)),
+        WHOAREYOU_FLAG => {
+            fields.push(next_field(&mut offset, limit, "id-nonce", 16));
+            let mut enr_seq = next_field(&mut offset, limit, "enr-seq", 8);
+            if enr_seq.is_complete() {
+                let mut seq = [0u8; 8];
+                seq.copy_from_slice(&unmasked[enr_seq.range.clone()]);
+                enr_seq.note = Some(u64::from_be_bytes(seq).to_string());
+            }
+            fields.push(enr_seq);
+        }
+        HANDSHAKE_FLAG => {
+            fields.push(next_field(&mut offset, limit, "src-id", NODE_ID_LENGTH));
+            let sig_size = next_field(&mut offset, limit, "sig-size", 1);
+            let key_size = next_field(&mut offset, limit, "eph-key-size", 1);
+            let sizes = if sig_size.is_complete() && key_size.is_complete() {
+                Some((
+                    unmasked[sig_size.range.start] as usize,
+                    unmasked[key_size.range.start] as usize,
+                ))
+            } else {
+                None
+            };
+            fields.push(sig_size);
+            fields.push(key_size);
+            if let Some((sig_size, key_size)) = sizes {
+                fields.push(next_field(&mut offset, limit, "id-signature", sig_size));
+                fields.push(next_field(&mut offset, limit, "eph-pubkey", key_size));
+                let record_length = authdata_end.saturating_sub(offset);
+                if record_length > 0 {
+                    fields.push(next_field(&mut offset, limit, "record", record_length));
+                }
+            }
+        }
+        _ => fields.push(next_field(
+            &mut offset,
+            limit,
+            "authdata",
+            header_length - STATIC_HEADER_LENGTH,
+        )),
+    }
+    // authdata longer than its fields, unless the packet ends first
+    if offset < authdata_end && offset < length {
+        fields.push(Field::new(
+            "unknown-authdata",
+            offset,
+            authdata_end - offset,
+            length,
+            true,
+        ));
+    }
+
+    if length > authdata_end {
+        let message_length = length - authdata_end;
+        if flag == WHOAREYOU_FLAG || message_length < TAG_LENGTH {
+            let mut message = Field::new("message", authdata_end, message_length, length, false);
+            message.note = Some(if flag == WHOAREYOU_FLAG {
+                "a WHOAREYOU has no message".into()
+            } else {
+                format!("shorter than the {} byte tag", TAG_LENGTH)
+            });
+            fields.push(message);
+        } else {
+            let ciphertext_length = message_length - TAG_LENGTH;
+            fields.push(Field::new(
+                "message-ciphertext",
+                authdata_end,
+                ciphertext_length,
+                length,
+                false,
+            ));
+            fields.push(Field::new(
+                "message-tag",
+                authdata_end + ciphertext_length,
+                TAG_LENGTH,
+                length,
+                false,
+            ));
+        }
+    }
+    fields
+}
+
+/// The authdata field at `offset`, which is then moved past it.
+fn next_field(offset: &mut usize, limit: usize, name: &'static str, length: usize) -> Field {
+    let field = Field::new(name, *offset, length, limit, true);
+    *offset += length;
+    field
+}
+
+/// The packet type of a flag.
+pub fn flag_name(flag: u8) -> &'static str {
+    match flag {
+        MESSAGE_FLAG => "message",
+        WHOAREYOU_FLAG => "whoareyou",
+        HANDSHAKE_FLAG => "handshake",
+        _ => "unknown",
+    }
+}
+
+fn static_header_note(name: &str, bytes: &[u8]) -> Option<String> {
+    match name {
+        "protocol-id" => Some(format!("{:?}", String::from_utf8_lossy(bytes))),
+        "version" => Some(u16::from_be_bytes([bytes[0], bytes[1]]).to_string()),
+        "flag" => Some(flag_name(bytes[0]).into()),
+        "authdata-size" => Some(format!(
+            "{} bytes",
+            u16::from_be_bytes([bytes[0], bytes[1]])
+        )),
+        _ => None,
+    }
+}
+
+/// Logs the fields of a packet sent to `dst_id`, one per line, with their offsets, unmasked
+/// bytes and, for header fields, the masked bytes as sent.
+pub fn print(dst_id: &NodeId, packet: &[u8]) {
+    let (unmasked, _) = unmask_header(dst_id, packet);
+    info!(
+        "Packet layout ({} bytes, header unmasked with node id {}):",
+        packet.len(),
+        dst_id
+    );
+    for field in fields(dst_id, packet) {
+        let mut line = format!(
+            "  {:>4}..{:<4} {:<18} 0x{}",
+            field.range.start,
+            field.range.end,
+            field.name,
+            hex::encode(&unmasked[field.range.clone()])
+        );
+        if let Some(note) = &field.note {
+            line.push_str(&format!(" = {}", note));
+        }
+        if field.masked {
+            line.push_str(&format!(
+                " (masked 0x{})",
+                hex::encode(&packet[field.range.clone()])
+            ));
+        }
+        if !field.is_complete() {
+            line.push_str(&format!(
+                " truncated: {} of {} bytes",
+                field.range.len(),
+                field.expected_length
+            ));
+        }
+        info!("{}", line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::vectors::{
+        HANDSHAKE_ENR_PACKET, HANDSHAKE_PACKET, NODE_B_ID, PING_PACKET, WHOAREYOU_PACKET,
+    };
+    use super::*;
+
+    /// The name and range of every field of a spec vector packet sent to node B.
+    fn layout(packet: &str) -> Vec<(&'static str, Range<usize>)> {
+        let dst_id = NodeId::parse(&hex::decode(NODE_B_ID).unwrap()).unwrap();
+        let fields = fields(&dst_id, &hex::decode(packet).unwrap());
+        assert!(fields.iter().all(Field::is_complete));
+        fields
+            .into_iter()
+            .map(|field| (field.name, field.range))
+            .collect()
+    }
+
+    const STATIC_HEADER: [(&str, Range<usize>); 6] = [
+        ("masking-iv", 0..16),
+        ("protocol-id", 16..22),
+        ("version", 22..24),
+        ("flag", 24..25),
+        ("message-nonce", 25..37),
+        ("authdata-size", 37..39),
+    ];
+
+    fn expected(fields: &[(&'static str, Range<usize>)]) -> Vec<(&'static str, Range<usize>)> {
+        STATIC_HEADER.iter().chain(fields).cloned().collect()
+    }
+
+    #[test]
+    fn labels_the_message_packet() {
+        assert_eq!(
+            layout(PING_PACKET),
+            expected(&[
+                ("src-id", 39..71),
+                ("message-ciphertext", 71..79),
+                ("message-tag", 79..95),
+            ])
+        );
+    }
+
+    #[test]
+    fn labels_the_whoareyou_packet() {
+        assert_eq!(
+            layout(WHOAREYOU_PACKET),
+            expected(&[("id-nonce", 39..55), ("enr-seq", 55..63)])
+        );
+    }
+
+    #[test]
+    fn labels_the_handshake_packets() {
+        assert_eq!(
+            layout(HANDSHAKE_PACKET),
+            expected(&[
+                ("src-id", 39..71),
+                ("sig-size", 71..72),
+                ("eph-key-size", 72..73),
+                ("id-signature", 73..137),
+                ("eph-pubkey", 137..170),
+                ("message-ciphertext", 170..178),
+                ("message-tag", 178..194),
+            ])
+        );
+        let with_enr = layout(HANDSHAKE_ENR_PACKET);
+        assert_eq!(with_enr[11], ("record", 170..297));
+        assert_eq!(with_enr.len(), 14);
+    }
+}
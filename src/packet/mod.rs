@@ -1,29 +1,229 @@
 //! Handles the packet-based logic functions
 
+use crate::cli;
+use crate::error::{Error, Result};
+use aes::Aes128;
 use clap::ArgMatches;
-use discv5::{enr, packet::Packet};
-use log::{error, info};
+use ctr::cipher::{NewCipher, StreamCipher};
+use discv5::enr;
+use discv5::packet::{Packet, IV_LENGTH, STATIC_HEADER_LENGTH};
+use discv5::rpc::{Message, Request, RequestBody, RequestId};
+use keyring::Keyring;
+use log::{error, info, warn};
 
-/// Decodes a packet based on the CLI options.
-pub fn decode(matches: &ArgMatches) {
-    let packet_bytes_string = matches
-        .value_of("packet")
-        .expect("A <packet> must be supplied");
+pub mod diagnose;
+pub mod input;
+pub mod keyring;
+pub mod layout;
+pub mod vectors;
+
+/// The protocol id at the start of every static header.
+pub const PROTOCOL_ID: &[u8] = b"discv5";
+/// The protocol version in the static header.
+pub const PROTOCOL_VERSION: u16 = 1;
+/// The offset of the big-endian version in the static header.
+pub const VERSION_OFFSET: usize = 6;
+/// The offset of the flag in the static header.
+pub const FLAG_OFFSET: usize = 8;
+/// The offset of the message nonce in the static header.
+pub const NONCE_OFFSET: usize = 9;
+/// The offset of the big-endian authdata size in the static header.
+pub const AUTHDATA_SIZE_OFFSET: usize = 21;
+
+/// The flag of message packets.
+pub const MESSAGE_FLAG: u8 = 0;
+/// The flag of WHOAREYOU packets.
+pub const WHOAREYOU_FLAG: u8 = 1;
+/// The flag of handshake packets.
+pub const HANDSHAKE_FLAG: u8 = 2;
+/// The smallest packet, a WHOAREYOU.
+pub const MIN_PACKET_SIZE: usize = IV_LENGTH + STATIC_HEADER_LENGTH + 24;
+/// The largest packet discv5 accepts.
+pub const MAX_PACKET_SIZE: usize = 1280;
 
-    let packet_bytes = hex::decode(packet_bytes_string).expect("Packet bytes must be valid hex");
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
 
-    let node_id = matches
-        .value_of("node-id")
+/// Decodes packets based on the CLI options. Every packet is decoded even if earlier ones fail.
+pub fn decode(matches: &ArgMatches) -> Result<()> {
+    let packet = matches
+        .value_of("packet")
+        .ok_or_else(|| Error::Usage("A <packet> must be supplied".into()))?;
+    let format: input::Format = cli::value(matches, "format")?;
+    let packets = input::read_packets(packet, format, matches.is_present("wrapped"))?;
+    if packets.is_empty() {
+        return Err(Error::Usage("The input contains no packets".into()));
+    }
+
+    let node_id = cli::hex_value(matches, "node-id")?
         .map(|bytes| {
-            enr::NodeId::parse(&hex::decode(bytes).expect("Node Id must be valid hex bytes"))
-                .expect("Must be a valid node-id")
+            enr::NodeId::parse(&bytes).map_err(|e| Error::Parse(format!("Invalid node id: {}", e)))
         })
-        .unwrap_or_else(|| enr::NodeId::parse(&vec![0; 32]).expect("Valid 0 node-id"));
+        .transpose()?;
+    let keyring = matches
+        .value_of("keyring")
+        .map(Keyring::from_file)
+        .transpose()?;
+    if keyring.as_ref().is_some_and(Keyring::is_empty) {
+        return Err(Error::Usage("The keyring contains no nodes".into()));
+    }
+
+    match (&node_id, &keyring) {
+        (Some(node_id), _) => info!("Using decoding node id: {}", node_id),
+        (None, Some(keyring)) => info!(
+            "Trying the {} nodes of the keyring as destinations",
+            keyring.len()
+        ),
+        (None, None) => {
+            warn!("No node id was supplied. Headers are unmasked with the zero node id")
+        }
+    }
+    if packets.len() == 1 {
+        return decode_one(matches, node_id.as_ref(), keyring.as_ref(), &packets[0]);
+    }
+    let mut failures = 0;
+    for (index, packet_bytes) in packets.iter().enumerate() {
+        info!("Packet {} of {}:", index + 1, packets.len());
+        if let Err(e) = decode_one(matches, node_id.as_ref(), keyring.as_ref(), packet_bytes) {
+            error!("{}", e);
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(Error::Protocol(format!(
+            "{} of {} packets failed to be decoded",
+            failures,
+            packets.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Decodes a single packet, printing its layout and diagnosis if requested. Without a node id the
+/// destination is looked up in the keyring.
+fn decode_one(
+    matches: &ArgMatches,
+    node_id: Option<&enr::NodeId>,
+    keyring: Option<&Keyring>,
+    packet_bytes: &[u8],
+) -> Result<()> {
+    let dst_id = match (node_id, keyring) {
+        (Some(node_id), keyring) => {
+            if let Some(keyring) = keyring {
+                info!("Destination: {}", keyring.label(node_id));
+            }
+            *node_id
+        }
+        (None, Some(keyring)) => match keyring.find_destination(packet_bytes) {
+            Some(entry) => {
+                info!("Destination: {}", entry);
+                entry.node_id
+            }
+            None => {
+                warn!("No node of the keyring unmasks the packet header");
+                enr::NodeId::new(&[0; 32])
+            }
+        },
+        (None, None) => enr::NodeId::new(&[0; 32]),
+    };
+
+    if matches.is_present("layout") {
+        layout::print(&dst_id, packet_bytes);
+    }
+
+    let decoded = decode_packet(&dst_id, packet_bytes);
+    if decoded.is_err() && matches.is_present("diagnose") {
+        explain_failure(&dst_id, packet_bytes, matches.is_present("node-id"));
+    }
+    let (packet, authenticated_data) = decoded?;
+    info!("Packet decoded: {:?}", packet);
+    if let (Some(keyring), Some(src_id)) = (keyring, packet.src_id()) {
+        info!("Source: {}", keyring.label(&src_id));
+    }
+    // the authenticated data of a WHOAREYOU is the challenge data signed and used as HKDF salt
+    if packet.is_whoareyou() {
+        info!("Challenge data: 0x{}", hex::encode(&authenticated_data));
+    } else {
+        info!("Authenticated data: 0x{}", hex::encode(&authenticated_data));
+    }
+    Ok(())
+}
+
+/// Decodes a packet sent to the node with `node_id`, which unmasks the packet header. Returns the
+/// packet and its authenticated data.
+pub fn decode_packet(node_id: &enr::NodeId, packet_bytes: &[u8]) -> Result<(Packet, Vec<u8>)> {
+    Packet::decode(node_id, packet_bytes)
+        .map_err(|e| Error::Protocol(format!("Packet failed to be decoded: {:?}", e)))
+}
+
+/// Logs the likely cause of a decoding failure and what to try next.
+fn explain_failure(node_id: &enr::NodeId, packet_bytes: &[u8], node_id_supplied: bool) {
+    match diagnose::diagnose(node_id, packet_bytes) {
+        Some(cause) => {
+            error!("Likely cause: {}", cause);
+            if cause == diagnose::Cause::WrongNodeId && !node_id_supplied {
+                error!("No node id was supplied, so the header was unmasked with the zero node id");
+            }
+            info!("Next step: {}", cause.next_step());
+        }
+        None => {
+            error!("Likely cause: The header is well-formed, so the authdata contents are invalid, such as the ENR of a handshake");
+            info!("Next step: Inspect the authdata fields with packet decode --layout");
+        }
+    }
+}
+
+/// Masks or unmasks header bytes of a packet sent to `dst_id`. The mask is AES-128-CTR keyed with
+/// the first 16 bytes of the node id, so applying it twice restores the input. `header` must start
+/// directly after the IV.
+pub fn apply_header_mask(dst_id: &enr::NodeId, iv: &[u8; IV_LENGTH], header: &mut [u8]) {
+    let mut key = [0u8; 16];
+    key.copy_from_slice(&dst_id.raw()[..16]);
+    let mut cipher = Aes128Ctr::new((&key).into(), iv.into());
+    cipher.apply_keystream(header);
+}
+
+/// Unmasks as much of the header of a packet sent to `dst_id` as the packet contains. Returns the
+/// packet with its header unmasked and the header length declared by the static header, if it is
+/// complete.
+pub fn unmask_header(dst_id: &enr::NodeId, packet: &[u8]) -> (Vec<u8>, Option<usize>) {
+    let mut unmasked = packet.to_vec();
+    if packet.len() <= IV_LENGTH {
+        return (unmasked, None);
+    }
+    let mut iv = [0u8; IV_LENGTH];
+    iv.copy_from_slice(&packet[..IV_LENGTH]);
+
+    let static_end = packet.len().min(IV_LENGTH + STATIC_HEADER_LENGTH);
+    apply_header_mask(dst_id, &iv, &mut unmasked[IV_LENGTH..static_end]);
+    if static_end < IV_LENGTH + STATIC_HEADER_LENGTH {
+        return (unmasked, None);
+    }
+    let size_offset = IV_LENGTH + AUTHDATA_SIZE_OFFSET;
+    let authdata_size =
+        u16::from_be_bytes([unmasked[size_offset], unmasked[size_offset + 1]]) as usize;
+    let header_length = STATIC_HEADER_LENGTH + authdata_size;
 
-    info!("Using decoding node id: {}", node_id);
+    // the keystream runs on from the static header, so the header is unmasked in one pass
+    let header_end = packet.len().min(IV_LENGTH + header_length);
+    unmasked.copy_from_slice(packet);
+    apply_header_mask(dst_id, &iv, &mut unmasked[IV_LENGTH..header_end]);
+    (unmasked, Some(header_length))
+}
+
+/// Encodes the plaintext of a PING message: its type followed by the RLP list of the request id
+/// and the ENR sequence number.
+pub fn encode_ping(request_id: &[u8], enr_seq: u64) -> Vec<u8> {
+    Request {
+        id: RequestId(request_id.to_vec()),
+        body: RequestBody::Ping { enr_seq },
+    }
+    .encode()
+}
 
-    match Packet::decode(&node_id, &packet_bytes) {
-        Ok(p) => info!("Packet decoded: {:?}", p),
-        Err(e) => error!("Packet failed to be decoded. Error: {:?}", e),
+/// Decodes the plaintext of a message into a readable form, or the reason it is invalid.
+pub fn describe_message(plaintext: &[u8]) -> String {
+    match Message::decode(plaintext) {
+        Ok(message) => message.to_string(),
+        Err(e) => format!("invalid message: {:?}", e),
     }
 }
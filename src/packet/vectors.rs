@@ -0,0 +1,572 @@
+//! The discv5 wire test vectors, reproduced from their published inputs.
+//!
+//! Packets are encoded with discv5 and decoded with [`decode_packet`](super::decode_packet), so a
+//! mismatch is reported for the decoded field that differs as well as for the raw packet.
+
+use super::decode_packet;
+use crate::cli::hex0x;
+use crate::crypto;
+use crate::error::{Error, Result};
+use crate::server;
+use discv5::enr::{CombinedKey, EnrKey, NodeId};
+use discv5::packet::{Packet, PacketHeader, PacketKind};
+use log::{error, info};
+
+type Enr = discv5::enr::Enr<CombinedKey>;
+
+/// The static key of node A, the sender of every packet.
+pub(crate) const NODE_A_KEY: &str =
+    "eef77acb6c6a6eebc5b363a475ac583ec7eccdb42b6481424c60f59aa326547f";
+/// The static key of node B, the recipient of every packet.
+pub(crate) const NODE_B_KEY: &str =
+    "66fb62bfbd66b9177a138c1e5cddbe4f7c30c343e94e68df8769459cb1cde628";
+pub(crate) const NODE_A_ID: &str =
+    "aaaa8419e9f49d0083561b48287df592939a8d19947d8c0ef88f2a4856a69fbb";
+pub(crate) const NODE_B_ID: &str =
+    "bbbb9d047f0488c0b5a93c1c3f2d8bafc7c8ff337024a55434a0d0555de64db9";
+/// The ENR of node A sent in a handshake.
+const NODE_A_ENR: &str = "enr:-H24QBfhsHORjaMtZAZCx2LA4ngWmOSXH4qzmnd0atrYPwHnb_yHTFkkgIu-fFCJCILCuKASh6CwgxLR1ToX1Rf16ycBgmlkgnY0gmlwhH8AAAGJc2VjcDI1NmsxoQMT0UIR4Ch7I2GhYViQqbUhIIBUbQoleuTP-Wz1NJksuQ";
+
+/// The WHOAREYOU challenge data of the vectors with an ENR sequence number of 0.
+pub(crate) const CHALLENGE_DATA: &str = "000000000000000000000000000000006469736376350001010102030405060708090a0b0c00180102030405060708090a0b0c0d0e0f100000000000000000";
+/// The WHOAREYOU challenge data of the vectors with an ENR sequence number of 1.
+pub(crate) const CHALLENGE_DATA_SEQ_1: &str = "000000000000000000000000000000006469736376350001010102030405060708090a0b0c00180102030405060708090a0b0c0d0e0f100000000000000001";
+/// The ephemeral key of the handshakes.
+pub(crate) const HANDSHAKE_EPHEMERAL_KEY: &str =
+    "0288ef00023598499cb6c940146d050d2b1fb914198c327f76aad590bead68b6";
+/// The public key of the ephemeral key of the handshakes.
+const HANDSHAKE_EPHEMERAL_PUBKEY: &str =
+    "039a003ba6517b473fa0cd74aefe99dadfdb34627f90fec6362df85803908f53a5";
+/// The initiator key of the handshake without an ENR.
+const HANDSHAKE_READ_KEY: &str = "4f9fac6de7567d1e3b1241dffe90f662";
+/// The initiator key of the handshake with an ENR.
+const HANDSHAKE_ENR_READ_KEY: &str = "53b1c075f41876423154e157470c2f48";
+/// The message nonce of the packets that carry a PING.
+const PING_NONCE: [u8; 12] = [0xff; 12];
+/// The request id of every PING.
+const PING_REQUEST_ID: [u8; 4] = [0, 0, 0, 1];
+/// The request nonce and id nonce of the WHOAREYOU packets.
+const REQUEST_NONCE: &str = "0102030405060708090a0b0c";
+const ID_NONCE: &str = "0102030405060708090a0b0c0d0e0f10";
+
+pub(super) const PING_PACKET: &str = "00000000000000000000000000000000088b3d4342774649325f313964a39e55ea96c005ad52be8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d34c4f53245d08dab84102ed931f66d1492acb308fa1c6715b9d139b81acbdcc";
+pub(super) const WHOAREYOU_PACKET: &str = "00000000000000000000000000000000088b3d434277464933a1ccc59f5967ad1d6035f15e528627dde75cd68292f9e6c27d6b66c8100a873fcbaed4e16b8d";
+pub(crate) const HANDSHAKE_PACKET: &str = "00000000000000000000000000000000088b3d4342774649305f313964a39e55ea96c005ad521d8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d34c4f53245d08da4bb252012b2cba3f4f374a90a75cff91f142fa9be3e0a5f3ef268ccb9065aeecfd67a999e7fdc137e062b2ec4a0eb92947f0d9a74bfbf44dfba776b21301f8b65efd5796706adff216ab862a9186875f9494150c4ae06fa4d1f0396c93f215fa4ef524f1eadf5f0f4126b79336671cbcf7a885b1f8bd2a5d839cf8";
+pub(super) const HANDSHAKE_ENR_PACKET: &str = "00000000000000000000000000000000088b3d4342774649305f313964a39e55ea96c005ad539c8c7560413a7008f16c9e6d2f43bbea8814a546b7409ce783d34c4f53245d08da4bb23698868350aaad22e3ab8dd034f548a1c43cd246be98562fafa0a1fa86d8e7a3b95ae78cc2b988ded6a5b59eb83ad58097252188b902b21481e30e5e285f19735796706adff216ab862a9186875f9494150c4ae06fa4d1f0396c93f215fa4ef524e0ed04c3c21e39b1868e1ca8105e585ec17315e755e6cfc4dd6cb7fd8e1a1f55e49b4b5eb024221482105346f3c82b15fdaae36a3bb12a494683b4a3c7f2ae41306252fed84785e2bbff3b022812d0882f06978df84a80d443972213342d04b9048fc3b1d5fcb1df0f822152eced6da4d3f6df27e70e4539717307a0208cd208d65093ccab5aa596a34d7511401987662d8cf62b139471";
+
+/// A value computed from the inputs of a vector.
+struct Output {
+    name: &'static str,
+    actual: String,
+    /// The published value.
+    expected: String,
+}
+
+impl Output {
+    fn checked(name: &'static str, actual: String, expected: String) -> Self {
+        Output {
+            name,
+            actual,
+            expected,
+        }
+    }
+}
+
+/// A published test vector.
+struct Vector {
+    name: &'static str,
+    inputs: Vec<(&'static str, String)>,
+    outputs: Vec<Output>,
+}
+
+/// Prints every test vector computed from its published inputs.
+pub fn generate() -> Result<()> {
+    for vector in vectors()? {
+        info!("{}", vector.name);
+        for (name, value) in &vector.inputs {
+            info!("  {} = {}", name, value);
+        }
+        for output in &vector.outputs {
+            info!("  {} = {}", output.name, output.actual);
+        }
+    }
+    Ok(())
+}
+
+/// Computes every test vector and compares the results to the published values field by field.
+pub fn verify() -> Result<()> {
+    let vectors = vectors()?;
+    let mut failed = 0;
+    for vector in &vectors {
+        let mut mismatches = 0;
+        for output in &vector.outputs {
+            if output.expected != output.actual {
+                error!(
+                    "{}: {} mismatch. Expected: {}, computed: {}",
+                    vector.name, output.name, output.expected, output.actual
+                );
+                mismatches += 1;
+            }
+        }
+        if mismatches == 0 {
+            info!("{}: OK", vector.name);
+        } else {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(Error::CheckFailed(format!(
+            "{} of {} test vectors do not match",
+            failed,
+            vectors.len()
+        )));
+    }
+    info!("All {} test vectors match", vectors.len());
+    Ok(())
+}
+
+fn vectors() -> Result<Vec<Vector>> {
+    Ok(vec![
+        node_ids()?,
+        ecdh()?,
+        key_derivation()?,
+        id_signature()?,
+        encryption()?,
+        ping_packet()?,
+        whoareyou_packet()?,
+        handshake_packet(false)?,
+        handshake_packet(true)?,
+    ])
+}
+
+fn node_ids() -> Result<Vector> {
+    let node_a = NodeId::from(server::key_from_bytes(&bytes(NODE_A_KEY))?.public());
+    let node_b = NodeId::from(server::key_from_bytes(&bytes(NODE_B_KEY))?.public());
+
+    Ok(Vector {
+        name: "Node ids",
+        inputs: vec![
+            ("node-a-key", prefixed(NODE_A_KEY)),
+            ("node-b-key", prefixed(NODE_B_KEY)),
+        ],
+        outputs: vec![
+            Output::checked("node-id-a", hex0x(&node_a.raw()), prefixed(NODE_A_ID)),
+            Output::checked("node-id-b", hex0x(&node_b.raw()), prefixed(NODE_B_ID)),
+        ],
+    })
+}
+
+fn ecdh() -> Result<Vector> {
+    let secret_key = "fb757dc581730490a1d7a00deea65e9b1936924caaea8f44d476014856b68736";
+    let public_key = "039961e4c2356d61bedb83052c115d311acb3a96f5777296dcf297351130266231";
+    let shared_secret = crypto::ecdh(
+        &crypto::public_key(&bytes(public_key))?,
+        &crypto::secret_key(&bytes(secret_key))?,
+    )?;
+
+    Ok(Vector {
+        name: "ECDH",
+        inputs: vec![
+            ("public-key", prefixed(public_key)),
+            ("secret-key", prefixed(secret_key)),
+        ],
+        outputs: vec![Output::checked(
+            "shared-secret",
+            hex0x(&shared_secret),
+            prefixed("033b11a2a1f214567e1537ce5e509ffd9b21373247f2a3ff6841f4976f53165e7e"),
+        )],
+    })
+}
+
+fn key_derivation() -> Result<Vector> {
+    let ephemeral_key = "fb757dc581730490a1d7a00deea65e9b1936924caaea8f44d476014856b68736";
+    let dest_pubkey = "0317931e6e0840220642f230037d285d122bc59063221ef3226b1f403ddc69ca91";
+    let keys = crypto::derive_keys(
+        &crypto::public_key(&bytes(dest_pubkey))?,
+        &crypto::secret_key(&bytes(ephemeral_key))?,
+        &bytes(CHALLENGE_DATA),
+        &node_id(NODE_A_ID)?,
+        &node_id(NODE_B_ID)?,
+    )?;
+
+    Ok(Vector {
+        name: "Key derivation",
+        inputs: vec![
+            ("ephemeral-key", prefixed(ephemeral_key)),
+            ("dest-pubkey", prefixed(dest_pubkey)),
+            ("node-id-a", prefixed(NODE_A_ID)),
+            ("node-id-b", prefixed(NODE_B_ID)),
+            ("challenge-data", prefixed(CHALLENGE_DATA)),
+        ],
+        outputs: vec![
+            Output::checked(
+                "initiator-key",
+                hex0x(&keys.initiator_key),
+                prefixed("dccc82d81bd610f4f76d3ebe97a40571"),
+            ),
+            Output::checked(
+                "recipient-key",
+                hex0x(&keys.recipient_key),
+                prefixed("ac74bb8773749920b0d3a8881c173ec5"),
+            ),
+        ],
+    })
+}
+
+fn id_signature() -> Result<Vector> {
+    let static_key = "fb757dc581730490a1d7a00deea65e9b1936924caaea8f44d476014856b68736";
+    let ephemeral_pubkey = "039961e4c2356d61bedb83052c115d311acb3a96f5777296dcf297351130266231";
+    let secret_key = crypto::secret_key(&bytes(static_key))?;
+    let signature = crypto::id_sign(
+        &secret_key,
+        &bytes(CHALLENGE_DATA),
+        &bytes(ephemeral_pubkey),
+        &node_id(NODE_B_ID)?,
+    );
+    let valid = crypto::id_verify(
+        &secp256k1::PublicKey::from_secret_key(&secret_key),
+        &signature,
+        &bytes(CHALLENGE_DATA),
+        &bytes(ephemeral_pubkey),
+        &node_id(NODE_B_ID)?,
+    )?;
+
+    Ok(Vector {
+        name: "ID nonce signing",
+        inputs: vec![
+            ("static-key", prefixed(static_key)),
+            ("challenge-data", prefixed(CHALLENGE_DATA)),
+            ("ephemeral-pubkey", prefixed(ephemeral_pubkey)),
+            ("node-id-B", prefixed(NODE_B_ID)),
+        ],
+        outputs: vec![
+            Output::checked(
+                "id-signature",
+                hex0x(&signature),
+                prefixed("94852a1e2318c4e5e9d422c98eaf19d1d90d876b29cd06ca7cb7546d0fff7b484fe86c09a064fe72bdbef73ba8e9c34df0cd2b53e9d65528c2c7f336d5dfc6e6"),
+            ),
+            Output::checked("id-signature-valid", valid.to_string(), true.to_string()),
+        ],
+    })
+}
+
+fn encryption() -> Result<Vector> {
+    let key = "9f2d77db7004bf8a1a85107ac686990b";
+    let nonce = "27b5af763c446acd2749fe8e";
+    let plaintext = "01c20101";
+    let ad = "93a7400fa0d6a694ebc24d5cf570f65d04215b6ac00757875e3f3a5f42107903";
+    let ciphertext = crypto::encrypt(&array(key), &array(nonce), &bytes(plaintext), &bytes(ad))?;
+
+    Ok(Vector {
+        name: "Encryption/Decryption",
+        inputs: vec![
+            ("encryption-key", prefixed(key)),
+            ("nonce", prefixed(nonce)),
+            ("pt", prefixed(plaintext)),
+            ("ad", prefixed(ad)),
+        ],
+        outputs: vec![Output::checked(
+            "message-ciphertext",
+            hex0x(&ciphertext),
+            prefixed("a5d12a2d94b8ccb3ba55558229867dc13bfa3648"),
+        )],
+    })
+}
+
+fn ping_packet() -> Result<Vector> {
+    let src_id = node_id(NODE_A_ID)?;
+    let dst_id = node_id(NODE_B_ID)?;
+    let read_key = [0u8; crypto::KEY_LENGTH];
+    let plaintext = super::encode_ping(&PING_REQUEST_ID, 2);
+
+    let packet = Packet {
+        iv: 0,
+        header: PacketHeader {
+            message_nonce: PING_NONCE,
+            kind: PacketKind::Message { src_id },
+        },
+        message: Vec::new(),
+    };
+    let encoded = encrypt_and_encode(packet, &dst_id, &read_key, &plaintext)?;
+
+    Ok(Vector {
+        name: "Ping message packet (flag 0)",
+        inputs: vec![
+            ("src-node-id", prefixed(NODE_A_ID)),
+            ("dest-node-id", prefixed(NODE_B_ID)),
+            ("nonce", hex0x(&PING_NONCE)),
+            ("read-key", hex0x(&read_key)),
+            ("ping.req-id", hex0x(&PING_REQUEST_ID)),
+            ("ping.enr-seq", "2".into()),
+        ],
+        outputs: packet_outputs(&dst_id, &encoded, PING_PACKET, Some(&read_key)),
+    })
+}
+
+fn whoareyou_packet() -> Result<Vector> {
+    let dst_id = node_id(NODE_B_ID)?;
+
+    let packet = Packet {
+        iv: 0,
+        header: PacketHeader {
+            message_nonce: array(REQUEST_NONCE),
+            kind: PacketKind::WhoAreYou {
+                id_nonce: array(ID_NONCE),
+                enr_seq: 0,
+            },
+        },
+        message: Vec::new(),
+    };
+    let challenge_data = packet.authenticated_data();
+    let encoded = packet.encode(&dst_id);
+
+    let mut outputs = vec![Output::checked(
+        "whoareyou.challenge-data",
+        hex0x(&challenge_data),
+        prefixed(CHALLENGE_DATA),
+    )];
+    outputs.extend(packet_outputs(&dst_id, &encoded, WHOAREYOU_PACKET, None));
+
+    Ok(Vector {
+        name: "WHOAREYOU packet (flag 1)",
+        inputs: vec![
+            ("src-node-id", prefixed(NODE_A_ID)),
+            ("dest-node-id", prefixed(NODE_B_ID)),
+            ("whoareyou.request-nonce", prefixed(REQUEST_NONCE)),
+            ("whoareyou.id-nonce", prefixed(ID_NONCE)),
+            ("whoareyou.enr-seq", "0".into()),
+        ],
+        outputs,
+    })
+}
+
+/// The handshake from node A answering node B's WHOAREYOU, with A's ENR if B's challenge had an
+/// ENR sequence number of 0.
+fn handshake_packet(with_enr: bool) -> Result<Vector> {
+    let src_id = node_id(NODE_A_ID)?;
+    let dst_id = node_id(NODE_B_ID)?;
+    let (challenge_data, read_key, expected_packet, enr_record) = if with_enr {
+        let enr = NODE_A_ENR
+            .parse::<Enr>()
+            .map_err(|e| Error::Parse(format!("Invalid test vector ENR: {}", e)))?;
+        (
+            CHALLENGE_DATA,
+            HANDSHAKE_ENR_READ_KEY,
+            HANDSHAKE_ENR_PACKET,
+            Some(enr),
+        )
+    } else {
+        (
+            CHALLENGE_DATA_SEQ_1,
+            HANDSHAKE_READ_KEY,
+            HANDSHAKE_PACKET,
+            None,
+        )
+    };
+
+    let static_key = crypto::secret_key(&bytes(NODE_A_KEY))?;
+    let recipient_key = crypto::secret_key(&bytes(NODE_B_KEY))?;
+    let ephemeral_key = crypto::secret_key(&bytes(HANDSHAKE_EPHEMERAL_KEY))?;
+    let ephemeral_pubkey =
+        secp256k1::PublicKey::from_secret_key(&ephemeral_key).serialize_compressed();
+
+    let keys = crypto::derive_keys(
+        &secp256k1::PublicKey::from_secret_key(&recipient_key),
+        &ephemeral_key,
+        &bytes(challenge_data),
+        &src_id,
+        &dst_id,
+    )?;
+    let id_signature = crypto::id_sign(
+        &static_key,
+        &bytes(challenge_data),
+        &ephemeral_pubkey,
+        &dst_id,
+    );
+    let plaintext = super::encode_ping(&PING_REQUEST_ID, 1);
+
+    let packet = Packet {
+        iv: 0,
+        header: PacketHeader {
+            message_nonce: PING_NONCE,
+            kind: PacketKind::Handshake {
+                src_id,
+                id_nonce_sig: id_signature.to_vec(),
+                ephem_pubkey: ephemeral_pubkey.to_vec(),
+                enr_record: enr_record.clone(),
+            },
+        },
+        message: Vec::new(),
+    };
+    let encoded = encrypt_and_encode(packet, &dst_id, &keys.initiator_key, &plaintext)?;
+
+    let mut inputs = vec![
+        ("src-node-id", prefixed(NODE_A_ID)),
+        ("dest-node-id", prefixed(NODE_B_ID)),
+        ("nonce", hex0x(&PING_NONCE)),
+        ("ping.req-id", hex0x(&PING_REQUEST_ID)),
+        ("ping.enr-seq", "1".into()),
+        ("whoareyou.challenge-data", prefixed(challenge_data)),
+        ("ephemeral-key", prefixed(HANDSHAKE_EPHEMERAL_KEY)),
+    ];
+    if let Some(enr) = &enr_record {
+        inputs.push(("enr", enr.to_base64()));
+    }
+
+    let mut outputs = vec![
+        Output::checked(
+            "ephemeral-pubkey",
+            hex0x(&ephemeral_pubkey),
+            prefixed(HANDSHAKE_EPHEMERAL_PUBKEY),
+        ),
+        Output::checked("read-key", hex0x(&keys.initiator_key), prefixed(read_key)),
+    ];
+    outputs.extend(packet_outputs(
+        &dst_id,
+        &encoded,
+        expected_packet,
+        Some(&keys.initiator_key),
+    ));
+
+    Ok(Vector {
+        name: if with_enr {
+            "Ping handshake message packet with ENR (flag 2)"
+        } else {
+            "Ping handshake message packet (flag 2)"
+        },
+        inputs,
+        outputs,
+    })
+}
+
+/// Encrypts `plaintext` as the message of `packet`, authenticating the header, and encodes it.
+fn encrypt_and_encode(
+    mut packet: Packet,
+    dst_id: &NodeId,
+    key: &[u8; crypto::KEY_LENGTH],
+    plaintext: &[u8],
+) -> Result<Vec<u8>> {
+    packet.message = crypto::encrypt(
+        key,
+        &packet.header.message_nonce,
+        plaintext,
+        &packet.authenticated_data(),
+    )?;
+    Ok(packet.encode(dst_id))
+}
+
+/// Compares an encoded packet to the published one, in full and per decoded field.
+fn packet_outputs(
+    dst_id: &NodeId,
+    actual: &[u8],
+    expected_hex: &str,
+    read_key: Option<&[u8; crypto::KEY_LENGTH]>,
+) -> Vec<Output> {
+    let expected = bytes(expected_hex);
+    let mut outputs = vec![Output::checked("packet", hex0x(actual), hex0x(&expected))];
+
+    let expected_fields = decoded_fields(dst_id, &expected, read_key);
+    for (name, actual) in decoded_fields(dst_id, actual, read_key) {
+        let expected = expected_fields
+            .iter()
+            .find(|(expected_name, _)| *expected_name == name)
+            .map_or_else(|| "absent".to_string(), |(_, value)| value.clone());
+        outputs.push(Output::checked(name, actual, expected));
+    }
+    outputs
+}
+
+/// Decodes a packet into named fields, decrypting the message if a key is given.
+fn decoded_fields(
+    dst_id: &NodeId,
+    packet_bytes: &[u8],
+    read_key: Option<&[u8; crypto::KEY_LENGTH]>,
+) -> Vec<(&'static str, String)> {
+    let (packet, authenticated_data) = match decode_packet(dst_id, packet_bytes) {
+        Ok(decoded) => decoded,
+        Err(e) => return vec![("decoded", e.to_string())],
+    };
+
+    let mut fields = vec![
+        ("iv", format!("0x{:032x}", packet.iv)),
+        ("nonce", hex0x(&packet.header.message_nonce)),
+    ];
+    match &packet.header.kind {
+        PacketKind::Message { src_id } => {
+            fields.push(("flag", "0".into()));
+            fields.push(("src-id", hex0x(&src_id.raw())));
+        }
+        PacketKind::WhoAreYou { id_nonce, enr_seq } => {
+            fields.push(("flag", "1".into()));
+            fields.push(("id-nonce", hex0x(id_nonce)));
+            fields.push(("enr-seq", enr_seq.to_string()));
+        }
+        PacketKind::Handshake {
+            src_id,
+            id_nonce_sig,
+            ephem_pubkey,
+            enr_record,
+        } => {
+            fields.push(("flag", "2".into()));
+            fields.push(("src-id", hex0x(&src_id.raw())));
+            fields.push(("id-signature", hex0x(id_nonce_sig)));
+            fields.push(("ephemeral-pubkey", hex0x(ephem_pubkey)));
+            fields.push((
+                "record",
+                enr_record
+                    .as_ref()
+                    .map_or_else(|| "none".to_string(), |enr| enr.to_base64()),
+            ));
+        }
+    }
+
+    if !packet.message.is_empty() {
+        fields.push(("message-ciphertext", hex0x(&packet.message)));
+    }
+    if let Some(key) = read_key {
+        let plaintext = crypto::decrypt(
+            key,
+            &packet.header.message_nonce,
+            &packet.message,
+            &authenticated_data,
+        );
+        match plaintext {
+            Ok(plaintext) => {
+                fields.push(("message-plaintext", hex0x(&plaintext)));
+                fields.push(("message", super::describe_message(&plaintext)));
+            }
+            Err(e) => fields.push(("message-plaintext", e.to_string())),
+        }
+    }
+    fields
+}
+
+/// The node id of a published vector.
+fn node_id(hex_id: &str) -> Result<NodeId> {
+    NodeId::parse(&bytes(hex_id)).map_err(|e| Error::Parse(format!("Invalid node id: {}", e)))
+}
+
+/// Decodes a hex constant of the vectors.
+fn bytes(hex_value: &str) -> Vec<u8> {
+    hex::decode(hex_value).expect("The test vectors are valid hex")
+}
+
+/// Decodes a fixed length hex constant of the vectors.
+fn array<const N: usize>(hex_value: &str) -> [u8; N] {
+    let mut array = [0u8; N];
+    array.copy_from_slice(&bytes(hex_value));
+    array
+}
+
+fn prefixed(hex_value: &str) -> String {
+    format!("0x{}", hex_value)
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn published_vectors_match() {
+        super::verify().unwrap();
+    }
+}
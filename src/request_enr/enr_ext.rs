@@ -1,120 +1,325 @@
+use crate::enr::fields;
+use crate::error::{Error, Result};
 use discv5::enr::{CombinedKey, CombinedPublicKey};
-use libp2p_core::{identity::Keypair, multiaddr::Protocol, Multiaddr, PeerId};
+use libp2p_core::{multiaddr::Protocol, Multiaddr};
+use libp2p_identity::{self as identity, Keypair, PeerId};
+use std::net::IpAddr;
 
 type Enr = discv5::enr::Enr<CombinedKey>;
 
 /// Extend ENR for libp2p types.
 pub trait EnrExt {
     /// The libp2p `PeerId` for the record.
+    #[deprecated(note = "panics if libp2p rejects the public key, use `try_peer_id`")]
     fn peer_id(&self) -> PeerId;
 
     /// Returns a list of multiaddrs if the ENR has an `ip` and either a `tcp` or `udp` key **or** an `ip6` and either a `tcp6` or `udp6`.
     /// The vector remains empty if these fields are not defined.
+    #[deprecated(note = "panics if libp2p rejects the public key, use `try_multiaddr`")]
     fn multiaddr(&self) -> Vec<Multiaddr>;
+
+    /// The libp2p `PeerId` for the record, or an error if its public key has no libp2p
+    /// equivalent.
+    fn try_peer_id(&self) -> Result<PeerId>;
+
+    /// Returns a list of multiaddrs if the ENR has an `ip` and either a `tcp`, `udp` or `quic` key **or** an `ip6` and either a `tcp6`, `udp6` or `quic6`.
+    /// The vector remains empty if these fields are not defined, and an error is returned if they
+    /// cannot be built.
+    fn try_multiaddr(&self) -> Result<Vec<Multiaddr>>;
+
+    /// The UDP multiaddrs the node can be discovered at.
+    fn discovery_multiaddrs(&self) -> Result<Vec<Multiaddr>>;
+
+    /// The TCP and QUIC multiaddrs the node accepts libp2p connections at.
+    fn transport_multiaddrs(&self) -> Result<Vec<Multiaddr>>;
 }
 
 /// Extend ENR CombinedPublicKey for libp2p types.
 pub trait CombinedKeyPublicExt {
     /// Converts the publickey into a peer id, without consuming the key.
+    #[deprecated(note = "panics if libp2p rejects the key, use `try_into_peer_id`")]
+    #[allow(clippy::wrong_self_convention)]
     fn into_peer_id(&self) -> PeerId;
+
+    /// Converts the publickey into a peer id, or returns an error if libp2p rejects the key.
+    fn try_into_peer_id(&self) -> Result<PeerId>;
 }
 
 /// Extend ENR CombinedKey for conversion to libp2p keys.
 pub trait CombinedKeyExt {
     /// Converts a libp2p key into an ENR combined key.
-    fn from_libp2p(key: &Keypair) -> Result<CombinedKey, &'static str>;
+    fn from_libp2p(key: &Keypair) -> std::result::Result<CombinedKey, &'static str>;
 }
 
 impl EnrExt for Enr {
-    /// The libp2p `PeerId` for the record.
     fn peer_id(&self) -> PeerId {
-        self.public_key().into_peer_id()
+        self.try_peer_id().expect("valid public key")
     }
 
-    /// Returns a list of multiaddrs if the ENR has an `ip` and either a `tcp` or `udp` key **or** an `ip6` and either a `tcp6` or `udp6`.
-    /// The vector remains empty if these fields are not defined.
-    ///
-    /// Note: Only available with the `libp2p` feature flag.
     fn multiaddr(&self) -> Vec<Multiaddr> {
-        let peer_id = self.peer_id();
+        self.try_multiaddr().expect("valid public key and ports")
+    }
+
+    fn try_peer_id(&self) -> Result<PeerId> {
+        self.public_key().try_into_peer_id()
+    }
+
+    fn try_multiaddr(&self) -> Result<Vec<Multiaddr>> {
+        let peer_id = self.try_peer_id()?;
 
         let mut multiaddrs: Vec<Multiaddr> = Vec::new();
         if let Some(ip) = self.ip4() {
             if let Some(udp) = self.udp4() {
-                let mut multiaddr: Multiaddr = ip.into();
-                multiaddr.push(Protocol::Udp(udp));
-                multiaddr.push(Protocol::P2p(peer_id.clone().into()));
-                multiaddrs.push(multiaddr);
-            }
-
-            if let Some(tcp) = self.tcp4() {
-                let mut multiaddr: Multiaddr = ip.into();
-                multiaddr.push(Protocol::Tcp(tcp));
-                multiaddr.push(Protocol::P2p(peer_id.clone().into()));
-                multiaddrs.push(multiaddr);
+                multiaddrs.push(build_multiaddr(ip.into(), &[Protocol::Udp(udp)], peer_id));
             }
+            multiaddrs.extend(transport_multiaddrs(
+                self,
+                ip.into(),
+                self.tcp4(),
+                "quic",
+                peer_id,
+            )?);
         }
         if let Some(ip6) = self.ip6() {
             if let Some(udp6) = self.udp6() {
-                let mut multiaddr: Multiaddr = ip6.into();
-                multiaddr.push(Protocol::Udp(udp6));
-                multiaddr.push(Protocol::P2p(peer_id.clone().into()));
-                multiaddrs.push(multiaddr);
+                multiaddrs.push(build_multiaddr(ip6.into(), &[Protocol::Udp(udp6)], peer_id));
             }
+            multiaddrs.extend(transport_multiaddrs(
+                self,
+                ip6.into(),
+                self.tcp6(),
+                "quic6",
+                peer_id,
+            )?);
+        }
+        Ok(multiaddrs)
+    }
 
-            if let Some(tcp6) = self.tcp6() {
-                let mut multiaddr: Multiaddr = ip6.into();
-                multiaddr.push(Protocol::Tcp(tcp6));
-                multiaddr.push(Protocol::P2p(peer_id.clone().into()));
-                multiaddrs.push(multiaddr);
-            }
+    fn discovery_multiaddrs(&self) -> Result<Vec<Multiaddr>> {
+        let peer_id = self.try_peer_id()?;
+
+        let mut multiaddrs: Vec<Multiaddr> = Vec::new();
+        if let (Some(ip), Some(udp)) = (self.ip4(), self.udp4()) {
+            multiaddrs.push(build_multiaddr(ip.into(), &[Protocol::Udp(udp)], peer_id));
         }
-        multiaddrs
+        if let (Some(ip6), Some(udp6)) = (self.ip6(), self.udp6()) {
+            multiaddrs.push(build_multiaddr(ip6.into(), &[Protocol::Udp(udp6)], peer_id));
+        }
+        Ok(multiaddrs)
+    }
+
+    fn transport_multiaddrs(&self) -> Result<Vec<Multiaddr>> {
+        let peer_id = self.try_peer_id()?;
+
+        let mut multiaddrs: Vec<Multiaddr> = Vec::new();
+        if let Some(ip) = self.ip4() {
+            multiaddrs.extend(transport_multiaddrs(
+                self,
+                ip.into(),
+                self.tcp4(),
+                "quic",
+                peer_id,
+            )?);
+        }
+        if let Some(ip6) = self.ip6() {
+            multiaddrs.extend(transport_multiaddrs(
+                self,
+                ip6.into(),
+                self.tcp6(),
+                "quic6",
+                peer_id,
+            )?);
+        }
+        Ok(multiaddrs)
     }
 }
 
+/// The TCP and QUIC multiaddrs of one IP of a record.
+fn transport_multiaddrs(
+    enr: &Enr,
+    ip: IpAddr,
+    tcp: Option<u16>,
+    quic_key: &str,
+    peer_id: PeerId,
+) -> Result<Vec<Multiaddr>> {
+    let mut multiaddrs = Vec::new();
+    if let Some(tcp) = tcp {
+        multiaddrs.push(build_multiaddr(ip, &[Protocol::Tcp(tcp)], peer_id));
+    }
+    if let Some(quic) = quic_port(enr, quic_key)? {
+        multiaddrs.push(build_multiaddr(
+            ip,
+            &[Protocol::Udp(quic), Protocol::QuicV1],
+            peer_id,
+        ));
+    }
+    Ok(multiaddrs)
+}
+
+/// Builds `/<ip>/<protocols>/p2p/<peer-id>`.
+fn build_multiaddr(ip: IpAddr, protocols: &[Protocol], peer_id: PeerId) -> Multiaddr {
+    let mut multiaddr: Multiaddr = ip.into();
+    for protocol in protocols {
+        multiaddr.push(protocol.clone());
+    }
+    multiaddr.push(Protocol::P2p(peer_id.into()));
+    multiaddr
+}
+
+/// Reads a QUIC port, which the ENR crate has no accessor for.
+fn quic_port(enr: &Enr, key: &str) -> Result<Option<u16>> {
+    enr.get(key)
+        .map(|value| {
+            fields::port(value).map_err(|e| Error::Parse(format!("Invalid {} port: {}", key, e)))
+        })
+        .transpose()
+}
+
 impl CombinedKeyPublicExt for CombinedPublicKey {
-    /// Converts the publickey into a peer id, without consuming the key.
-    ///
-    /// This is only available with the `libp2p` feature flag.
     fn into_peer_id(&self) -> PeerId {
-        match self {
+        self.try_into_peer_id().expect("valid public key")
+    }
+
+    fn try_into_peer_id(&self) -> Result<PeerId> {
+        let libp2p_pk: identity::PublicKey = match self {
             Self::Secp256k1(pk) => {
                 let pk_bytes = pk.to_bytes();
-                let libp2p_pk = libp2p_core::PublicKey::Secp256k1(
-                    libp2p_core::identity::secp256k1::PublicKey::decode(&pk_bytes)
-                        .expect("valid public key"),
-                );
-                PeerId::from_public_key(libp2p_pk)
+                identity::secp256k1::PublicKey::try_from_bytes(&pk_bytes)
+                    .map_err(|e| Error::Parse(format!("Invalid secp256k1 public key: {}", e)))?
+                    .into()
             }
             Self::Ed25519(pk) => {
                 let pk_bytes = pk.to_bytes();
-                let libp2p_pk = libp2p_core::PublicKey::Ed25519(
-                    libp2p_core::identity::ed25519::PublicKey::decode(&pk_bytes)
-                        .expect("valid public key"),
-                );
-                PeerId::from_public_key(libp2p_pk)
+                identity::ed25519::PublicKey::try_from_bytes(&pk_bytes)
+                    .map_err(|e| Error::Parse(format!("Invalid ed25519 public key: {}", e)))?
+                    .into()
             }
-        }
+        };
+        Ok(PeerId::from_public_key(&libp2p_pk))
     }
 }
 
 impl CombinedKeyExt for CombinedKey {
-    fn from_libp2p(key: &Keypair) -> Result<CombinedKey, &'static str> {
-        match key {
-            Keypair::Secp256k1(key) => {
-                let secret =
-                    discv5::enr::k256::ecdsa::SigningKey::from_bytes(&key.secret().to_bytes())
-                        .expect("libp2p key must be valid");
-                Ok(CombinedKey::Secp256k1(secret))
-            }
-            Keypair::Ed25519(key) => {
-                let ed_keypair =
-                    discv5::enr::ed25519_dalek::SecretKey::from_bytes(&key.encode()[..32])
-                        .expect("libp2p key must be valid");
-                Ok(CombinedKey::from(ed_keypair))
-            }
-            _ => Err("ENR: Unsupported libp2p key type"),
+    fn from_libp2p(key: &Keypair) -> std::result::Result<CombinedKey, &'static str> {
+        if let Ok(key) = key.clone().try_into_secp256k1() {
+            let secret = discv5::enr::k256::ecdsa::SigningKey::from_bytes(&key.secret().to_bytes())
+                .map_err(|_| "ENR: Invalid libp2p secp256k1 key")?;
+            Ok(CombinedKey::Secp256k1(secret))
+        } else if let Ok(key) = key.clone().try_into_ed25519() {
+            let ed_keypair =
+                discv5::enr::ed25519_dalek::SecretKey::from_bytes(&key.to_bytes()[..32])
+                    .map_err(|_| "ENR: Invalid libp2p ed25519 key")?;
+            Ok(CombinedKey::from(ed_keypair))
+        } else {
+            Err("ENR: Unsupported libp2p key type")
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discv5::enr::EnrBuilder;
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    const IP4: Ipv4Addr = Ipv4Addr::new(1, 2, 3, 4);
+    const IP6: Ipv6Addr = Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+    /// The multiaddrs as strings, with the peer id of the record replaced by `<id>`.
+    fn addresses(enr: &Enr, multiaddrs: Result<Vec<Multiaddr>>) -> Vec<String> {
+        let peer_id = enr.try_peer_id().unwrap().to_string();
+        multiaddrs
+            .unwrap()
+            .iter()
+            .map(|multiaddr| multiaddr.to_string().replace(&peer_id, "<id>"))
+            .collect()
+    }
+
+    fn build(builder: &mut EnrBuilder<CombinedKey>) -> Enr {
+        builder.build(&CombinedKey::generate_secp256k1()).unwrap()
+    }
+
+    #[test]
+    fn builds_ip4_multiaddrs() {
+        let enr = build(EnrBuilder::new("v4").ip4(IP4).udp4(9000).tcp4(9001));
+        assert_eq!(
+            addresses(&enr, enr.try_multiaddr()),
+            vec![
+                "/ip4/1.2.3.4/udp/9000/p2p/<id>",
+                "/ip4/1.2.3.4/tcp/9001/p2p/<id>"
+            ]
+        );
+        assert_eq!(
+            addresses(&enr, enr.discovery_multiaddrs()),
+            vec!["/ip4/1.2.3.4/udp/9000/p2p/<id>"]
+        );
+        assert_eq!(
+            addresses(&enr, enr.transport_multiaddrs()),
+            vec!["/ip4/1.2.3.4/tcp/9001/p2p/<id>"]
+        );
+        #[allow(deprecated)]
+        let multiaddr = enr.multiaddr();
+        assert_eq!(multiaddr, enr.try_multiaddr().unwrap());
+    }
+
+    #[test]
+    fn builds_ip6_multiaddrs() {
+        let enr = build(EnrBuilder::new("v4").ip6(IP6).udp6(9000));
+        assert_eq!(
+            addresses(&enr, enr.try_multiaddr()),
+            vec!["/ip6/2001:db8::1/udp/9000/p2p/<id>"]
+        );
+        assert!(enr.transport_multiaddrs().unwrap().is_empty());
+    }
+
+    #[test]
+    fn builds_dual_stack_multiaddrs() {
+        let enr = build(
+            EnrBuilder::new("v4")
+                .ip4(IP4)
+                .udp4(9000)
+                .tcp4(9000)
+                .ip6(IP6)
+                .udp6(9006)
+                .tcp6(9006),
+        );
+        assert_eq!(
+            addresses(&enr, enr.try_multiaddr()),
+            vec![
+                "/ip4/1.2.3.4/udp/9000/p2p/<id>",
+                "/ip4/1.2.3.4/tcp/9000/p2p/<id>",
+                "/ip6/2001:db8::1/udp/9006/p2p/<id>",
+                "/ip6/2001:db8::1/tcp/9006/p2p/<id>",
+            ]
+        );
+        assert_eq!(
+            addresses(&enr, enr.discovery_multiaddrs()),
+            vec![
+                "/ip4/1.2.3.4/udp/9000/p2p/<id>",
+                "/ip6/2001:db8::1/udp/9006/p2p/<id>",
+            ]
+        );
+    }
+
+    #[test]
+    fn builds_quic_multiaddrs() {
+        let enr = build(
+            EnrBuilder::new("v4")
+                .ip4(IP4)
+                .udp4(9000)
+                .add_value("quic", &9001u16.to_be_bytes())
+                .ip6(IP6)
+                .add_value("quic6", &9007u16.to_be_bytes()),
+        );
+        assert_eq!(
+            addresses(&enr, enr.transport_multiaddrs()),
+            vec![
+                "/ip4/1.2.3.4/udp/9001/quic-v1/p2p/<id>",
+                "/ip6/2001:db8::1/udp/9007/quic-v1/p2p/<id>",
+            ]
+        );
+
+        let enr = build(EnrBuilder::new("v4").ip4(IP4).add_value("quic", &[1, 2, 3]));
+        assert!(matches!(enr.try_multiaddr(), Err(Error::Parse(_))));
+    }
+}
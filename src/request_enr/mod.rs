@@ -1,53 +1,63 @@
+use crate::enr::Enode;
+use crate::error::{Error, Result};
+use crate::server;
 use clap::ArgMatches;
 use discv5::{enr, enr::CombinedKey, Discv5, Discv5ConfigBuilder};
 use libp2p_core::Multiaddr;
 use log::{error, info};
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
-mod enr_ext;
+pub mod enr_ext;
 use enr_ext::EnrExt;
 
-pub async fn run(matches: &ArgMatches<'_>) {
-    // Obtain the multiaddr
-    let multiaddr = matches
+pub async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    // Obtain the multiaddr, converting enode URLs
+    let m_addr = matches
         .value_of("multiaddr")
-        .map(|m_addr| {
-            m_addr
-                .parse::<Multiaddr>()
-                .expect("Invalid Multiaddr provided")
-        })
-        .expect("Multiaddr must be provided");
+        .ok_or_else(|| Error::Usage("Multiaddr must be provided".into()))?;
+    let multiaddr = if Enode::is_enode(m_addr) {
+        m_addr
+            .parse::<Enode>()
+            .map_err(|e| Error::Parse(format!("Invalid enode provided: {}", e)))?
+            .multiaddr()?
+    } else {
+        m_addr
+            .parse::<Multiaddr>()
+            .map_err(|e| Error::Parse(format!("Invalid Multiaddr provided: {}", e)))?
+    };
 
     // set up a server to receive the response
-    let listen_address = "0.0.0.0"
-        .parse::<IpAddr>()
-        .expect("This is a valid address");
+    let listen_address = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
     let listen_port = 9001;
     let enr_key = CombinedKey::generate_secp256k1();
 
     // build a local ENR
-    let enr = enr::EnrBuilder::new("v4")
-        .ip(listen_address)
-        .udp4(listen_port)
-        .build(&enr_key)
-        .unwrap();
+    let enr = server::EnrConfig {
+        ip: Some(listen_address),
+        udp_port: Some(listen_port),
+        ..Default::default()
+    }
+    .build(&enr_key)?;
 
     let listen_socket = SocketAddr::new(listen_address, listen_port);
     // default discv5 configuration
     let config = Discv5ConfigBuilder::new().build();
     // construct the discv5 service
-    let mut discv5 = Discv5::new(enr, enr_key, config).unwrap();
+    let mut discv5 = Discv5::new(enr, enr_key, config)
+        .map_err(|e| Error::Protocol(format!("Could not create the server: {}", e)))?;
 
     // start the server
-    discv5.start(listen_socket).await.unwrap();
+    server::start(&mut discv5, listen_socket).await?;
 
     // Request the ENR
     info!("Requesting ENR for: {}", multiaddr);
 
-    match discv5.request_enr(multiaddr.to_string()).await {
-        Ok(enr) => print_enr(enr),
-        Err(e) => error!("Failed to obtain ENR. Error: {}", e),
-    }
+    let enr = discv5
+        .request_enr(multiaddr.to_string())
+        .await
+        .map_err(|e| Error::request("Failed to obtain ENR", e))?;
+    print_enr(enr);
+    Ok(())
 }
 
 // Print various information about the obtained ENR.
@@ -55,7 +65,10 @@ fn print_enr(enr: enr::Enr<CombinedKey>) {
     info!("ENR Found:");
     info!("Sequence No:{}", enr.seq());
     info!("NodeId:{}", enr.node_id());
-    info!("Libp2p PeerId:{}", enr.peer_id());
+    match enr.try_peer_id() {
+        Ok(peer_id) => info!("Libp2p PeerId:{}", peer_id),
+        Err(e) => error!("Invalid libp2p PeerId. Error: {}", e),
+    }
     if let Some(ip) = enr.ip4() {
         info!("IP:{:?}", ip);
     }
@@ -66,11 +79,25 @@ fn print_enr(enr: enr::Enr<CombinedKey>) {
         info!("UDP Port:{}", udp);
     }
 
-    let multiaddrs = enr.multiaddr();
-    if !multiaddrs.is_empty() {
-        info!("Known multiaddrs:");
-        for multiaddr in multiaddrs {
-            info!("{}", multiaddr);
+    if let Some(Ok(quic)) = enr.get("quic").map(crate::enr::fields::port) {
+        info!("QUIC Port:{}", quic);
+    }
+
+    for (kind, multiaddrs) in [
+        ("discovery", enr.discovery_multiaddrs()),
+        ("transport", enr.transport_multiaddrs()),
+    ]
+    .iter()
+    {
+        match multiaddrs {
+            Ok(multiaddrs) if !multiaddrs.is_empty() => {
+                info!("Known {} multiaddrs:", kind);
+                for multiaddr in multiaddrs {
+                    info!("{}", multiaddr);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => error!("Failed to build {} multiaddrs. Error: {}", kind, e),
         }
     }
 }
@@ -0,0 +1,277 @@
+//! Searches the network for peers whose ENR satisfies a predicate, such as membership of an
+//! attestation or sync committee subnet.
+
+use crate::cli;
+use crate::enr::EnrOrEnode;
+use crate::error::{Error, Result};
+use crate::request_enr::enr_ext::EnrExt;
+use crate::server;
+use clap::ArgMatches;
+use discv5::{enr, enr::CombinedKey, Discv5, Discv5ConfigBuilder};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, Instant};
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The ENR key of the attestation subnet bitfield.
+const ATTNETS_KEY: &str = "attnets";
+/// The ENR key of the sync committee subnet bitfield.
+const SYNCNETS_KEY: &str = "syncnets";
+/// The number of attestation subnets, the bits of the `attnets` bitfield.
+const ATTESTATION_SUBNET_COUNT: usize = 64;
+/// The number of sync committee subnets, the bits of the `syncnets` bitfield.
+const SYNC_COMMITTEE_SUBNET_COUNT: usize = 4;
+/// The pause after a query that found no new peers, doubled after each such query.
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+/// The longest pause between queries.
+const MAX_BACKOFF: Duration = Duration::from_secs(16);
+
+/// The property a peer's ENR must have to match the search.
+#[derive(Debug, Clone)]
+pub enum Predicate {
+    /// The peer subscribes to the attestation subnet.
+    Attnet(usize),
+    /// The peer subscribes to the sync committee subnet.
+    Syncnet(usize),
+    /// The ENR contains the key.
+    HasKey(String),
+}
+
+impl Predicate {
+    /// Returns true if the ENR satisfies the predicate.
+    pub fn matches(&self, enr: &Enr) -> bool {
+        match self {
+            Predicate::Attnet(subnet) => bitfield_contains(enr.get(ATTNETS_KEY), *subnet),
+            Predicate::Syncnet(subnet) => bitfield_contains(enr.get(SYNCNETS_KEY), *subnet),
+            Predicate::HasKey(key) => enr.get(key).is_some(),
+        }
+    }
+}
+
+impl std::fmt::Display for Predicate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Predicate::Attnet(subnet) => write!(f, "attestation subnet {}", subnet),
+            Predicate::Syncnet(subnet) => write!(f, "sync committee subnet {}", subnet),
+            Predicate::HasKey(key) => write!(f, "ENR key \"{}\"", key),
+        }
+    }
+}
+
+/// Checks whether bit `index` of an SSZ encoded bitvector is set.
+fn bitfield_contains(bitfield: Option<&[u8]>, index: usize) -> bool {
+    bitfield
+        .and_then(|bytes| bytes.get(index / 8))
+        .is_some_and(|byte| byte & (1 << (index % 8)) != 0)
+}
+
+/// Parses a subnet argument, rejecting indexes of subnets that do not exist.
+fn subnet_value(matches: &ArgMatches, name: &str, subnet_count: usize) -> Result<Option<usize>> {
+    match cli::optional_value(matches, name)? {
+        Some(subnet) if subnet >= subnet_count => Err(Error::Parse(format!(
+            "Invalid {} \"{}\": subnets range from 0 to {}",
+            name,
+            subnet,
+            subnet_count - 1
+        ))),
+        subnet => Ok(subnet),
+    }
+}
+
+/// Runs a search based on the CLI options.
+pub async fn run(matches: &ArgMatches<'_>) -> Result<()> {
+    let attnet = subnet_value(matches, "attnet", ATTESTATION_SUBNET_COUNT)?;
+    let syncnet = subnet_value(matches, "syncnet", SYNC_COMMITTEE_SUBNET_COUNT)?;
+    let predicate = if let Some(subnet) = attnet {
+        Predicate::Attnet(subnet)
+    } else if let Some(subnet) = syncnet {
+        Predicate::Syncnet(subnet)
+    } else if let Some(key) = matches.value_of("has-key") {
+        Predicate::HasKey(key.into())
+    } else {
+        return Err(Error::Usage("A search predicate must be supplied".into()));
+    };
+
+    let listen_address: IpAddr = cli::value(matches, "listen-address")?;
+    let listen_port: u16 = cli::value(matches, "listen-port")?;
+    let target_peers: usize = cli::value(matches, "peers")?;
+    let timeout = Duration::from_secs(cli::value(matches, "timeout")?);
+
+    let bootnodes = matches
+        .values_of("enr")
+        .into_iter()
+        .flatten()
+        .map(|enr| {
+            enr.parse::<EnrOrEnode>()
+                .map_err(|e| Error::Parse(format!("Invalid enr \"{}\": {}", enr, e)))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    // set up a local node to perform the search
+    let enr_key = CombinedKey::generate_secp256k1();
+    let enr = server::EnrConfig::default().build(&enr_key)?;
+    let config = Discv5ConfigBuilder::new().build();
+    let mut discv5 = Discv5::new(enr, enr_key, config)
+        .map_err(|e| Error::Protocol(format!("Could not create the server: {}", e)))?;
+
+    server::start(&mut discv5, SocketAddr::new(listen_address, listen_port)).await?;
+
+    for bootnode in bootnodes {
+        match crate::enr::resolve(&mut discv5, bootnode).await {
+            Ok(enr) => {
+                if let Err(e) = discv5.add_enr(enr) {
+                    warn!("ENR not added: {:?}", e);
+                }
+            }
+            Err(e) => warn!("ENR not added: {}", e),
+        }
+    }
+
+    info!(
+        "Searching for {} peers on {}. Timeout: {}s",
+        target_peers,
+        predicate,
+        timeout.as_secs()
+    );
+
+    let deadline = Instant::now() + timeout;
+    let mut seen = HashSet::new();
+    let mut found = Vec::new();
+    let mut queries = 0;
+    let mut backoff = INITIAL_BACKOFF;
+
+    while found.len() < target_peers {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::from_secs(0) {
+            break;
+        }
+
+        let query_predicate = predicate.clone();
+        let query = discv5.find_node_predicate(
+            enr::NodeId::random(),
+            Box::new(move |enr: &Enr| query_predicate.matches(enr)),
+            target_peers - found.len(),
+        );
+        queries += 1;
+
+        let found_before = found.len();
+        match tokio::time::timeout(remaining, query).await {
+            Err(_) => break,
+            Ok(Err(e)) => warn!("Find Node result failed: {:?}", e),
+            Ok(Ok(found_enrs)) => {
+                for enr in found_enrs {
+                    if predicate.matches(&enr) && seen.insert(enr.node_id()) {
+                        info!("Found matching peer: {}", enr.node_id());
+                        found.push(enr);
+                    }
+                }
+                info!(
+                    "Query {} completed. Matching peers: {}/{}",
+                    queries,
+                    found.len(),
+                    target_peers
+                );
+            }
+        }
+
+        // an empty or unreachable table answers instantly, so wait before querying it again
+        if found.len() == found_before && found.len() < target_peers {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            tokio::time::sleep(backoff.min(remaining)).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        } else {
+            backoff = INITIAL_BACKOFF;
+        }
+    }
+
+    if found.len() < target_peers {
+        warn!(
+            "Deadline reached after {} queries. Found {} of {} peers",
+            queries,
+            found.len(),
+            target_peers
+        );
+    } else {
+        info!("Found {} peers in {} queries", found.len(), queries);
+    }
+
+    for enr in found.iter().take(target_peers) {
+        info!("Peer: {}, ENR: {}", enr.node_id(), enr.to_base64());
+        match enr.try_multiaddr() {
+            Ok(multiaddrs) => {
+                for multiaddr in multiaddrs {
+                    info!("  {}", multiaddr);
+                }
+            }
+            Err(e) => warn!("  Failed to build multiaddrs. Error: {}", e),
+        }
+    }
+
+    if found.is_empty() {
+        return Err(Error::Timeout(format!(
+            "No peers on {} found within {}s",
+            predicate,
+            timeout.as_secs()
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{App, Arg};
+
+    fn record(key: &str, value: &[u8]) -> Enr {
+        let mut builder = enr::EnrBuilder::new("v4");
+        builder.add_value(key, value);
+        builder.build(&CombinedKey::generate_secp256k1()).unwrap()
+    }
+
+    #[test]
+    fn matches_subnets_and_keys() {
+        // subnets 1 and 63 of an SSZ bitvector, least significant bit first
+        let enr = record(ATTNETS_KEY, &[0b10, 0, 0, 0, 0, 0, 0, 0x80]);
+        assert!(Predicate::Attnet(1).matches(&enr));
+        assert!(Predicate::Attnet(63).matches(&enr));
+        assert!(!Predicate::Attnet(0).matches(&enr));
+        assert!(!Predicate::Syncnet(1).matches(&enr));
+        assert!(Predicate::HasKey(ATTNETS_KEY.into()).matches(&enr));
+        assert!(!Predicate::HasKey("eth2".into()).matches(&enr));
+
+        let enr = record(SYNCNETS_KEY, &[0b1000]);
+        assert!(Predicate::Syncnet(3).matches(&enr));
+        assert!(!Predicate::Syncnet(2).matches(&enr));
+
+        let enr = record("eth2", &[0; crate::enr::fields::ETH2_LENGTH]);
+        assert!(Predicate::HasKey("eth2".into()).matches(&enr));
+        assert!(!Predicate::Attnet(0).matches(&enr));
+    }
+
+    #[test]
+    fn reads_bits_least_significant_first() {
+        assert!(bitfield_contains(Some(&[0b1]), 0));
+        assert!(bitfield_contains(Some(&[0, 0b100]), 10));
+        assert!(
+            !bitfield_contains(Some(&[0b100]), 10),
+            "beyond the bitfield"
+        );
+        assert!(!bitfield_contains(None, 0));
+    }
+
+    #[test]
+    fn rejects_subnets_out_of_range() {
+        let app = App::new("search").arg(Arg::with_name("attnet").long("attnet").takes_value(true));
+        let matches = |args: &[&str]| app.clone().get_matches_from(args);
+
+        let attnet = |args| subnet_value(&matches(args), "attnet", ATTESTATION_SUBNET_COUNT);
+        assert_eq!(attnet(&["search", "--attnet", "63"]).unwrap(), Some(63));
+        assert_eq!(attnet(&["search"]).unwrap(), None);
+        assert!(matches!(
+            attnet(&["search", "--attnet", "64"]),
+            Err(Error::Parse(_))
+        ));
+    }
+}
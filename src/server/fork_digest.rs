@@ -0,0 +1,180 @@
+//! Restricts the routing table and query results to nodes of a single eth2 fork.
+//!
+//! The `eth2` ENR field holds an SSZ encoded `ENRForkID`, the first 4 bytes of which are the fork
+//! digest. The discv5 table filter is a plain function, so the fork digest it admits is kept in a
+//! static and can only be set once per process. Nodes that establish a session with the server
+//! bypass the table filter and are removed from the routing table by the table monitor instead.
+
+use crate::error::Error;
+use discv5::{enr, enr::CombinedKey};
+use log::info;
+use std::collections::BTreeMap;
+use std::sync::OnceLock;
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The length of a fork digest.
+const FORK_DIGEST_LENGTH: usize = 4;
+
+/// The fork digest admitted by [`table_filter`].
+static TABLE_FORK_DIGEST: OnceLock<[u8; FORK_DIGEST_LENGTH]> = OnceLock::new();
+
+/// The reason a node was rejected by the fork digest filter.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Rejection {
+    /// The ENR has no `eth2` field.
+    NoEth2Field,
+    /// The `eth2` field is too short to contain a fork digest.
+    InvalidEth2Field(usize),
+    /// The ENR advertises a different fork digest.
+    ForkDigest([u8; FORK_DIGEST_LENGTH]),
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::NoEth2Field => write!(f, "no eth2 field"),
+            Rejection::InvalidEth2Field(len) => write!(f, "invalid eth2 field of {} bytes", len),
+            Rejection::ForkDigest(digest) => write!(f, "fork digest 0x{}", hex::encode(digest)),
+        }
+    }
+}
+
+/// Parses a hex encoded fork digest, with or without a `0x` prefix.
+pub fn parse_fork_digest(digest: &str) -> Result<[u8; FORK_DIGEST_LENGTH], Error> {
+    let bytes = hex::decode(digest.trim_start_matches("0x"))
+        .map_err(|e| Error::Parse(format!("Invalid fork digest hex bytes: {}", e)))?;
+    if bytes.len() != FORK_DIGEST_LENGTH {
+        return Err(Error::Parse(format!(
+            "A fork digest must be {} bytes",
+            FORK_DIGEST_LENGTH
+        )));
+    }
+    let mut fork_digest = [0u8; FORK_DIGEST_LENGTH];
+    fork_digest.copy_from_slice(&bytes);
+    Ok(fork_digest)
+}
+
+/// Checks whether an ENR advertises the given fork digest.
+pub fn check(enr: &Enr, fork_digest: &[u8; FORK_DIGEST_LENGTH]) -> Result<(), Rejection> {
+    let eth2 = enr.get("eth2").ok_or(Rejection::NoEth2Field)?;
+    if eth2.len() < FORK_DIGEST_LENGTH {
+        return Err(Rejection::InvalidEth2Field(eth2.len()));
+    }
+    if &eth2[..FORK_DIGEST_LENGTH] != fork_digest {
+        let mut digest = [0u8; FORK_DIGEST_LENGTH];
+        digest.copy_from_slice(&eth2[..FORK_DIGEST_LENGTH]);
+        return Err(Rejection::ForkDigest(digest));
+    }
+    Ok(())
+}
+
+/// Sets the fork digest admitted by [`table_filter`]. Servers of a single process must share it.
+pub fn set_table_fork_digest(fork_digest: [u8; FORK_DIGEST_LENGTH]) -> Result<(), Error> {
+    let table_fork_digest = TABLE_FORK_DIGEST.get_or_init(|| fork_digest);
+    if *table_fork_digest != fork_digest {
+        return Err(Error::Usage(format!(
+            "The routing tables of this process already filter by fork digest 0x{}",
+            hex::encode(table_fork_digest)
+        )));
+    }
+    Ok(())
+}
+
+/// The discv5 table filter, which admits the nodes of the fork digest set with
+/// [`set_table_fork_digest`] and any node if none is set.
+pub fn table_filter(enr: &Enr) -> bool {
+    TABLE_FORK_DIGEST
+        .get()
+        .is_none_or(|fork_digest| check(enr, fork_digest).is_ok())
+}
+
+/// Removes the nodes of other forks from query results, reporting how many were rejected and why.
+pub fn filter_found(found_enrs: Vec<Enr>, fork_digest: &[u8; FORK_DIGEST_LENGTH]) -> Vec<Enr> {
+    let mut rejections = BTreeMap::new();
+    let accepted: Vec<_> = found_enrs
+        .into_iter()
+        .filter(|enr| match check(enr, fork_digest) {
+            Ok(()) => true,
+            Err(rejection) => {
+                *rejections.entry(rejection).or_insert(0) += 1;
+                false
+            }
+        })
+        .collect();
+
+    if !rejections.is_empty() {
+        info!(
+            "Rejected {} nodes not on fork digest 0x{}:",
+            rejections.values().sum::<usize>(),
+            hex::encode(fork_digest)
+        );
+        for (rejection, count) in rejections {
+            info!("{} nodes with {}", count, rejection);
+        }
+    }
+    accepted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FORK_DIGEST: [u8; FORK_DIGEST_LENGTH] = [0xb5, 0x30, 0x3f, 0x2a];
+
+    fn record(eth2: Option<&[u8]>) -> Enr {
+        let mut builder = enr::EnrBuilder::new("v4");
+        if let Some(eth2) = eth2 {
+            builder.add_value("eth2", eth2);
+        }
+        builder.build(&CombinedKey::generate_secp256k1()).unwrap()
+    }
+
+    /// An SSZ `ENRForkID` with the fork digest and a far future next fork.
+    fn eth2(fork_digest: [u8; FORK_DIGEST_LENGTH]) -> Vec<u8> {
+        let mut eth2 = fork_digest.to_vec();
+        eth2.extend_from_slice(&[0; 4]);
+        eth2.extend_from_slice(&u64::MAX.to_le_bytes());
+        eth2
+    }
+
+    #[test]
+    fn parses_fork_digests() {
+        assert_eq!(parse_fork_digest("0xb5303f2a").unwrap(), FORK_DIGEST);
+        assert_eq!(parse_fork_digest("b5303f2a").unwrap(), FORK_DIGEST);
+        assert!(matches!(parse_fork_digest("b5303f"), Err(Error::Parse(_))));
+        assert!(matches!(
+            parse_fork_digest("b5303f2z"),
+            Err(Error::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn checks_the_eth2_field() {
+        assert_eq!(
+            check(&record(Some(&eth2(FORK_DIGEST))), &FORK_DIGEST),
+            Ok(())
+        );
+        assert_eq!(
+            check(&record(Some(&eth2([1, 2, 3, 4]))), &FORK_DIGEST),
+            Err(Rejection::ForkDigest([1, 2, 3, 4]))
+        );
+        assert_eq!(
+            check(&record(None), &FORK_DIGEST),
+            Err(Rejection::NoEth2Field)
+        );
+        assert_eq!(
+            check(&record(Some(&[0xb5, 0x30])), &FORK_DIGEST),
+            Err(Rejection::InvalidEth2Field(2))
+        );
+    }
+
+    #[test]
+    fn filters_query_results() {
+        let on_fork = record(Some(&eth2(FORK_DIGEST)));
+        let found = vec![on_fork.clone(), record(None), record(Some(&eth2([0; 4])))];
+        let accepted = filter_found(found, &FORK_DIGEST);
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].node_id(), on_fork.node_id());
+    }
+}
@@ -1,144 +1,367 @@
+pub mod fork_digest;
+mod peer_tracker;
 pub mod query_server;
+mod socket_updates;
+mod table_monitor;
+use crate::cli;
+use crate::enr::EnrOrEnode;
+use crate::error::{Error, Result};
+use crate::lookup::client::LookupClient;
 use clap::ArgMatches;
 use discv5::{enr, enr::k256, enr::CombinedKey, Discv5, Discv5ConfigBuilder};
+use futures::future::FutureExt;
+use futures::stream::{BoxStream, StreamExt};
 use log::{info, warn};
-use std::net::{IpAddr, SocketAddr};
-
-// handle a query server
-
-pub async fn run(server_matches: &ArgMatches<'_>) {
-    let listen_address = server_matches
-        .value_of("listen-address")
-        .expect("required parameter")
-        .parse::<IpAddr>()
-        .expect("Invalid listening address");
-
-    let listen_port = server_matches
-        .value_of("listen-port")
-        .expect("required parameter")
-        .parse::<u16>()
-        .expect("Invalid listening port");
-
-    let no_search = server_matches.is_present("no-search");
-
-    // the number of nodes required to come to consensus before our external IP is updated.
-    let peer_update_min = server_matches
-        .value_of("peer-update-min")
-        .expect("There must be a value for nodes-to-update")
-        .parse::<usize>()
-        .expect("Nodes to implement must be an unsigned integer");
-
-    let time_between_searches = std::time::Duration::from_secs(
-        server_matches
-            .value_of("break-time")
-            .expect("This value must exist")
-            .parse::<u64>()
-            .expect("The break time must be a uint."),
-    );
-
-    let stats = server_matches.is_present("stats");
+use peer_tracker::PeerTracker;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use table_monitor::TableMonitor;
 
-    // create the key pair
-    let enr_key = if server_matches.is_present("static-key") {
-        // A fixed key for testing
-        let raw_key = vec![
-            183, 28, 113, 166, 126, 17, 119, 173, 78, 144, 22, 149, 225, 180, 185, 238, 23, 174,
-            22, 198, 102, 141, 49, 62, 172, 47, 150, 219, 205, 163, 242, 145,
-        ];
-        let secret_key = k256::ecdsa::SigningKey::from_bytes(&raw_key).unwrap();
-        CombinedKey::from(secret_key)
-    } else if let Some(string_key) = server_matches.value_of("secp256k1-key") {
-        let raw_key = hex::decode(string_key).expect("Invalid hex bytes for secp256k1 key");
-        let secret_key =
-            k256::ecdsa::SigningKey::from_bytes(&raw_key).expect("Invalid secp256k1 key");
-        CombinedKey::from(secret_key)
-    } else {
-        CombinedKey::generate_secp256k1()
-    };
+/// The fixed secp256k1 key used by the `--static-key` flag.
+const STATIC_KEY: [u8; 32] = [
+    183, 28, 113, 166, 126, 17, 119, 173, 78, 144, 22, 149, 225, 180, 185, 238, 23, 174, 22, 198,
+    102, 141, 49, 62, 172, 47, 150, 219, 205, 163, 242, 145,
+];
 
-    // build the ENR
-    let enr = {
-        let mut builder = enr::EnrBuilder::new("v4");
+/// The configuration of a discv5 server. The defaults match those of the `server` subcommand.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// The listening address. Listens on all interfaces if not set.
+    pub listen_address: Option<IpAddr>,
+    /// The listening UDP port. A port of 0 is assigned by the OS.
+    pub listen_port: u16,
+    /// The raw secp256k1 secret key of the node. A random key is generated if not set.
+    pub secret_key: Option<Vec<u8>>,
+    /// The fields of the local ENR.
+    pub enr: EnrConfig,
+    /// A node to add to the routing table on startup.
+    pub connect_node: Option<EnrOrEnode>,
+    /// Only answer requests instead of running queries.
+    pub no_search: bool,
+    /// The number of nodes required to come to consensus before the external IP is updated.
+    pub peer_update_min: usize,
+    /// The time between queries.
+    pub break_time: Duration,
+    /// Display table statistics after each query.
+    pub stats: bool,
+    /// The file table dumps are written to. Dumps are logged if this is not set.
+    pub dump_path: Option<PathBuf>,
+    /// The file the topology of discovered nodes is written to. Queries then send their own
+    /// requests to learn which peer returned which node.
+    pub topology_path: Option<PathBuf>,
+    /// Probe table peers after each query to track their latency and reliability.
+    pub track_peers: bool,
+    /// The file the peer report is written to, if `track_peers` is set.
+    pub peer_report_path: Option<PathBuf>,
+    /// Only admit nodes of this eth2 fork.
+    pub fork_digest: Option<[u8; 4]>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen_address: None,
+            listen_port: 9000,
+            secret_key: None,
+            enr: EnrConfig::default(),
+            connect_node: None,
+            no_search: false,
+            peer_update_min: 2,
+            break_time: Duration::from_secs(10),
+            stats: false,
+            dump_path: None,
+            topology_path: None,
+            track_peers: false,
+            peer_report_path: None,
+            fork_digest: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Builds the configuration from the `server` subcommand options.
+    pub fn from_matches(server_matches: &ArgMatches) -> Result<Self> {
+        let listen_address: IpAddr = cli::value(server_matches, "listen-address")?;
+        let listen_port: u16 = cli::value(server_matches, "listen-port")?;
+
+        let secret_key = if server_matches.is_present("static-key") {
+            // A fixed key for testing
+            Some(STATIC_KEY.to_vec())
+        } else {
+            cli::hex_value(server_matches, "secp256k1-key")?
+        };
 
         // if the -w switch is used, use the listen_address and port for the ENR
-        if server_matches.is_present("enr_default") {
-            builder.ip(listen_address);
-            builder.udp4(listen_port);
+        let (enr_address, enr_port) = if server_matches.is_present("enr_default") {
+            (Some(listen_address), Some(listen_port))
         } else {
-            if let Some(address_string) = server_matches.value_of("enr-address") {
-                let enr_address = address_string
-                    .parse::<IpAddr>()
-                    .expect("Invalid enr-address");
-                builder.ip(enr_address);
-            }
-            if let Some(port_string) = server_matches.value_of("enr-port") {
-                let enr_port = port_string.parse::<u16>().expect("Invalid enr-port");
-                builder.udp4(enr_port);
-            }
-        }
+            (
+                cli::optional_value(server_matches, "enr-address")?,
+                cli::optional_value(server_matches, "enr-port")?,
+            )
+        };
 
-        if let Some(seq_no_string) = server_matches.value_of("enr-seq-no") {
-            let seq_no = seq_no_string
-                .parse::<u64>()
-                .expect("Invalid sequence number, must be a uint");
+        Ok(ServerConfig {
+            listen_address: Some(listen_address),
+            listen_port,
+            secret_key,
+            enr: EnrConfig {
+                ip: enr_address,
+                udp_port: enr_port,
+                seq_no: cli::optional_value(server_matches, "enr-seq-no")?,
+                eth2: cli::hex_value(server_matches, "enr-eth2")?,
+            },
+            connect_node: cli::optional_value(server_matches, "enr")?,
+            no_search: server_matches.is_present("no-search"),
+            peer_update_min: cli::value(server_matches, "peer-update-min")?,
+            break_time: Duration::from_secs(cli::value(server_matches, "break-time")?),
+            stats: server_matches.is_present("stats"),
+            dump_path: server_matches.value_of("dump-table").map(PathBuf::from),
+            topology_path: server_matches.value_of("topology").map(PathBuf::from),
+            track_peers: server_matches.is_present("track-peers"),
+            peer_report_path: server_matches.value_of("peer-report").map(PathBuf::from),
+            fork_digest: server_matches
+                .value_of("fork-digest")
+                .map(fork_digest::parse_fork_digest)
+                .transpose()?,
+        })
+    }
+}
+
+/// The fields a server can advertise in its ENR.
+#[derive(Debug, Clone, Default)]
+pub struct EnrConfig {
+    /// The IP address of the node. The ENR has no address if not set.
+    pub ip: Option<IpAddr>,
+    /// The UDP port of the node.
+    pub udp_port: Option<u16>,
+    /// The sequence number of the ENR. Starts at 1 if not set.
+    pub seq_no: Option<u64>,
+    /// The SSZ encoded `ENRForkID`.
+    pub eth2: Option<Vec<u8>>,
+}
+
+impl EnrConfig {
+    /// Builds and signs a "v4" ENR with the configured fields.
+    pub fn build(&self, enr_key: &CombinedKey) -> Result<enr::Enr<CombinedKey>> {
+        let mut builder = enr::EnrBuilder::new("v4");
+
+        if let Some(ip) = self.ip {
+            builder.ip(ip);
+        }
+        if let Some(udp_port) = self.udp_port {
+            builder.udp4(udp_port);
+        }
+        if let Some(seq_no) = self.seq_no {
             builder.seq(seq_no);
         }
-
-        if let Some(eth2_string) = server_matches.value_of("enr-eth2") {
-            let ssz_bytes = hex::decode(eth2_string).expect("Invalid eth2 hex bytes");
-            builder.add_value("eth2", &ssz_bytes);
+        if let Some(ssz_bytes) = &self.eth2 {
+            builder.add_value("eth2", ssz_bytes);
         }
 
-        builder.build(&enr_key).unwrap()
+        sign_enr(&mut builder, enr_key)
+    }
+}
+
+/// Runs a discv5 server until it receives SIGINT or SIGTERM, dumping the routing table on
+/// SIGUSR1.
+pub async fn run(config: ServerConfig) -> Result<()> {
+    let shutdown = table_monitor::shutdown_signal()?;
+    let dump_trigger = table_monitor::dump_signal()?;
+    serve(config, shutdown, dump_trigger).await
+}
+
+/// Runs a discv5 server until `shutdown` completes, then prints the final report and shuts it
+/// down. No signal handlers are installed.
+pub async fn run_with_shutdown(
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    serve(config, shutdown, futures::stream::pending().boxed()).await
+}
+
+/// Runs a discv5 server, dumping the routing table each time `dump_trigger` yields, until
+/// `shutdown` completes.
+async fn serve(
+    config: ServerConfig,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+    dump_trigger: BoxStream<'static, ()>,
+) -> Result<()> {
+    let listen_address = config
+        .listen_address
+        .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+
+    // track the latency and reliability of table peers
+    let peer_tracker = if config.track_peers {
+        Some(PeerTracker::new(config.peer_report_path))
+    } else {
+        None
+    };
+
+    // create the key pair
+    let enr_key = match &config.secret_key {
+        Some(raw_key) => key_from_bytes(raw_key)?,
+        None => CombinedKey::generate_secp256k1(),
     };
 
+    // build the ENR
+    let enr = config.enr.build(&enr_key)?;
+
     // if the ENR is useful print it
     info!("Node Id: {}", enr.node_id());
-    if enr.udp4_socket().is_some() {
+    if let Some(socket) = enr.udp4_socket() {
         info!("Base64 ENR: {}", enr.to_base64());
-        info!("ip: {}, udp port:{}", enr.ip4().unwrap(), enr.udp4().unwrap());
+        info!("ip: {}, udp port:{}", socket.ip(), socket.port());
     } else {
         warn!("ENR is not printed as no IP:PORT was specified");
     }
 
-    let connect_enr = server_matches.value_of("enr").map(|enr| {
-        enr.parse::<enr::Enr<enr::CombinedKey>>()
-            .expect("Invalid base64 encoded ENR")
-    });
-
     // default discv5 configuration
-    let config = Discv5ConfigBuilder::new()
-        .enr_peer_update_min(peer_update_min)
-        .build();
+    let mut config_builder = Discv5ConfigBuilder::new();
+    config_builder.enr_peer_update_min(config.peer_update_min);
+    // only keep nodes of the given fork in the routing table
+    if let Some(fork_digest) = config.fork_digest {
+        fork_digest::set_table_fork_digest(fork_digest)?;
+        config_builder.table_filter(fork_digest::table_filter);
+        info!(
+            "Filtering nodes by fork digest: 0x{}",
+            hex::encode(fork_digest)
+        );
+    }
+    let discv5_config = config_builder.build();
+    // discv5 does not report which peer returned which node, so topology lookups use own requests
+    let lookup_client = match &config.topology_path {
+        Some(_) => Some(LookupClient::new(
+            discv5_config.query_parallelism,
+            discv5_config.query_peer_timeout,
+        )?),
+        None => None,
+    };
     // construct the discv5 service
-    let mut discv5 = Discv5::new(enr, enr_key, config).unwrap();
-
-    // try to connect to an ENR if specified
-    if !no_search {
-        if let Some(connect_enr) = connect_enr {
-            info!(
-                "Connecting to ENR. ip: {:?}, udp_port: {:?},  tcp_port: {:?}",
-                connect_enr.ip4(),
-                connect_enr.udp4(),
-                connect_enr.tcp4()
-            );
-            if let Err(e) = discv5.add_enr(connect_enr) {
-                warn!("ENR not added: {:?}", e);
+    let mut discv5 = Discv5::new(enr, enr_key, discv5_config)
+        .map_err(|e| Error::Protocol(format!("Could not create the server: {}", e)))?;
+
+    // start the server
+    start(
+        &mut discv5,
+        SocketAddr::new(listen_address, config.listen_port),
+    )
+    .await?;
+
+    // try to connect to an ENR or enode if specified
+    if !config.no_search {
+        if let Some(connect_node) = config.connect_node {
+            match crate::enr::resolve(&mut discv5, connect_node).await {
+                Ok(connect_enr) => {
+                    info!(
+                        "Connecting to ENR. ip: {:?}, udp_port: {:?},  tcp_port: {:?}",
+                        connect_enr.ip4(),
+                        connect_enr.udp4(),
+                        connect_enr.tcp4()
+                    );
+                    let on_fork = match &config.fork_digest {
+                        Some(fork_digest) => fork_digest::check(&connect_enr, fork_digest),
+                        None => Ok(()),
+                    };
+                    if let Err(rejection) = on_fork {
+                        warn!("ENR not added: {}", rejection);
+                    } else if let Err(e) = discv5.add_enr(connect_enr) {
+                        warn!("ENR not added: {:?}", e);
+                    }
+                }
+                Err(e) => warn!("ENR not added: {}", e),
             }
         }
     }
 
-    // start the server
-    discv5
-        .start(SocketAddr::new(listen_address, listen_port))
-        .await
-        .expect("Should be able to start the server");
+    // watch the routing table, dumping it on request and shutting down once `shutdown` completes
+    let mut monitor = TableMonitor::new(
+        &mut discv5,
+        config.dump_path,
+        config.topology_path,
+        peer_tracker,
+        config.peer_update_min,
+        config.fork_digest,
+        dump_trigger,
+        shutdown.boxed(),
+    )
+    .await?;
 
     // start the query
-    if !no_search {
-        query_server::run_query_server(discv5, time_between_searches, stats).await;
+    if !config.no_search {
+        query_server::run_query_server(
+            discv5,
+            monitor,
+            lookup_client,
+            config.break_time,
+            config.stats,
+        )
+        .await;
     } else {
         info!("Server running...");
-        let _ = tokio::signal::ctrl_c().await;
+        monitor
+            .run_until(&mut discv5, std::future::pending::<()>())
+            .await;
+        monitor.shutdown(discv5, 0);
+    }
+    Ok(())
+}
+
+/// Starts a discv5 server on `socket`.
+pub async fn start(discv5: &mut Discv5, socket: SocketAddr) -> Result<()> {
+    discv5
+        .start(socket)
+        .await
+        .map_err(|e| Error::Io(format!("Could not start the server on {}: {}", socket, e)))
+}
+
+/// Signs the record of `builder` with `enr_key`. Fails if the record exceeds the maximum size.
+pub fn sign_enr(
+    builder: &mut enr::EnrBuilder<CombinedKey>,
+    enr_key: &CombinedKey,
+) -> Result<enr::Enr<CombinedKey>> {
+    builder
+        .build(enr_key)
+        .map_err(|e| Error::Protocol(format!("Could not build the ENR: {:?}", e)))
+}
+
+/// Builds a secp256k1 `CombinedKey` from raw secret key bytes.
+pub fn key_from_bytes(raw_key: &[u8]) -> Result<CombinedKey> {
+    let secret_key = k256::ecdsa::SigningKey::from_bytes(raw_key)
+        .map_err(|_| Error::Parse("Invalid secp256k1 key".into()))?;
+    Ok(CombinedKey::from(secret_key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn servers_with_fork_digests_stop_on_shutdown() {
+        let mut servers = Vec::new();
+        let mut stops = Vec::new();
+        for _ in 0..2 {
+            let config = ServerConfig {
+                listen_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                listen_port: 0,
+                no_search: true,
+                fork_digest: Some([0xb5, 0x30, 0x3f, 0x2a]),
+                ..Default::default()
+            };
+            let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+            servers.push(tokio::spawn(run_with_shutdown(config, async {
+                let _ = stopped.await;
+            })));
+            stops.push(stop);
+        }
+
+        for stop in stops {
+            stop.send(()).unwrap();
+        }
+        for server in servers {
+            server.await.unwrap().unwrap();
+        }
     }
 }
@@ -0,0 +1,238 @@
+//! Tracks the latency and reliability of every peer in the routing table.
+//!
+//! discv5 does not expose the requests it sends during queries, so after each query every table
+//! entry is probed with an ENR request. The numbers describe these probes, not query traffic. The
+//! round-trip time or the reason a probe failed is recorded per peer, and peers are ranked by
+//! success ratio and median latency. discv5 reports no handshake failures; they surface as
+//! timeouts or invalid packets.
+
+use discv5::{enr, RequestError};
+use log::{error, info};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// The probes sent to a single peer and their outcomes.
+struct PeerRecord {
+    /// When the peer was first probed.
+    first_seen: Instant,
+    /// The round-trip times of successful probes.
+    rtts: Vec<Duration>,
+    timeouts: usize,
+    malformed_responses: usize,
+    other_failures: usize,
+}
+
+impl PeerRecord {
+    fn new() -> Self {
+        PeerRecord {
+            first_seen: Instant::now(),
+            rtts: Vec::new(),
+            timeouts: 0,
+            malformed_responses: 0,
+            other_failures: 0,
+        }
+    }
+
+    fn requests(&self) -> usize {
+        self.rtts.len() + self.timeouts + self.malformed_responses + self.other_failures
+    }
+
+    fn success_ratio(&self) -> f64 {
+        match self.requests() {
+            0 => 0.0,
+            requests => self.rtts.len() as f64 / requests as f64,
+        }
+    }
+
+    fn median_rtt(&self) -> Option<Duration> {
+        let mut rtts = self.rtts.clone();
+        rtts.sort();
+        rtts.get(rtts.len() / 2).copied()
+    }
+}
+
+/// The per-peer probe statistics of the server.
+pub struct PeerTracker {
+    peers: HashMap<enr::NodeId, PeerRecord>,
+    /// The file the ranked report is written to as JSON.
+    report_path: Option<PathBuf>,
+}
+
+impl PeerTracker {
+    pub fn new(report_path: Option<PathBuf>) -> Self {
+        PeerTracker {
+            peers: HashMap::new(),
+            report_path,
+        }
+    }
+
+    /// Records the outcome of a probe of a peer.
+    pub fn record<T>(
+        &mut self,
+        node_id: enr::NodeId,
+        rtt: Duration,
+        result: &Result<T, RequestError>,
+    ) {
+        let record = self.peers.entry(node_id).or_insert_with(PeerRecord::new);
+        match result {
+            Ok(_) => record.rtts.push(rtt),
+            Err(RequestError::Timeout) => record.timeouts += 1,
+            Err(RequestError::InvalidRemotePacket) | Err(RequestError::InvalidRemoteEnr) => {
+                record.malformed_responses += 1
+            }
+            Err(_) => record.other_failures += 1,
+        }
+    }
+
+    /// The peers ranked by success ratio, then by median latency.
+    fn ranked(&self) -> Vec<(&enr::NodeId, &PeerRecord)> {
+        let mut ranked: Vec<_> = self.peers.iter().collect();
+        ranked.sort_by(|(_, a), (_, b)| {
+            b.success_ratio()
+                .partial_cmp(&a.success_ratio())
+                .unwrap_or(std::cmp::Ordering::Equal)
+                .then_with(|| a.median_rtt().cmp(&b.median_rtt()))
+        });
+        ranked
+    }
+
+    /// Displays the ranked peer report.
+    pub fn print_report(&self) {
+        info!("Peer report. Peers: {}", self.peers.len());
+        for (rank, (node_id, record)) in self.ranked().into_iter().enumerate() {
+            info!(
+                "{}. Node: {}, median latency: {}, success: {}/{} ({:.0}%), timeouts: {}, malformed responses: {}, other failures: {}, uptime: {}s",
+                rank + 1,
+                node_id,
+                record
+                    .median_rtt()
+                    .map_or_else(|| "-".to_string(), |rtt| format!("{}ms", rtt.as_millis())),
+                record.rtts.len(),
+                record.requests(),
+                record.success_ratio() * 100.0,
+                record.timeouts,
+                record.malformed_responses,
+                record.other_failures,
+                record.first_seen.elapsed().as_secs()
+            );
+        }
+    }
+
+    /// The rows of the ranked peer report.
+    fn report(&self) -> Vec<serde_json::Value> {
+        self.ranked()
+            .into_iter()
+            .map(|(node_id, record)| {
+                serde_json::json!({
+                    "node_id": hex::encode(node_id.raw()),
+                    "median_latency_ms": record.median_rtt().map(|rtt| rtt.as_millis() as u64),
+                    "requests": record.requests(),
+                    "successes": record.rtts.len(),
+                    "success_ratio": record.success_ratio(),
+                    "timeouts": record.timeouts,
+                    "malformed_responses": record.malformed_responses,
+                    "other_failures": record.other_failures,
+                    "uptime_secs": record.first_seen.elapsed().as_secs(),
+                })
+            })
+            .collect()
+    }
+
+    /// Writes the ranked peer report as JSON, if a report file is set.
+    pub fn write_report(&self) {
+        let path = match &self.report_path {
+            Some(path) => path,
+            None => return,
+        };
+        match serde_json::to_string_pretty(&self.report())
+            .map_err(|e| e.to_string())
+            .and_then(|json| std::fs::write(path, json).map_err(|e| e.to_string()))
+        {
+            Ok(()) => info!("Peer report written to {}", path.display()),
+            Err(e) => error!("Failed to write the peer report. Error: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id(byte: u8) -> enr::NodeId {
+        enr::NodeId::new(&[byte; 32])
+    }
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    /// Records successful probes with the given round-trip times and then the failures.
+    fn probe(tracker: &mut PeerTracker, byte: u8, rtts: &[u64], failures: Vec<RequestError>) {
+        for rtt in rtts {
+            tracker.record(node_id(byte), ms(*rtt), &Ok::<(), _>(()));
+        }
+        for failure in failures {
+            tracker.record(node_id(byte), ms(0), &Err::<(), _>(failure));
+        }
+    }
+
+    #[test]
+    fn classifies_outcomes() {
+        let mut tracker = PeerTracker::new(None);
+        probe(
+            &mut tracker,
+            1,
+            &[30, 10, 20],
+            vec![
+                RequestError::Timeout,
+                RequestError::InvalidRemotePacket,
+                RequestError::InvalidRemoteEnr,
+                RequestError::ServiceNotStarted,
+            ],
+        );
+        let record = &tracker.peers[&node_id(1)];
+        assert_eq!(record.requests(), 7);
+        assert_eq!(record.timeouts, 1);
+        assert_eq!(record.malformed_responses, 2);
+        assert_eq!(record.other_failures, 1);
+        assert!((record.success_ratio() - 3.0 / 7.0).abs() < f64::EPSILON);
+        assert_eq!(record.median_rtt(), Some(ms(20)));
+    }
+
+    #[test]
+    fn takes_the_upper_median_of_an_even_count() {
+        let mut tracker = PeerTracker::new(None);
+        probe(&mut tracker, 1, &[40, 10, 30, 20], vec![]);
+        assert_eq!(tracker.peers[&node_id(1)].median_rtt(), Some(ms(30)));
+
+        probe(&mut tracker, 2, &[], vec![RequestError::Timeout]);
+        let record = &tracker.peers[&node_id(2)];
+        assert_eq!(record.median_rtt(), None);
+        assert_eq!(record.success_ratio(), 0.0);
+    }
+
+    #[test]
+    fn ranks_by_success_then_latency() {
+        let mut tracker = PeerTracker::new(None);
+        probe(&mut tracker, 1, &[50], vec![RequestError::Timeout]);
+        probe(&mut tracker, 2, &[80, 80], vec![]);
+        probe(&mut tracker, 3, &[20, 20], vec![]);
+        probe(&mut tracker, 4, &[], vec![RequestError::Timeout]);
+
+        let report = tracker.report();
+        let order: Vec<_> = report.iter().map(|row| row["node_id"].clone()).collect();
+        let expected: Vec<_> = [3u8, 2, 1, 4]
+            .iter()
+            .map(|byte| serde_json::json!(hex::encode([*byte; 32])))
+            .collect();
+        assert_eq!(order, expected);
+
+        assert_eq!(report[2]["median_latency_ms"], 50);
+        assert_eq!(report[2]["requests"], 2);
+        assert_eq!(report[2]["successes"], 1);
+        assert_eq!(report[2]["success_ratio"], 0.5);
+        assert_eq!(report[2]["timeouts"], 1);
+        assert!(report[3]["median_latency_ms"].is_null());
+    }
+}
@@ -1,75 +1,208 @@
-use discv5::{enr, ConnectionDirection, ConnectionState, Discv5};
-use log::info;
-use std::collections::HashMap;
+use super::table_monitor::TableMonitor;
+use crate::lookup::client::LookupClient;
+use discv5::{
+    enr, enr::CombinedKey, kbucket::MAX_NODES_PER_BUCKET, ConnectionDirection, ConnectionState,
+    Discv5,
+};
+use log::{info, warn};
+use std::collections::BTreeMap;
 use std::time::Duration;
 
+/// The largest log2-distance between two node ids, i.e. the number of buckets.
+const MAX_LOG2_DISTANCE: u64 = 256;
+
 /// Starts a simple discv5 server which regularly queries for new peers and displays the results.
-pub async fn run_query_server(mut discv5: Discv5, break_time: Duration, stats: bool) {
+///
+/// With a `lookup_client` the queries are run by the client instead of discv5, which records which
+/// peer returned which node. The nodes found are added to the routing table.
+pub(crate) async fn run_query_server(
+    mut discv5: Discv5,
+    mut monitor: TableMonitor,
+    lookup_client: Option<LookupClient>,
+    break_time: Duration,
+    stats: bool,
+) {
+    let mut queries_run = 0;
     loop {
         info!("Searching for peers...");
         // pick a random node target
         let target_random_node_id = enr::NodeId::random();
-        match discv5.find_node(target_random_node_id).await {
-            Err(e) => println!("Find Node result failed: {:?}", e),
-            Ok(found_enrs) => {
-                info!("Query Completed. Nodes found: {}", found_enrs.len());
-                for enr in found_enrs {
-                    info!("Node: {}", enr.node_id());
+        if let Some(client) = &lookup_client {
+            let lookup = client.lookup(target_random_node_id, discv5.table_entries_enr());
+            let found = match monitor.run_until(&mut discv5, lookup).await {
+                Some(found) => found,
+                None => break,
+            };
+            monitor.observe_lookup(&found);
+            let found_enrs = monitor.filter_found(found.enrs);
+            for enr in &found_enrs {
+                // full buckets reject nodes, which is expected
+                let _ = discv5.add_enr(enr.clone());
+            }
+            monitor.observe_found(&found_enrs);
+            report_nodes_found(&found_enrs);
+        } else {
+            let query = discv5.find_node(target_random_node_id);
+            match monitor.run_until(&mut discv5, query).await {
+                None => break,
+                Some(Err(e)) => warn!("Find Node result failed: {:?}", e),
+                Some(Ok(found_enrs)) => {
+                    let found_enrs = monitor.filter_found(found_enrs);
+                    monitor.observe_found(&found_enrs);
+                    report_nodes_found(&found_enrs);
                 }
             }
         }
+        queries_run += 1;
+
+        // measure the latency and reliability of table peers, if requested
+        if !monitor.probe_peers(&mut discv5).await {
+            break;
+        }
 
         // If stats are requested, print some table stats.
         if stats {
             print_stats(&mut discv5);
+            monitor.print_peer_report();
         }
 
-        tokio::time::sleep(break_time).await;
+        if monitor
+            .run_until(&mut discv5, tokio::time::sleep(break_time))
+            .await
+            .is_none()
+        {
+            break;
+        }
         info!("Connected Peers: {}", discv5.connected_peers());
     }
-}
 
-fn print_stats(discv5: &mut Discv5) {
-    let table_entries = discv5.table_entries();
-    let self_id: discv5::Key<_> = discv5.local_enr().node_id().into();
-
-    let mut bucket_values = HashMap::new();
+    monitor.shutdown(discv5, queries_run);
+}
 
-    // Reconstruct the buckets
-    for (node_id, enr, status) in table_entries {
-        let key: discv5::Key<_> = node_id.into();
-        let bucket_no = key.log2_distance(&self_id);
-        if let Some(bucket_no) = bucket_no {
-            bucket_values
-                .entry(bucket_no)
-                .or_insert_with(|| Vec::new())
-                .push((enr, status));
-        }
+/// Displays the result of a completed `FIND_NODE` query.
+pub fn report_nodes_found(found_enrs: &[enr::Enr<CombinedKey>]) {
+    info!("Query Completed. Nodes found: {}", found_enrs.len());
+    for enr in found_enrs {
+        info!("Node: {}", enr.node_id());
     }
+}
 
-    // Build some stats
-    for (bucket, entries) in bucket_values {
-        let mut connected_peers = 0;
-        let mut connected_incoming_peers = 0;
-        let mut connected_outgoing_peers = 0;
-        let mut disconnected_peers = 0;
-
-        for (_enr, status) in entries {
-            match (status.state, status.direction) {
+/// Displays per-bucket statistics of the local routing table, a histogram of the bucket fill
+/// levels over all log2-distances and table-wide totals.
+pub fn print_stats(discv5: &mut Discv5) {
+    // Collect the non-empty buckets, ordered by log2-distance. Bucket `i` holds the nodes at
+    // log2-distance `i + 1`.
+    let mut buckets: BTreeMap<u64, BucketStats> = BTreeMap::new();
+    for (index, bucket) in discv5.kbuckets().buckets_iter().enumerate() {
+        let mut stats = BucketStats::default();
+        for node in bucket.iter() {
+            match (node.status.state, node.status.direction) {
                 (ConnectionState::Connected, ConnectionDirection::Incoming) => {
-                    connected_peers += 1;
-                    connected_incoming_peers += 1;
+                    stats.connected_incoming += 1
                 }
                 (ConnectionState::Connected, ConnectionDirection::Outgoing) => {
-                    connected_peers += 1;
-                    connected_outgoing_peers += 1;
+                    stats.connected_outgoing += 1
                 }
-                (ConnectionState::Disconnected, _) => {
-                    disconnected_peers += 1;
+                (ConnectionState::Disconnected, _) => stats.disconnected += 1,
+            }
+        }
+        if bucket.pending().is_some() {
+            stats.pending += 1;
+        }
+        if stats.entries() + stats.pending > 0 {
+            buckets.insert(index as u64 + 1, stats);
+        }
+    }
+
+    let mut totals = BucketStats::default();
+    for (bucket, stats) in &buckets {
+        info!(
+            "Bucket {} statistics: Fill: {}/{}, Connected peers: {} (Incoming: {}, Outgoing: {}), Disconnected Peers: {}, Pending: {}",
+            bucket,
+            stats.entries(),
+            MAX_NODES_PER_BUCKET,
+            stats.connected(),
+            stats.connected_incoming,
+            stats.connected_outgoing,
+            stats.disconnected,
+            stats.pending
+        );
+        totals.connected_incoming += stats.connected_incoming;
+        totals.connected_outgoing += stats.connected_outgoing;
+        totals.disconnected += stats.disconnected;
+        totals.pending += stats.pending;
+    }
+
+    // One row per log2-distance: '#' connected, '+' disconnected, '.' free and a trailing '?' for
+    // a node pending insertion. Runs of empty buckets are collapsed into a single row.
+    info!("Bucket fill histogram ('#' connected, '+' disconnected, '.' free, '?' pending):");
+    let mut empty_since = None;
+    for distance in 1..=MAX_LOG2_DISTANCE {
+        match buckets.get(&distance) {
+            None => {
+                empty_since.get_or_insert(distance);
+            }
+            Some(stats) => {
+                if let Some(first_empty) = empty_since.take() {
+                    print_empty_range(first_empty, distance - 1);
                 }
+                info!(
+                    "{:>3} |{}{}{}{}",
+                    distance,
+                    "#".repeat(stats.connected()),
+                    "+".repeat(stats.disconnected),
+                    ".".repeat(MAX_NODES_PER_BUCKET.saturating_sub(stats.entries())),
+                    "?".repeat(stats.pending)
+                );
             }
         }
+    }
+    if let Some(first_empty) = empty_since {
+        print_empty_range(first_empty, MAX_LOG2_DISTANCE);
+    }
+
+    info!(
+        "Table totals: Entries: {}/{} in {} buckets, Connected peers: {} (Incoming: {}, Outgoing: {}), Disconnected Peers: {}, Pending: {}",
+        totals.entries(),
+        buckets.len() * MAX_NODES_PER_BUCKET,
+        buckets.len(),
+        totals.connected(),
+        totals.connected_incoming,
+        totals.connected_outgoing,
+        totals.disconnected,
+        totals.pending
+    );
+}
+
+fn print_empty_range(first: u64, last: u64) {
+    if first == last {
+        info!("{:>3} |", first);
+    } else {
+        info!(
+            "{:>3} | ... {} empty buckets up to {}",
+            first,
+            last - first + 1,
+            last
+        );
+    }
+}
+
+/// The entry counts of a single bucket.
+#[derive(Default)]
+struct BucketStats {
+    connected_incoming: usize,
+    connected_outgoing: usize,
+    disconnected: usize,
+    /// Nodes waiting to replace a disconnected entry of a full bucket.
+    pending: usize,
+}
+
+impl BucketStats {
+    fn connected(&self) -> usize {
+        self.connected_incoming + self.connected_outgoing
+    }
 
-        info!("Bucket {} statistics: Connected peers: {} (Incoming: {}, Outgoing: {}), Disconnected Peers: {}", bucket, connected_peers, connected_incoming_peers, connected_outgoing_peers, disconnected_peers);
+    fn entries(&self) -> usize {
+        self.connected() + self.disconnected
     }
 }
@@ -0,0 +1,95 @@
+//! Reports the external socket updates of the local ENR.
+//!
+//! discv5 counts the address each peer observes in its PONG and updates the local ENR once
+//! `--update-nodes` peers agree. discv5 handles PONGs internally and does not expose which peer
+//! observed which address, so the individual votes cannot be reported. Only the outcome of the
+//! votes is: every `SocketUpdated` event, each of which means that enough peers agreed, and every
+//! change of the local ENR.
+
+use discv5::{enr, enr::CombinedKey, Discv5};
+use log::info;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+/// An external socket update reported by discv5.
+struct SocketUpdate {
+    time: SystemTime,
+    socket: SocketAddr,
+}
+
+/// Tracks socket updates and the resulting local ENRs.
+pub struct SocketUpdates {
+    /// The number of agreeing peers required to update the ENR.
+    peer_update_min: usize,
+    /// Every socket update, in order.
+    updates: Vec<SocketUpdate>,
+    /// The number of times discv5 switched to each socket.
+    updates_per_socket: BTreeMap<SocketAddr, usize>,
+    /// The last known local ENR.
+    local_enr: enr::Enr<CombinedKey>,
+}
+
+impl SocketUpdates {
+    pub fn new(peer_update_min: usize, local_enr: enr::Enr<CombinedKey>) -> Self {
+        SocketUpdates {
+            peer_update_min,
+            updates: Vec::new(),
+            updates_per_socket: BTreeMap::new(),
+            local_enr,
+        }
+    }
+
+    /// Records a `SocketUpdated` event and prints the updates so far.
+    pub fn socket_updated(&mut self, socket: SocketAddr) {
+        self.updates.push(SocketUpdate {
+            time: SystemTime::now(),
+            socket,
+        });
+        *self.updates_per_socket.entry(socket).or_insert(0) += 1;
+
+        info!(
+            "External socket updated to {}, as observed by at least {} peers. The local ENR will be updated",
+            socket, self.peer_update_min
+        );
+        self.print_updates();
+    }
+
+    /// Reports the local ENR if it changed since the last check.
+    pub fn check_local_enr(&mut self, discv5: &Discv5) {
+        let local_enr = discv5.local_enr();
+        if local_enr.seq() == self.local_enr.seq() {
+            return;
+        }
+        info!(
+            "Local ENR updated. Sequence number: {} -> {}, UDP socket: {:?} -> {:?}",
+            self.local_enr.seq(),
+            local_enr.seq(),
+            self.local_enr.udp4_socket(),
+            local_enr.udp4_socket()
+        );
+        info!("Base64 ENR: {}", local_enr.to_base64());
+        self.local_enr = local_enr;
+    }
+
+    /// Prints every socket update and the number of updates to each socket.
+    pub fn print_updates(&self) {
+        if self.updates.is_empty() {
+            info!("No external socket updates");
+            return;
+        }
+        info!("External socket updates:");
+        for update in &self.updates {
+            let seconds_ago = update.time.elapsed().unwrap_or_default().as_secs();
+            info!("{}s ago: {}", seconds_ago, update.socket);
+        }
+        for (socket, updates) in &self.updates_per_socket {
+            info!("Socket: {}, updates: {}", socket, updates);
+        }
+        info!(
+            "Current ENR sequence number: {}, UDP socket: {:?}",
+            self.local_enr.seq(),
+            self.local_enr.udp4_socket()
+        );
+    }
+}
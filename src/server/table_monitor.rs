@@ -0,0 +1,509 @@
+//! Observes the discv5 event stream, dumps the routing table on request and coordinates shutdown.
+//!
+//! Each time the dump trigger yields, every routing table entry is written to the log, or as JSON
+//! to the file given by `--dump-table`, without interrupting the server. The CLI triggers dumps on
+//! `SIGUSR1`. The shutdown future, by default `SIGINT` or `SIGTERM`, stops the server, prints a
+//! final report and writes a last table dump.
+
+use super::fork_digest;
+use super::peer_tracker::PeerTracker;
+use super::query_server;
+use super::socket_updates::SocketUpdates;
+use crate::error::Error;
+use crate::lookup::client::Found;
+use crate::request_enr::enr_ext::EnrExt;
+use crate::topology::Topology;
+use discv5::kbucket::NodeStatus;
+use discv5::{enr, enr::CombinedKey, ConnectionState, Discv5, Discv5Event};
+use futures::future::{BoxFuture, Fuse, FutureExt};
+use futures::stream::{BoxStream, StreamExt};
+use log::{debug, error, info, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tokio::sync::mpsc;
+
+/// The maximum number of peer probes in flight.
+const PROBE_CONCURRENCY: usize = 8;
+
+/// A routing table entry as returned by `Discv5::table_entries`.
+type TableEntry = (enr::NodeId, enr::Enr<CombinedKey>, NodeStatus);
+
+/// Tracks when table entries were last seen and services table dump and shutdown requests.
+pub struct TableMonitor {
+    /// When the server was started.
+    start_time: Instant,
+    /// The last time each node was discovered, inserted or returned by a query.
+    last_seen: HashMap<enr::NodeId, SystemTime>,
+    /// The discv5 event stream.
+    event_stream: mpsc::Receiver<Discv5Event>,
+    /// Triggers a table dump each time it yields.
+    dump_trigger: BoxStream<'static, ()>,
+    /// Triggers a shutdown once it completes.
+    shutdown: Fuse<BoxFuture<'static, ()>>,
+    /// The file to write table dumps to. Dumps are logged if this is not set.
+    dump_path: Option<PathBuf>,
+    /// The external socket updates of the local ENR.
+    socket_updates: SocketUpdates,
+    /// The graph of discovered nodes, written on table dumps if a file is set.
+    topology: Topology,
+    /// The file to write the topology to.
+    topology_path: Option<PathBuf>,
+    /// The latency and reliability of table peers, if tracked.
+    peer_tracker: Option<PeerTracker>,
+    /// The fork digest table entries and query results must advertise, if filtering.
+    fork_digest: Option<[u8; 4]>,
+}
+
+impl TableMonitor {
+    /// Subscribes to the events of a started discv5 server. The table is dumped each time
+    /// `dump_trigger` yields and the server is shut down once `shutdown` completes.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn new(
+        discv5: &mut Discv5,
+        dump_path: Option<PathBuf>,
+        topology_path: Option<PathBuf>,
+        peer_tracker: Option<PeerTracker>,
+        peer_update_min: usize,
+        fork_digest: Option<[u8; 4]>,
+        dump_trigger: BoxStream<'static, ()>,
+        shutdown: BoxFuture<'static, ()>,
+    ) -> Result<Self, Error> {
+        let event_stream = discv5
+            .event_stream()
+            .await
+            .map_err(|e| Error::Protocol(format!("Could not subscribe to server events: {}", e)))?;
+
+        Ok(TableMonitor {
+            start_time: Instant::now(),
+            last_seen: HashMap::new(),
+            event_stream,
+            dump_trigger,
+            shutdown: shutdown.fuse(),
+            dump_path,
+            socket_updates: SocketUpdates::new(peer_update_min, discv5.local_enr()),
+            topology: Topology::new(),
+            topology_path,
+            peer_tracker,
+            fork_digest,
+        })
+    }
+
+    /// Drives `future` to completion while processing discv5 events and table dump requests.
+    ///
+    /// Returns `None` if a shutdown was requested before the future completed.
+    pub async fn run_until<F: Future>(
+        &mut self,
+        discv5: &mut Discv5,
+        future: F,
+    ) -> Option<F::Output> {
+        tokio::pin!(future);
+        loop {
+            tokio::select! {
+                output = &mut future => {
+                    self.socket_updates.check_local_enr(discv5);
+                    return Some(output);
+                }
+                Some(event) = self.event_stream.recv() => {
+                    self.on_event(discv5, event);
+                    self.socket_updates.check_local_enr(discv5);
+                }
+                Some(()) = self.dump_trigger.next() => self.dump_table(discv5),
+                _ = &mut self.shutdown => return None,
+            }
+        }
+    }
+
+    /// Stops the server, prints a final report and writes a last table dump if a dump file is
+    /// set.
+    pub fn shutdown(mut self, mut discv5: Discv5, queries_run: usize) {
+        info!("Final report:");
+        info!(
+            "Uptime: {}s, queries run: {}, unique nodes seen: {}",
+            self.start_time.elapsed().as_secs(),
+            queries_run,
+            self.last_seen.len()
+        );
+        query_server::print_stats(&mut discv5);
+        self.socket_updates.print_updates();
+        if let Some(peer_tracker) = &self.peer_tracker {
+            peer_tracker.print_report();
+            peer_tracker.write_report();
+        }
+        if self.dump_path.is_some() || self.topology_path.is_some() {
+            self.dump_table(&mut discv5);
+        }
+
+        discv5.shutdown();
+    }
+
+    /// Sends an ENR request to every table peer, at most `PROBE_CONCURRENCY` at a time, and
+    /// records the round-trip time or failure, if peers are tracked.
+    ///
+    /// Returns `false` if a shutdown was requested before all requests completed.
+    pub async fn probe_peers(&mut self, discv5: &mut Discv5) -> bool {
+        if self.peer_tracker.is_none() {
+            return true;
+        }
+
+        let probes: Vec<_> = discv5
+            .table_entries_enr()
+            .into_iter()
+            .filter_map(|enr| {
+                let multiaddr = enr.discovery_multiaddrs().ok()?.into_iter().next()?;
+                let request = discv5.request_enr(multiaddr.to_string());
+                Some(async move {
+                    let start = Instant::now();
+                    let result = request.await;
+                    (enr.node_id(), start.elapsed(), result)
+                })
+            })
+            .collect();
+        let probes = futures::stream::iter(probes)
+            .buffer_unordered(PROBE_CONCURRENCY)
+            .collect::<Vec<_>>();
+
+        let results = match self.run_until(discv5, probes).await {
+            Some(results) => results,
+            None => return false,
+        };
+
+        if let Some(peer_tracker) = &mut self.peer_tracker {
+            for (node_id, rtt, result) in results {
+                peer_tracker.record(node_id, rtt, &result);
+            }
+            peer_tracker.write_report();
+        }
+        true
+    }
+
+    /// Displays the ranked peer report, if peers are tracked.
+    pub fn print_peer_report(&self) {
+        if let Some(peer_tracker) = &self.peer_tracker {
+            peer_tracker.print_report();
+        }
+    }
+
+    /// Removes the nodes of other forks from query results, if filtering by fork digest.
+    pub fn filter_found(
+        &self,
+        found_enrs: Vec<enr::Enr<CombinedKey>>,
+    ) -> Vec<enr::Enr<CombinedKey>> {
+        match &self.fork_digest {
+            Some(fork_digest) => fork_digest::filter_found(found_enrs, fork_digest),
+            None => found_enrs,
+        }
+    }
+
+    /// Records the nodes returned by a query as seen.
+    pub fn observe_found(&mut self, found_enrs: &[enr::Enr<CombinedKey>]) {
+        let now = SystemTime::now();
+        for enr in found_enrs {
+            self.last_seen.insert(enr.node_id(), now);
+        }
+    }
+
+    /// Records which peer returned which node during a lookup. The peers that responded are alive.
+    pub fn observe_lookup(&mut self, found: &Found) {
+        for node_id in &found.responded {
+            self.topology.mark_live(node_id);
+        }
+        for (from, to) in &found.edges {
+            self.topology.add_edge(from, to);
+        }
+    }
+
+    fn on_event(&mut self, discv5: &mut Discv5, event: Discv5Event) {
+        let node_id = match event {
+            Discv5Event::Discovered(enr) => enr.node_id(),
+            Discv5Event::EnrAdded { enr, .. } => {
+                self.enforce_fork_digest(discv5, &enr.node_id());
+                enr.node_id()
+            }
+            Discv5Event::NodeInserted { node_id, .. } => {
+                self.enforce_fork_digest(discv5, &node_id);
+                node_id
+            }
+            Discv5Event::SocketUpdated(socket) => {
+                self.socket_updates.socket_updated(socket);
+                return;
+            }
+            _ => return,
+        };
+        self.last_seen.insert(node_id, SystemTime::now());
+    }
+
+    /// Removes a node that was added to the routing table if it is not on the filtered fork. discv5
+    /// inserts nodes that establish a session with the server without applying the table filter.
+    fn enforce_fork_digest(&self, discv5: &mut Discv5, node_id: &enr::NodeId) {
+        let fork_digest = match &self.fork_digest {
+            Some(fork_digest) => fork_digest,
+            None => return,
+        };
+        if let Some(enr) = discv5.find_enr(node_id) {
+            if let Err(rejection) = fork_digest::check(&enr, fork_digest) {
+                debug!("Removing node {} from the table: {}", node_id, rejection);
+                discv5.remove_node(node_id);
+            }
+        }
+    }
+
+    /// Writes every routing table entry to the log or the dump file, and the topology to its file.
+    pub fn dump_table(&mut self, discv5: &mut Discv5) {
+        if let Some(path) = &self.topology_path {
+            // connected table entries are alive
+            for (node_id, _, status) in discv5.table_entries() {
+                if let ConnectionState::Connected = status.state {
+                    self.topology.mark_live(&node_id);
+                }
+            }
+            self.topology.mark_live(&discv5.local_enr().node_id());
+            let (nodes, edges) = self.topology.counts();
+            match self.topology.write(path) {
+                Ok(()) => info!(
+                    "Topology written to {}. Nodes: {}, edges: {}",
+                    path.display(),
+                    nodes,
+                    edges
+                ),
+                Err(e) => error!("Failed to write the topology. Error: {}", e),
+            }
+        }
+
+        let local_id = discv5.local_enr().node_id();
+        let table_entries = sorted_by_bucket(&local_id, discv5.table_entries());
+        let now = SystemTime::now();
+
+        match &self.dump_path {
+            None => {
+                info!("Routing table dump. Entries: {}", table_entries.len());
+                for line in dump_lines(&local_id, &table_entries, &self.last_seen, now) {
+                    info!("{}", line);
+                }
+            }
+            Some(path) => {
+                let dump = dump_json(&local_id, &table_entries, &self.last_seen, now);
+                match serde_json::to_string_pretty(&dump)
+                    .map_err(|e| e.to_string())
+                    .and_then(|json| std::fs::write(path, json).map_err(|e| e.to_string()))
+                {
+                    Ok(()) => info!(
+                        "Routing table dumped to {}. Entries: {}",
+                        path.display(),
+                        table_entries.len()
+                    ),
+                    Err(e) => error!("Failed to dump the routing table. Error: {}", e),
+                }
+            }
+        }
+        if table_entries.is_empty() {
+            warn!("The routing table is empty");
+        }
+    }
+}
+
+/// Sorts table entries by their bucket, i.e. their log2-distance to the local node.
+fn sorted_by_bucket(local_id: &enr::NodeId, mut table_entries: Vec<TableEntry>) -> Vec<TableEntry> {
+    let local_key: discv5::Key<_> = (*local_id).into();
+    table_entries.sort_by_key(|(node_id, _, _)| {
+        let key: discv5::Key<_> = (*node_id).into();
+        key.log2_distance(&local_key)
+    });
+    table_entries
+}
+
+/// The log lines of a table dump, one per entry.
+fn dump_lines(
+    local_id: &enr::NodeId,
+    table_entries: &[TableEntry],
+    last_seen: &HashMap<enr::NodeId, SystemTime>,
+    now: SystemTime,
+) -> Vec<String> {
+    let local_key: discv5::Key<_> = (*local_id).into();
+    table_entries
+        .iter()
+        .map(|(node_id, enr, status)| {
+            let key: discv5::Key<_> = (*node_id).into();
+            let last_seen = match last_seen.get(node_id) {
+                Some(time) => format!(
+                    "{}s ago",
+                    now.duration_since(*time).unwrap_or_default().as_secs()
+                ),
+                None => "never".into(),
+            };
+            format!(
+                "Node: {}, bucket: {:?}, state: {:?}, direction: {:?}, last seen: {}, ENR: {}",
+                node_id,
+                key.log2_distance(&local_key),
+                status.state,
+                status.direction,
+                last_seen,
+                enr.to_base64()
+            )
+        })
+        .collect()
+}
+
+/// The JSON document of a table dump.
+fn dump_json(
+    local_id: &enr::NodeId,
+    table_entries: &[TableEntry],
+    last_seen: &HashMap<enr::NodeId, SystemTime>,
+    now: SystemTime,
+) -> serde_json::Value {
+    let local_key: discv5::Key<_> = (*local_id).into();
+    let entries: Vec<_> = table_entries
+        .iter()
+        .map(|(node_id, enr, status)| {
+            let key: discv5::Key<_> = (*node_id).into();
+            serde_json::json!({
+                "node_id": hex::encode(node_id.raw()),
+                "bucket": key.log2_distance(&local_key),
+                "enr": enr.to_base64(),
+                "state": format!("{:?}", status.state),
+                "direction": format!("{:?}", status.direction),
+                "last_seen": last_seen.get(node_id).map(unix_seconds),
+            })
+        })
+        .collect();
+    serde_json::json!({
+        "local_node_id": hex::encode(local_id.raw()),
+        "time": unix_seconds(&now),
+        "entries": entries,
+    })
+}
+
+/// Yields each time the process receives SIGUSR1.
+pub fn dump_signal() -> Result<BoxStream<'static, ()>, Error> {
+    let dump_signal = listen(SignalKind::user_defined1(), "SIGUSR1")?;
+    Ok(
+        futures::stream::unfold(dump_signal, |mut dump_signal| async move {
+            dump_signal.recv().await.map(|()| ((), dump_signal))
+        })
+        .boxed(),
+    )
+}
+
+/// Completes when the process receives SIGINT or SIGTERM.
+pub fn shutdown_signal() -> Result<BoxFuture<'static, ()>, Error> {
+    let mut interrupt_signal = listen(SignalKind::interrupt(), "SIGINT")?;
+    let mut terminate_signal = listen(SignalKind::terminate(), "SIGTERM")?;
+    Ok(async move {
+        tokio::select! {
+            _ = interrupt_signal.recv() => info!("Received SIGINT, shutting down"),
+            _ = terminate_signal.recv() => info!("Received SIGTERM, shutting down"),
+        }
+    }
+    .boxed())
+}
+
+fn listen(kind: SignalKind, name: &str) -> Result<Signal, Error> {
+    signal(kind).map_err(|e| Error::Io(format!("Could not listen for {}: {}", name, e)))
+}
+
+/// Seconds since the unix epoch.
+fn unix_seconds(time: &SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_else(|_| Duration::from_secs(0))
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use discv5::ConnectionDirection;
+
+    /// An entry at distance 256 from the local node [0x00; 32] and one at distance 1.
+    fn table_entries() -> Vec<TableEntry> {
+        let key = CombinedKey::generate_secp256k1();
+        let enr = enr::EnrBuilder::new("v4").build(&key).unwrap();
+        let far = NodeStatus {
+            direction: ConnectionDirection::Outgoing,
+            state: ConnectionState::Connected,
+        };
+        let near = NodeStatus {
+            direction: ConnectionDirection::Incoming,
+            state: ConnectionState::Disconnected,
+        };
+        let mut near_id = [0; 32];
+        near_id[31] = 1;
+        vec![
+            (enr::NodeId::new(&[0xff; 32]), enr.clone(), far),
+            (enr::NodeId::new(&near_id), enr, near),
+        ]
+    }
+
+    #[test]
+    fn sorts_entries_by_bucket() {
+        let local_id = enr::NodeId::new(&[0; 32]);
+        let sorted = sorted_by_bucket(&local_id, table_entries());
+        let ids: Vec<_> = sorted
+            .iter()
+            .map(|(node_id, _, _)| node_id.raw()[0])
+            .collect();
+        assert_eq!(ids, vec![0, 0xff]);
+    }
+
+    #[test]
+    fn formats_log_lines() {
+        let local_id = enr::NodeId::new(&[0; 32]);
+        let entries = sorted_by_bucket(&local_id, table_entries());
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let last_seen = HashMap::from([(entries[1].0, now - Duration::from_secs(42))]);
+
+        let lines = dump_lines(&local_id, &entries, &last_seen, now);
+        assert_eq!(
+            lines[0],
+            format!(
+                "Node: {}, bucket: Some(1), state: Disconnected, direction: Incoming, last seen: never, ENR: {}",
+                entries[0].0,
+                entries[0].1.to_base64()
+            )
+        );
+        assert_eq!(
+            lines[1],
+            format!(
+                "Node: {}, bucket: Some(256), state: Connected, direction: Outgoing, last seen: 42s ago, ENR: {}",
+                entries[1].0,
+                entries[1].1.to_base64()
+            )
+        );
+    }
+
+    #[test]
+    fn formats_json_dumps() {
+        let local_id = enr::NodeId::new(&[0; 32]);
+        let entries = sorted_by_bucket(&local_id, table_entries());
+        let now = UNIX_EPOCH + Duration::from_secs(1000);
+        let last_seen = HashMap::from([(entries[1].0, now - Duration::from_secs(42))]);
+
+        let dump = dump_json(&local_id, &entries, &last_seen, now);
+        assert_eq!(
+            dump,
+            serde_json::json!({
+                "local_node_id": "00".repeat(32),
+                "time": 1000,
+                "entries": [
+                    {
+                        "node_id": format!("{}01", "00".repeat(31)),
+                        "bucket": 1,
+                        "enr": entries[0].1.to_base64(),
+                        "state": "Disconnected",
+                        "direction": "Incoming",
+                        "last_seen": null,
+                    },
+                    {
+                        "node_id": "ff".repeat(32),
+                        "bucket": 256,
+                        "enr": entries[1].1.to_base64(),
+                        "state": "Connected",
+                        "direction": "Outgoing",
+                        "last_seen": 958,
+                    },
+                ],
+            })
+        );
+    }
+}
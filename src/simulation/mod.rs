@@ -0,0 +1,123 @@
+//! Runs discovery lookups over a model of the routing tables of a discv5 network.
+//!
+//! This is a Kademlia model rather than a network of discv5 instances: nodes have the keys and
+//! ENRs of the `server` subcommand, but their routing tables are buckets of node indices and a
+//! `FINDNODE` is answered directly from them, without packets, sessions or handshakes. Messages
+//! are delayed and lost by a virtual clock and a seeded RNG, so a run is reproducible from its
+//! seed.
+
+use crate::cli;
+use crate::error::{Error, Result};
+use crate::server::query_server;
+use clap::ArgMatches;
+use log::info;
+use std::path::Path;
+
+mod network;
+pub use network::{LookupResult, SimConfig, SimNetwork};
+
+/// Runs a simulation based on the CLI options.
+pub fn run(matches: &ArgMatches) -> Result<()> {
+    let config = SimConfig {
+        nodes: cli::value(matches, "nodes")?,
+        seed: cli::value(matches, "seed")?,
+        latency_ms: cli::value(matches, "latency")?,
+        jitter_ms: cli::value(matches, "jitter")?,
+        loss: cli::value(matches, "loss")?,
+        parallelism: cli::value(matches, "parallelism")?,
+        request_timeout_ms: cli::value(matches, "request-timeout")?,
+    };
+    let queries: usize = cli::value(matches, "queries")?;
+
+    if config.nodes < 2 {
+        return Err(Error::Usage(
+            "A simulation requires at least 2 nodes".into(),
+        ));
+    }
+    if !(0.0..=1.0).contains(&config.loss) {
+        return Err(Error::Usage(
+            "The packet loss must be between 0 and 1".into(),
+        ));
+    }
+
+    info!(
+        "Simulating {} nodes. Seed: {}, latency: {}ms (+/- {}ms), loss: {}, parallelism: {}",
+        config.nodes,
+        config.seed,
+        config.latency_ms,
+        config.jitter_ms,
+        config.loss,
+        config.parallelism
+    );
+
+    let mut network = SimNetwork::new(config);
+    info!("Bootnode: {}", network.enr(0).to_base64());
+
+    // every node performs a lookup of its own id to populate the routing tables
+    network.bootstrap();
+    info!(
+        "Bootstrap complete at {}ms. Table entries: {}",
+        network.now(),
+        network.table_entries()
+    );
+
+    let mut results = Vec::with_capacity(queries);
+    for query in 0..queries {
+        info!("Searching for peers... (query {})", query);
+        let (origin, target) = network.random_lookup();
+        let result = network.find_node(origin, target);
+
+        query_server::report_nodes_found(&network.enrs(&result.found));
+        info!(
+            "Origin: {}, target: {}, closest node found: {}, hops: {}, messages: {}, timeouts: {}, duration: {}ms",
+            network.enr(origin).node_id(),
+            target,
+            result.found_closest,
+            result.hops,
+            result.messages,
+            result.timeouts,
+            result.duration_ms
+        );
+        results.push(result);
+    }
+
+    print_summary(&results);
+
+    if let Some(path) = matches.value_of("topology") {
+        let (nodes, edges) = network.topology().counts();
+        network
+            .topology()
+            .write(Path::new(path))
+            .map_err(|e| Error::Io(format!("Could not write the topology {}: {}", path, e)))?;
+        info!(
+            "Topology written to {}. Nodes: {}, edges: {}",
+            path, nodes, edges
+        );
+    }
+    Ok(())
+}
+
+/// Displays the aggregate results of all measured lookups.
+fn print_summary(results: &[LookupResult]) {
+    if results.is_empty() {
+        return;
+    }
+    let count = results.len() as f64;
+    let found_closest = results.iter().filter(|r| r.found_closest).count();
+    let mean = |f: fn(&LookupResult) -> u64| results.iter().map(f).sum::<u64>() as f64 / count;
+
+    info!("Simulation summary:");
+    info!(
+        "Lookups: {}, closest node found: {} ({:.1}%)",
+        results.len(),
+        found_closest,
+        found_closest as f64 * 100.0 / count
+    );
+    info!(
+        "Mean hops: {:.2}, mean messages: {:.2}, mean timeouts: {:.2}, mean duration: {:.1}ms",
+        mean(|r| r.hops),
+        mean(|r| r.messages),
+        mean(|r| r.timeouts),
+        mean(|r| r.duration_ms)
+    );
+}
@@ -0,0 +1,324 @@
+//! The virtual clock, message delays and modelled routing tables.
+
+use crate::lookup::{Lookup, MAX_NODES_RESPONSE};
+use crate::server;
+use crate::topology::Topology;
+use discv5::{enr, enr::CombinedKey, kbucket::MAX_NODES_PER_BUCKET, Key};
+use rand::{Rng, RngCore, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::net::{IpAddr, Ipv4Addr};
+
+type Enr = enr::Enr<CombinedKey>;
+
+/// The UDP port every simulated node advertises.
+const SIM_PORT: u16 = 9000;
+
+/// The parameters of a simulated network.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// The number of nodes in the network. Node 0 is the bootnode.
+    pub nodes: usize,
+    /// The seed of the RNG used for keys, lookup targets, latency and loss.
+    pub seed: u64,
+    /// The mean one-way latency of a message.
+    pub latency_ms: u64,
+    /// The maximum deviation from `latency_ms` of a message.
+    pub jitter_ms: u64,
+    /// The probability that any single message is lost.
+    pub loss: f64,
+    /// The number of concurrent requests of a lookup.
+    pub parallelism: usize,
+    /// The time after which an unanswered request is considered failed.
+    pub request_timeout_ms: u64,
+}
+
+/// The outcome of a single simulated lookup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LookupResult {
+    /// The indices of the nodes returned by the lookup, closest first.
+    pub found: Vec<usize>,
+    /// Whether the node closest to the target in the whole network was found.
+    pub found_closest: bool,
+    /// The longest chain of responses that led to a result.
+    pub hops: u64,
+    /// The number of `FINDNODE` requests sent.
+    pub messages: u64,
+    /// The number of requests that timed out.
+    pub timeouts: u64,
+    /// The virtual time the lookup took.
+    pub duration_ms: u64,
+}
+
+/// A modelled discv5 node.
+struct SimNode {
+    enr: Enr,
+    key: Key<enr::NodeId>,
+    /// The simulated routing table, indexed by log2-distance.
+    buckets: BTreeMap<u64, Vec<usize>>,
+}
+
+/// A request in flight, ordered by its virtual arrival time.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct InFlight {
+    arrival_ms: u64,
+    seq: u64,
+    peer: usize,
+    lost: bool,
+}
+
+/// A network of modelled nodes whose requests are answered from their routing tables.
+pub struct SimNetwork {
+    config: SimConfig,
+    rng: ChaCha8Rng,
+    /// The current virtual time.
+    now_ms: u64,
+    nodes: Vec<SimNode>,
+    /// Which node returned which other node in a `NODES` response.
+    topology: Topology,
+}
+
+impl SimNetwork {
+    /// Creates the nodes of the network. Every node except the bootnode knows the bootnode.
+    pub fn new(config: SimConfig) -> Self {
+        let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+
+        let mut nodes = Vec::with_capacity(config.nodes);
+        for index in 0..config.nodes {
+            let mut raw_key = [0u8; 32];
+            rng.fill_bytes(&mut raw_key);
+            // 32 random bytes are a valid key with overwhelming probability
+            let enr_key =
+                server::key_from_bytes(&raw_key).expect("Random bytes are a valid secp256k1 key");
+
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 0, (index >> 8) as u8, index as u8));
+            let enr = server::EnrConfig {
+                ip: Some(ip),
+                udp_port: Some(SIM_PORT),
+                ..Default::default()
+            }
+            .build(&enr_key)
+            .expect("An ENR with an IP and port fits the maximum size");
+            nodes.push(SimNode {
+                key: enr.node_id().into(),
+                enr,
+                buckets: BTreeMap::new(),
+            });
+        }
+
+        let mut network = SimNetwork {
+            config,
+            rng,
+            now_ms: 0,
+            nodes,
+            topology: Topology::new(),
+        };
+        for index in 1..network.nodes.len() {
+            network.insert(index, 0);
+        }
+        network
+    }
+
+    /// Every node performs a lookup of its own node id, in order.
+    pub fn bootstrap(&mut self) {
+        for index in 0..self.nodes.len() {
+            let target = self.nodes[index].enr.node_id();
+            self.find_node(index, target);
+        }
+    }
+
+    /// Picks a random origin node and lookup target.
+    pub fn random_lookup(&mut self) -> (usize, enr::NodeId) {
+        let origin = self.rng.gen_range(0..self.nodes.len());
+        let mut raw_target = [0u8; 32];
+        self.rng.fill_bytes(&mut raw_target);
+        (origin, enr::NodeId::new(&raw_target))
+    }
+
+    /// The current virtual time in milliseconds.
+    pub fn now(&self) -> u64 {
+        self.now_ms
+    }
+
+    /// The ENR of a node.
+    pub fn enr(&self, index: usize) -> &Enr {
+        &self.nodes[index].enr
+    }
+
+    /// The ENRs of a list of nodes.
+    pub fn enrs(&self, indices: &[usize]) -> Vec<Enr> {
+        indices.iter().map(|i| self.nodes[*i].enr.clone()).collect()
+    }
+
+    /// The graph of which node returned which other node in a `NODES` response.
+    pub fn topology(&self) -> &Topology {
+        &self.topology
+    }
+
+    /// The total number of entries over all routing tables.
+    pub fn table_entries(&self) -> usize {
+        self.nodes
+            .iter()
+            .map(|node| node.buckets.values().map(Vec::len).sum::<usize>())
+            .sum()
+    }
+
+    /// Runs an iterative `FINDNODE` lookup for `target` from the `origin` node, advancing the
+    /// virtual clock until the lookup completes.
+    pub fn find_node(&mut self, origin: usize, target: enr::NodeId) -> LookupResult {
+        let target_key: Key<enr::NodeId> = target.into();
+        let start_ms = self.now_ms;
+
+        let peers: Vec<_> = self
+            .closest(origin, &target_key)
+            .into_iter()
+            .map(|peer| (self.nodes[peer].enr.node_id(), peer))
+            .collect();
+        let mut lookup = Lookup::new(target, self.config.parallelism, peers);
+        let mut in_flight = BinaryHeap::new();
+        let mut seq = 0;
+
+        loop {
+            // keep `parallelism` requests in flight to the closest unqueried candidates
+            while let Some((_, peer)) = lookup.next_request() {
+                let lost = self.is_lost() || self.is_lost();
+                let arrival_ms = if lost {
+                    self.now_ms + self.config.request_timeout_ms
+                } else {
+                    self.now_ms + self.latency() + self.latency()
+                };
+                in_flight.push(Reverse(InFlight {
+                    arrival_ms,
+                    seq,
+                    peer,
+                    lost,
+                }));
+                seq += 1;
+            }
+
+            let Reverse(request) = match in_flight.pop() {
+                Some(request) => request,
+                None => break,
+            };
+            self.now_ms = request.arrival_ms;
+            let responder_id = self.nodes[request.peer].enr.node_id();
+
+            if request.lost {
+                lookup.on_failure(&responder_id);
+                continue;
+            }
+
+            // the peer learns about the requester and answers with its closest known nodes
+            self.insert(request.peer, origin);
+            self.insert(origin, request.peer);
+            self.topology.mark_live(&responder_id);
+
+            let mut nodes = Vec::new();
+            for peer in self.closest(request.peer, &target_key) {
+                let node_id = self.nodes[peer].enr.node_id();
+                self.topology.add_edge(&responder_id, &node_id);
+                if peer != origin {
+                    nodes.push((node_id, peer));
+                }
+            }
+            lookup.on_response(&responder_id, nodes);
+        }
+
+        let (found, stats) = lookup.finish();
+        let found_closest = self
+            .closest_in_network(origin, &target_key)
+            .is_some_and(|closest| found.contains(&closest));
+        LookupResult {
+            found,
+            found_closest,
+            hops: stats.hops,
+            messages: stats.messages,
+            timeouts: stats.timeouts,
+            duration_ms: self.now_ms - start_ms,
+        }
+    }
+
+    /// The nodes in the routing table of `node` closest to `target`.
+    fn closest(&self, node: usize, target: &Key<enr::NodeId>) -> Vec<usize> {
+        let mut entries: Vec<_> = self.nodes[node]
+            .buckets
+            .values()
+            .flatten()
+            .map(|peer| (self.nodes[*peer].key.distance(target), *peer))
+            .collect();
+        entries.sort();
+        entries
+            .into_iter()
+            .take(MAX_NODES_RESPONSE)
+            .map(|(_, peer)| peer)
+            .collect()
+    }
+
+    /// The node closest to `target` in the whole network, excluding `origin`.
+    fn closest_in_network(&self, origin: usize, target: &Key<enr::NodeId>) -> Option<usize> {
+        (0..self.nodes.len())
+            .filter(|index| *index != origin)
+            .min_by_key(|index| self.nodes[*index].key.distance(target))
+    }
+
+    /// Inserts `peer` into the routing table of `node` if its bucket has space.
+    fn insert(&mut self, node: usize, peer: usize) {
+        let bucket = match self.nodes[node].key.log2_distance(&self.nodes[peer].key) {
+            Some(bucket) => bucket,
+            None => return,
+        };
+        let entries = self.nodes[node].buckets.entry(bucket).or_default();
+        if entries.len() < MAX_NODES_PER_BUCKET && !entries.contains(&peer) {
+            entries.push(peer);
+        }
+    }
+
+    /// Draws the one-way latency of a message.
+    fn latency(&mut self) -> u64 {
+        let jitter = self.rng.gen_range(0..=self.config.jitter_ms * 2);
+        (self.config.latency_ms + jitter).saturating_sub(self.config.jitter_ms)
+    }
+
+    /// Draws whether a message is lost.
+    fn is_lost(&mut self) -> bool {
+        self.config.loss > 0.0 && self.rng.gen::<f64>() < self.config.loss
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(seed: u64) -> (Vec<enr::NodeId>, Vec<LookupResult>, u64) {
+        let mut network = SimNetwork::new(SimConfig {
+            nodes: 50,
+            seed,
+            latency_ms: 50,
+            jitter_ms: 20,
+            loss: 0.1,
+            parallelism: 3,
+            request_timeout_ms: 1000,
+        });
+        network.bootstrap();
+        let results = (0..5)
+            .map(|_| {
+                let (origin, target) = network.random_lookup();
+                network.find_node(origin, target)
+            })
+            .collect();
+        let node_ids = (0..50).map(|index| network.enr(index).node_id()).collect();
+        (node_ids, results, network.now())
+    }
+
+    #[test]
+    fn runs_are_reproducible_from_their_seed() {
+        let (node_ids, results, now) = run(7);
+        assert_eq!(run(7), (node_ids.clone(), results.clone(), now));
+        assert!(results.iter().all(|result| !result.found.is_empty()));
+
+        let (other_ids, other_results, _) = run(8);
+        assert_ne!(other_ids, node_ids);
+        assert_ne!(other_results, results);
+    }
+}
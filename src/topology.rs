@@ -0,0 +1,185 @@
+//! Records which node told us about which other node and exports the resulting graph.
+//!
+//! An edge `A -> B` means `A` returned `B` in a `NODES` response. Edges are annotated with the
+//! log2-distance between both nodes, i.e. the bucket `B` occupies in `A`'s routing table, and nodes
+//! with whether they were seen alive.
+
+use discv5::{enr::NodeId, Key};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::path::Path;
+
+/// A directed graph of discovered nodes.
+#[derive(Default)]
+pub struct Topology {
+    /// Every node in the graph and whether it is known to be alive.
+    nodes: BTreeMap<[u8; 32], bool>,
+    /// The "told us about" edges.
+    edges: BTreeSet<([u8; 32], [u8; 32])>,
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Topology::default()
+    }
+
+    /// Adds a node if it is not already part of the graph.
+    pub fn add_node(&mut self, node_id: &NodeId) {
+        self.nodes.entry(node_id.raw()).or_insert(false);
+    }
+
+    /// Marks a node as alive, e.g. after it responded to a request.
+    pub fn mark_live(&mut self, node_id: &NodeId) {
+        self.nodes.insert(node_id.raw(), true);
+    }
+
+    /// Records that `from` told us about `to`.
+    pub fn add_edge(&mut self, from: &NodeId, to: &NodeId) {
+        if from == to {
+            return;
+        }
+        self.add_node(from);
+        self.add_node(to);
+        self.edges.insert((from.raw(), to.raw()));
+    }
+
+    /// The number of nodes and edges in the graph.
+    pub fn counts(&self) -> (usize, usize) {
+        (self.nodes.len(), self.edges.len())
+    }
+
+    /// Encodes the graph in the GraphViz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph discv5 {\n");
+        for (node, live) in &self.nodes {
+            let _ = writeln!(
+                dot,
+                "  \"{}\" [label=\"{}\", live={}, color={}];",
+                hex::encode(node),
+                NodeId::new(node),
+                live,
+                if *live { "green" } else { "grey" }
+            );
+        }
+        for (from, to) in &self.edges {
+            let distance = log2_distance(from, to);
+            let _ = writeln!(
+                dot,
+                "  \"{}\" -> \"{}\" [label=\"{}\", distance={}];",
+                hex::encode(from),
+                hex::encode(to),
+                distance,
+                distance
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Encodes the graph in the GraphML format.
+    pub fn to_graphml(&self) -> String {
+        let mut graphml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n  \
+             <key id=\"live\" for=\"node\" attr.name=\"live\" attr.type=\"boolean\"/>\n  \
+             <key id=\"distance\" for=\"edge\" attr.name=\"distance\" attr.type=\"int\"/>\n  \
+             <graph id=\"discv5\" edgedefault=\"directed\">\n",
+        );
+        for (node, live) in &self.nodes {
+            let _ = writeln!(
+                graphml,
+                "    <node id=\"{}\"><data key=\"live\">{}</data></node>",
+                hex::encode(node),
+                live
+            );
+        }
+        for (from, to) in &self.edges {
+            let _ = writeln!(
+                graphml,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"distance\">{}</data></edge>",
+                hex::encode(from),
+                hex::encode(to),
+                log2_distance(from, to)
+            );
+        }
+        graphml.push_str("  </graph>\n</graphml>\n");
+        graphml
+    }
+
+    /// Writes the graph to `path`, as GraphML if the file ends in `.graphml` and as DOT otherwise.
+    pub fn write(&self, path: &Path) -> std::io::Result<()> {
+        let encoded = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("graphml") => self.to_graphml(),
+            _ => self.to_dot(),
+        };
+        std::fs::write(path, encoded)
+    }
+}
+
+/// The log2-distance between two raw node ids.
+fn log2_distance(from: &[u8; 32], to: &[u8; 32]) -> u64 {
+    let from: Key<NodeId> = NodeId::new(from).into();
+    let to: Key<NodeId> = NodeId::new(to).into();
+    from.log2_distance(&to).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node_id(byte: u8) -> NodeId {
+        NodeId::new(&[byte; 32])
+    }
+
+    /// `a -> b`, `a -> c` and `b -> c`, with `a -> b` reported twice and a self-edge ignored.
+    fn graph() -> Topology {
+        let mut topology = Topology::new();
+        topology.mark_live(&node_id(0xaa));
+        topology.add_edge(&node_id(0xaa), &node_id(0xbb));
+        topology.add_edge(&node_id(0xaa), &node_id(0xbb));
+        topology.add_edge(&node_id(0xaa), &node_id(0xcc));
+        topology.add_edge(&node_id(0xbb), &node_id(0xcc));
+        topology.add_edge(&node_id(0xcc), &node_id(0xcc));
+        topology
+    }
+
+    #[test]
+    fn exports_dot() {
+        let dot = graph().to_dot();
+        let (a, b, c) = ("aa".repeat(32), "bb".repeat(32), "cc".repeat(32));
+        assert!(dot.starts_with("digraph discv5 {\n"));
+        assert!(dot.contains(&format!(
+            "  \"{}\" [label=\"{}\", live=true",
+            a,
+            node_id(0xaa)
+        )));
+        assert!(dot.contains(&format!(
+            "  \"{}\" [label=\"{}\", live=false",
+            c,
+            node_id(0xcc)
+        )));
+        for (from, to) in [(&a, &b), (&a, &c), (&b, &c)] {
+            let edge = format!("  \"{}\" -> \"{}\" [label=\"", from, to);
+            assert_eq!(dot.matches(&edge).count(), 1, "{}", edge);
+        }
+        assert_eq!(dot.matches(" -> ").count(), 3);
+        assert!(dot.ends_with("}\n"));
+    }
+
+    #[test]
+    fn exports_graphml() {
+        let graphml = graph().to_graphml();
+        let (a, b, c) = ("aa".repeat(32), "bb".repeat(32), "cc".repeat(32));
+        assert!(graphml.contains(&format!(
+            "<node id=\"{}\"><data key=\"live\">true</data></node>",
+            a
+        )));
+        for (from, to) in [(&a, &b), (&a, &c), (&b, &c)] {
+            let edge = format!("<edge source=\"{}\" target=\"{}\">", from, to);
+            assert_eq!(graphml.matches(&edge).count(), 1, "{}", edge);
+        }
+        assert_eq!(graphml.matches("<edge ").count(), 3);
+        assert_eq!(graphml.matches("<node ").count(), 3);
+        assert_eq!(graph().counts(), (3, 3));
+    }
+}